@@ -1,33 +1,57 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::Write;
 
-use crate::assembler::{Assembler, IfBlock, TokenReader};
+use crate::assembler::{Assembled, Assembler, IfBlock, PassOptions, TokenReader};
+use crate::assembler::assembler_context::AssemblerContext;
 use crate::assembler::bank::Bank;
 use crate::assembler::constant::Constant;
 use crate::assembler::directive::directives::Directives;
 use crate::assembler::directive::enumerator::Enumerator;
-use crate::assembler::directive::macros::MacroHandler;
+use crate::assembler::directive::macros::{MAX_MACRO_DEPTH, MacroHandler, MacroSource};
+use crate::assembler::directive::savenex::SaveNex;
+use crate::assembler::directive::savesna::SaveSnapshot;
+use crate::assembler::directive::savetap::SaveTap;
+use crate::assembler::directive::section::Sections;
 use crate::assembler::directive::structure::Structure;
+use crate::assembler::directive::timing::Timing;
+use crate::assembler::directive::unittest::UnitTests;
 use crate::assembler::emitter::Emitter;
 use crate::assembler::error::{Error, ErrorLevel};
 use crate::assembler::error_type::ErrorType;
-use crate::assembler::error_type::ErrorType::SyntaxError;
 use crate::assembler::expression::ExpressionParser;
+use crate::assembler::file_provider::DiskFiles;
 use crate::assembler::get_token::GetToken;
 use crate::assembler::instruction_encoder::InstructionEncoder;
 use crate::assembler::label::Label;
+use crate::assembler::listing::Listing;
+use crate::assembler::nex::Nex;
+use crate::assembler::snapshot::Snapshot;
+use crate::assembler::symbols::SymbolFormat;
+use crate::assembler::tap::Tap;
+use crate::assembler::sld::Sld;
+use crate::assembler::token_reader::{DELIMITERS, OPERATORS};
 use crate::assembler::tokens::{AluOp, OpCode, Token};
-use crate::assembler::tokens::Directive::{Else, End, EndIf, Global, If};
+use crate::assembler::unit_test::TestResult;
+use crate::assembler::tokens::Directive::{Else, ElseIf, EndIf, Global, If, IfDef, IfNotDef};
 use crate::assembler::tokens::Op::Equals;
 use crate::assembler::tokens::RotOp::{Rl, Rlc, Rr, Rrc, Sla, Sll, Sra, Srl};
 use crate::assembler::tokens::Token::{Directive, Operator};
 
+const MAX_PASSES: usize = 10;
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler::new()
+    }
+}
+
 impl Assembler {
     pub fn new() -> Assembler {
         let context = Default::default();
         Assembler {
             context,
-            macros: MacroHandler::new(),
+            macros: MacroHandler::new(MAX_MACRO_DEPTH),
             tokens: vec![],
             origin: 0,
             bank: Bank::new(),
@@ -40,11 +64,41 @@ impl Assembler {
             collect_macro: false,
             collect_enum: None,
             collect_struct: None,
-            warnings: vec![],
+            diagnostics: vec![],
+            messages: vec![],
+            warn_unused: false,
+            max_errors: 0,
+            max_macro_depth: MAX_MACRO_DEPTH,
             include_dirs: vec![],
             labels_file: String::new(),
+            symbol_format: SymbolFormat::Default,
+            export_all: false,
             if_level: vec![],
             defines: vec![],
+            pass_options: PassOptions::default(),
+            jr_promote: false,
+            relative_jumps: HashMap::new(),
+            promoted_jumps: HashSet::new(),
+            listing: Listing::new(),
+            nex: Nex::new(),
+            snapshots: vec![],
+            taps: vec![],
+            sld: Sld::new(),
+            macro_source: None,
+            files: Box::new(DiskFiles),
+            source: None,
+            testing: false,
+            tests: vec![],
+            instructions: vec![],
+            timings_file: String::new(),
+            timing_regions: vec![],
+            phased: false,
+            sections: vec![],
+            section: None,
+            layout: HashMap::new(),
+            section_layout: HashMap::new(),
+            section_options: vec![],
+            memory_block: false,
             //next_label_global: false,
         }
     }
@@ -58,123 +112,333 @@ impl Assembler {
         }
     }
 
-    pub fn assemble(&mut self, file_name: &str) -> Result<(), Error> {
-        self.warnings.clear();
-        if self.console_output { green_ln!("First pass .... "); }
-
-        self.first_pass(file_name)?;
-        self.write_status();
+    fn begin_pass(&mut self, final_pass: bool) {
+        let options = self.pass_options;
+        self.origin = options.origin;
+        self.z80n_enabled = options.z80n_enabled;
+        self.c_spect_enabled = options.c_spect_enabled;
+        self.console_output = options.console_output;
+        self.jr_promote = options.jr_promote;
+        self.listing.enable(options.listing);
+        self.listing.begin_pass(final_pass);
+        self.bank.max_code_size(options.max_code_size);
+        self.bank.clear();
+        self.context.begin_pass(final_pass);
+        self.context.pc(self.origin);
+        self.bank.origin(self.origin);
+        self.nex = Nex::new();
+        self.snapshots.clear();
+        self.taps.clear();
+        self.tests.clear();
+        self.instructions.clear();
+        self.timing_regions.clear();
+        self.phased = false;
+        self.sections.clear();
+        self.section = None;
+        self.layout.clear();
+        self.memory_block = false;
+        self.sld.begin_pass(final_pass);
+        self.macro_source = None;
+        self.macros = MacroHandler::new(self.max_macro_depth);
+        self.tokens.clear();
+        self.if_level.clear();
+        self.collect_enum = None;
+        self.collect_struct = None;
+        self.diagnostics.clear();
+        self.messages.clear();
+        for define in self.defines.iter().filter(|d| !d.contains('=')) {
+            self.diagnostics.push(Error::warning(&ErrorType::DefineWithoutValue(define.clone()).to_string(), -1, ""));
+        }
+        self.total_lines = 0;
+        self.relative_jumps.clear();
+    }
 
-        self.warnings.clear();
+    pub fn assemble(&mut self, file_name: &str) -> Result<(), Error> {
+        self.pass_options = PassOptions {
+            origin: self.origin,
+            z80n_enabled: self.z80n_enabled,
+            c_spect_enabled: self.c_spect_enabled,
+            console_output: self.console_output,
+            max_code_size: self.bank.max_size(),
+            jr_promote: self.jr_promote,
+            listing: self.listing.enabled(),
+        };
+        self.promoted_jumps.clear();
+        self.parse_section_options()?;
 
-        if self.console_output { green_ln!("Second pass ... "); }
+        // Repeat until no label or constant changes value, forward references use the previous pass.
+        // Errors are reported by the final pass, only a fatal error stops early
+        loop {
+            self.begin_pass(false);
+            if self.console_output { green_ln!("Pass {} ....... ", self.context.pass); }
+            self.assemble_file(file_name)?;
+            let unstable = self.context.unstable_symbols();
+            if unstable.is_empty() {
+                break;
+            }
+            if self.context.pass >= MAX_PASSES {
+                // Values that depend on a line in error may never settle, report the error instead
+                if self.num_errors() > 0 {
+                    break;
+                }
+                let message = ErrorType::ValueNotConverged(MAX_PASSES, unstable.join(", ")).to_string();
+                return Err(Error::fatal(&message, -1, file_name));
+            }
+        }
 
-        self.second_pass()?;
+        // All values are stable, assemble once more reporting any errors that depend on them
+        self.begin_pass(true);
+        if self.console_output { green_ln!("Final pass ... "); }
+        self.assemble_file(file_name)?;
+        self.check_sections()?;
+        if self.warn_unused {
+            self.unused_labels();
+        }
         self.write_status();
+        if self.num_errors() > 0 {
+            return Err(Error::fatal(&ErrorType::AssemblyFailed(self.num_errors()).to_string(), -1, file_name));
+        }
+
+        self.listing.write(file_name, self.bank.as_slice())?;
+        self.write_timings()?;
+        self.sld.write(file_name, self.bank.device())?;
+        if !self.nex.file_name.is_empty() {
+            let nex_file = self.nex.file_name.clone();
+            self.write_nex(&nex_file)?;
+        }
+        for snapshot in self.snapshots.clone() {
+            self.write_snapshot(&snapshot)?;
+        }
+        for tap in self.taps.clone() {
+            self.write_tap(&tap)?;
+        }
 
-        self.context.export_labels(&self.labels_file)?;
+        self.context.export_labels(&self.labels_file, self.symbol_format, self.export_all)?;
 
         Ok(())
     }
 
-    pub(crate) fn first_pass(&mut self, file_name: &str) -> Result<(), Error> {
+    pub(crate) fn assemble_file(&mut self, file_name: &str) -> Result<(), Error> {
         self.collect_macro = false;
-        self.context.enter(file_name, &self.defines);
-        let file = File::open(file_name)?;
-        let buf = BufReader::new(file);
-        let mut reader = TokenReader::new(buf);
-        reader.delimiters(",").operators("()*/+-<>=^&|");
+        let if_depth = self.if_level.len();
+        let phased = self.phased;
+        let memory_block = self.memory_block;
+        let modules = self.context.modules.len();
+        self.context.enter(file_name, &self.defines)?;
+        let data = match &self.source {
+            Some((name, source)) if name == file_name => source.as_bytes().to_vec(),
+            _ => self.files.read(file_name)?
+        };
+        let mut reader = TokenReader::new(data.as_slice());
+        reader.delimiters(DELIMITERS).operators(OPERATORS);
         reader.file_name(file_name);
         self.tokens.clear();
         loop {
-            let tokens = &mut reader.read_line()?;
+            let tokens = &mut match reader.read_line() {
+                Ok(tokens) => tokens,
+                Err(e) => {
+                    self.context.next_line();
+                    self.report(e)?;
+                    continue;
+                }
+            };
             self.total_lines += 1;
             if tokens.first() == Some(&Token::EndOfFile) {
                 break;
             }
-            if self.macros.collecting() && tokens.first() != Some(&Token::Directive(End)) {
-                self.macros.collect(&mut self.context, tokens)?;
-            } else {
-                self.translate(tokens)?;
+            let entry = self.listing_begin(reader.line_number, reader.source_line(), false);
+            let source = MacroSource {
+                text: reader.source_line().to_string(),
+                file_name: self.context.current_file_name(),
+                line_number: self.context.current_line_number() + 1,
+            };
+            let result = self.assemble_line(tokens, source);
+            self.listing_end(entry);
+            if let Err(e) = result {
+                self.report(e)?;
             }
         }
+        // An IF, PHASE, MEMORY or MODULE block has to end in the file it started in, reported on the last line
+        if self.if_level.len() > if_depth {
+            self.if_level.truncate(if_depth);
+            let e = self.context.error(ErrorType::IfWithoutEndIf);
+            self.report(e)?;
+        }
+        if self.phased && !phased {
+            let e = self.context.error(ErrorType::PhaseWithoutDephase);
+            self.report(e)?;
+        }
+        if self.memory_block && !memory_block {
+            self.memory_block = false;
+            let e = self.context.error(ErrorType::MemoryWithoutEndMemory);
+            self.report(e)?;
+        }
+        if self.context.modules.len() > modules {
+            self.context.modules.truncate(modules);
+            let e = self.context.error(ErrorType::ModuleWithoutEndModule);
+            self.report(e)?;
+        }
         self.context.leave();
         Ok(())
     }
 
-    pub fn second_pass(&mut self) -> Result<(), Error> {
-        while let Some(mut fwd_ref) = self.context.next_forward_ref() {
-            let mut data: isize;
-            if fwd_ref.is_expression {
-                self.context.label_context = fwd_ref.label;
-                data = match self.expr.eval(&mut self.context, fwd_ref.expression.as_mut()) {
-                    Ok(n) => n,
-                    Err(e) => return Err(self.error_second_pass(e, fwd_ref.line_no, &fwd_ref.file_name)),
-                }
-            } else {
-                data = match self.context.get_label_or_constant_value(fwd_ref.label.as_str()) {
-                    Ok(n) => n,
-                    Err(_) => return Err(self.error_second_pass(SyntaxError, fwd_ref.line_no, &fwd_ref.file_name)),
-                }
-            }
-            let index = fwd_ref.pc as usize - self.origin as usize;
-            if fwd_ref.is_relative {
-                let offset = data - (fwd_ref.pc + 1) as isize;
-                self.bank[index] = offset as u8;
-            } else {
-                for d in 0..fwd_ref.byte_count as usize {
-                    self.bank[index + d] = (data & 0xff) as u8;
-                    data = data >> 8;
-                }
-                if self.z80n_enabled && fwd_ref.byte_count == 2 && index > 1 && self.bank[index - 2] == 0xed && self.bank[index - 1] == 0x8a {
-                    let b = self.bank[index + 1];
-                    self.bank[index + 1] = self.bank[index];
-                    self.bank[index] = b;
-                }
-            }
+    // Assembles source held in a string, includes are still read through the file provider
+    pub fn assemble_source(&mut self, name: &str, source: &str) -> Result<Assembled, Error> {
+        self.source = Some((name.to_string(), source.to_string()));
+        let result = self.assemble(name);
+        self.source = None;
+        result?;
+        Ok(self.assembled())
+    }
+
+    pub fn assembled(&mut self) -> Assembled {
+        Assembled {
+            bytes: self.output(),
+            symbols: self.context.symbols(true),
+            diagnostics: self.diagnostics.clone(),
+            messages: self.messages.clone(),
         }
-        Ok(())
+    }
+
+    // The encoding cross check followed by the result of each UNITTEST
+    pub fn run_tests(&mut self) -> Vec<TestResult> {
+        let mut results = vec![self.check_encoding()];
+        results.append(&mut self.execute_tests());
+        results
+    }
+
+    pub fn diagnostics(&self) -> &[Error] {
+        &self.diagnostics
+    }
+
+    pub fn messages(&self) -> &[String] {
+        &self.messages
     }
 
     pub fn save_raw(&mut self, file_name: &str) -> Result<(), Error> {
         let mut file = File::create(file_name)?;
-        file.write_all(self.bank.as_slice())?;
+        file.write_all(&self.output())?;
+        Ok(())
+    }
+
+    // With a paged memory model or sections the output is a memory image from the lowest to the highest address
+    // written, as the pages are currently mapped, otherwise the code is in the order it was assembled
+    fn output(&mut self) -> Vec<u8> {
+        let device = self.bank.device();
+        if device.is_paged() {
+            return match device.view_range() {
+                Some((first, last)) => device.view()[first..=last].to_vec(),
+                None => vec![]
+            };
+        }
+        match self.bank.address_range() {
+            Some((first, last)) if !self.sections.is_empty() => self.bank.read(first, (last - first + 1) as usize),
+            _ => self.bank.as_slice().to_vec()
+        }
+    }
+
+    pub fn save_nex(&mut self, file_name: &str) -> Result<(), Error> {
+        if !self.z80n_enabled {
+            return Err(Error::fatal(&ErrorType::Z80NDisabled.to_string(), -1, file_name));
+        }
+        self.write_nex(file_name)
+    }
+
+    pub fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        self.write_snapshot(snapshot)
+    }
+
+    pub fn save_tap(&mut self, tap: &Tap) -> Result<(), Error> {
+        self.write_tap(tap)
+    }
+
+    pub(crate) fn listing_begin(&mut self, line_number: isize, source: &str, expanded: bool) -> Option<usize> {
+        let offset = self.bank.as_slice().len();
+        self.listing.begin_line(&mut self.context, offset, line_number, source, expanded)
+    }
+
+    pub(crate) fn listing_end(&mut self, entry: Option<usize>) {
+        let offset = self.bank.as_slice().len();
+        self.listing.end_line(entry, offset);
+    }
+
+    // Errors are recorded and assembly carries on with the next line, fatal errors or
+    // reaching the error limit stop the assembly
+    pub(crate) fn report(&mut self, e: Error) -> Result<(), Error> {
+        if e.is_fatal() {
+            return Err(e);
+        }
+        self.diagnostics.push(e);
+        if self.max_errors > 0 && self.num_errors() >= self.max_errors {
+            let message = ErrorType::TooManyErrors(self.num_errors()).to_string();
+            return Err(Error::fatal(&message, -1, &self.context.current_file_name()));
+        }
         Ok(())
     }
 
+    // Errors inside a macro give the body line of each expansion as well as the line that called it
+    fn in_expansion(&self, mut e: Error) -> Error {
+        if !e.is_fatal() {
+            e.message = format!("{} in macro {}", e.message, self.macros.trace());
+        }
+        e
+    }
+
     pub fn warn(&mut self, t: ErrorType) {
-        self.warnings.push(format!("[{} : {}] Warning: {}", self.context.current_file_name(), self.context.current_line_number(), t.to_string()))
+        let warning = Error::warning(&t.to_string(), self.context.current_line_number(), &self.context.current_file_name());
+        self.diagnostics.push(warning);
+    }
+
+    // A warning for each label that is never referred to, in source order
+    fn unused_labels(&mut self) {
+        let mut unused: Vec<(String, isize, String)> = self.context.used.iter()
+            .filter(|(_, (used, _, _))| !used)
+            .map(|(name, (_, line, file))| (file.clone(), *line, name.clone()))
+            .collect();
+        unused.sort();
+        for (file, line, name) in unused {
+            self.diagnostics.push(Error::warning(&ErrorType::UnusedLabel(name).to_string(), line, &file));
+        }
+    }
+
+    fn count(&self, level: ErrorLevel) -> usize {
+        self.diagnostics.iter().filter(|d| d.level == level).count()
     }
 
     pub fn num_warnings(&self) -> usize {
-        self.warnings.len()
+        self.count(ErrorLevel::Warning)
+    }
+
+    pub fn num_errors(&self) -> usize {
+        self.count(ErrorLevel::Error)
     }
 
     pub fn display_warnings(&self) {
         if self.console_output {
-            for warning in &self.warnings {
-                cyan_ln!("{}",warning);
+            for warning in self.diagnostics.iter().filter(|d| d.level == ErrorLevel::Warning) {
+                if warning.line_no > -1 {
+                    cyan_ln!("[{} : {}] Warning: {}", warning.file_name, warning.line_no, warning.message);
+                } else {
+                    cyan_ln!("Warning: {}", warning.message);
+                }
             }
         }
     }
 
-    pub fn info(&mut self, m: &str) {
-        if self.console_output {
-            yellow_ln!("[{}:{}] {}",  self.context.current_file_name(), self.context.current_line_number(), m);
+    pub fn display_errors(&self) {
+        for error in self.diagnostics.iter().filter(|d| d.level == ErrorLevel::Error) {
+            red_ln!("[{} : {}] {}", error.file_name, error.line_no, error.message);
         }
     }
 
-    pub fn error_second_pass(&mut self, t: ErrorType, line_no: isize, file_name: &str) -> Error {
-        Error {
-            line_no,
-            message: t.to_string(),
-            level: ErrorLevel::Fatal,
-            file_name: file_name.to_string(),
+    pub fn info(&mut self, m: &str) {
+        if self.console_output && self.context.final_pass {
+            yellow_ln!("[{}:{}] {}",  self.context.current_file_name(), self.context.current_line_number(), m);
         }
     }
 
     fn handle_opcodes(&mut self, op: OpCode) -> Result<(), Error> {
-        return match op {
+        match op {
             OpCode::Nop => self.emit_byte(0),
             OpCode::Adc => self.alu_op_r(AluOp::Adc, 1, 0),
             OpCode::Add => self.alu_op_r(AluOp::Add, 0, 1),
@@ -253,7 +517,7 @@ impl Assembler {
                 self.encode_cspect(&op)?;
                 Ok(())
             }
-        };
+        }
     }
 
     fn encode_cspect(&mut self, op: &OpCode) -> Result<(), Error> {
@@ -287,7 +551,7 @@ impl Assembler {
             OpCode::Pixeldn => Some(vec![0xED, 0x93]),
             OpCode::Pixelad => Some(vec![0xED, 0x94]),
             OpCode::Setae => Some(vec![0xED, 0x95]),
-            OpCode::Test => Some(vec![0xED, 0x27, self.expect_byte(2)? as u8]),
+            OpCode::Test => Some(vec![0xED, 0x27, self.expect_byte()? as u8]),
             _ => None
         };
         if let Some(b) = code {
@@ -303,7 +567,7 @@ impl Assembler {
         self.context.next_label_global = false;
         if self.next_token_is(&Operator(Equals)) {
             self.tokens.pop();
-            match self.expr.parse(&mut self.context, &mut self.tokens, 0, -1, false) {
+            match self.expr.parse(&mut self.context, &mut self.tokens) {
                 Ok(Some(n)) => self.context.add_constant(l.to_string(), n)?,
                 Ok(None) => return Err(self.context.error(ErrorType::SyntaxError)),
                 Err(e) => return Err(self.context.error(e))
@@ -312,29 +576,29 @@ impl Assembler {
                 self.tokens.clear();
                 self.warn(ErrorType::ExtraCharacters)
             }
+        } else if let Some(key) = AssemblerContext::temp_label(l) {
+            self.context.add_temp_label(key);
         } else {
-            self.context.add_label(l.to_string(), global)?
+            self.context.add_label(l.to_string(), global)?;
+            let name = self.context.qualified_name(l.trim_end_matches(':'));
+            let address = self.context.offset_pc(0);
+            if let Some(physical) = self.bank.device().physical(address) {
+                self.context.physical.insert(name.clone(), physical);
+            }
+            if self.sld.recording() {
+                let page = self.bank.device().page_at(address);
+                self.sld.label(&self.context, self.macro_source.as_ref(), page, &name, address);
+            }
         }
         Ok(())
     }
 
     pub fn skip_translate(&mut self) -> Result<bool, Error> {
+        // Nested conditionals are still processed while skipping so each ENDIF closes its own IF
         let skip = match self.if_level.last().unwrap_or(&IfBlock::None) {
-            IfBlock::SkipEnd => match self.tokens.last() {
-                Some(Directive(EndIf)) => false,
-                _ => true
-            }
-            IfBlock::If(false) => match self.tokens.last() {
-                Some(Directive(If)) => false,
-                Some(Directive(Else)) => false,
-                Some(Directive(EndIf)) => false,
-                _ => true
-            },
-            IfBlock::Else(false) => match self.tokens.last() {
-                Some(Directive(If)) => false,
-                Some(Directive(EndIf)) => false,
-                _ => true
-            },
+            IfBlock::SkipEnd | IfBlock::If(false) | IfBlock::Else(false) => !matches!(self.tokens.last(),
+                Some(Directive(If)) | Some(Directive(IfDef)) | Some(Directive(IfNotDef)) |
+                Some(Directive(Else)) | Some(Directive(ElseIf)) | Some(Directive(EndIf))),
             _ => false
         };
 
@@ -344,6 +608,15 @@ impl Assembler {
         Ok(skip)
     }
 
+    // Lines inside a MACRO or REPT body are collected instead of assembled
+    pub(crate) fn assemble_line(&mut self, tokens: &mut Vec<Token>, source: MacroSource) -> Result<(), Error> {
+        if self.macros.collects(tokens) {
+            self.macros.collect(&mut self.context, tokens, source)
+        } else {
+            self.translate(tokens)
+        }
+    }
+
     pub fn translate(&mut self, tokens: &mut Vec<Token>) -> Result<(), Error> {
         if !self.macros.expanding() {
             self.context.next_line();
@@ -366,15 +639,32 @@ impl Assembler {
             } else if let Some(tok) = self.tokens.pop() {
                 match &tok {
                     Token::Directive(d) => self.process_directive(*d)?,
-                    Token::OpCode(op) => self.handle_opcodes(op.clone())?,
+                    Token::OpCode(op) => {
+                        let offset = self.bank.as_slice().len();
+                        self.handle_opcodes(op.clone())?;
+                        self.record_instruction(offset);
+                    }
                     Token::ConstLabel(l) => {
                         if self.is_struct(l) {
                             self.emit_struct(l)?;
                         } else if self.macros.macro_defined(l) {
                             self.macros.begin_expand(&mut self.context, l, &mut self.tokens)?;
-                            while let Some(line) = self.macros.expand() {
-                                self.translate(&mut line.clone())?
+                            // The expansion's own label replaces the scope for local labels until it ends
+                            let scope = self.context.label_context.clone();
+                            let outer_source = self.macro_source.take();
+                            while let Some((line, source)) = self.macros.expand() {
+                                let entry = self.listing_begin(self.context.current_line_number(), &source.text, true);
+                                self.macro_source = Some(source.clone());
+                                let result = self.assemble_line(&mut line.clone(), source);
+                                self.listing_end(entry);
+                                if let Err(e) = result {
+                                    let e = self.in_expansion(e);
+                                    self.report(e)?;
+                                }
                             }
+                            self.macro_source = outer_source;
+                            self.context.label_context = scope;
+                            self.tokens.clear();
                         } else {
                             self.handle_label(l, self.context.next_label_global)?
                         }
@@ -388,4 +678,91 @@ impl Assembler {
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::assembler::error::ErrorLevel;
+    use crate::assembler::test_support::assemble;
+
+    #[test]
+    fn forward_references_converge() {
+        let (mut assembler, result) = assemble("converge", " ds size\n ld a,size\nsize equ end_-start\nstart\n nop\n nop\nend_\n");
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[0, 0, 0x3e, 2, 0, 0]);
+    }
+
+    #[test]
+    fn unstable_value_is_reported() {
+        let (_, result) = assemble("unstable", " ds 1-end_\nend_\n");
+        match result {
+            Err(e) => assert_eq!(e.message, "Value did not converge after 10 passes, unstable symbols: end_"),
+            Ok(_) => panic!("expected ValueNotConverged"),
+        }
+    }
+
+    #[test]
+    fn errors_do_not_stop_convergence() {
+        // The JR is only in range once size has settled, an earlier error must not cut the passes short
+        let (assembler, result) = assemble("converge_errors", " ld q,1\n jr target\n ds 200-size\ntarget\nsize equ 150\n");
+        assert!(result.is_err());
+        let errors: Vec<(isize, &str)> = assembler.diagnostics.iter().map(|d| (d.line_no, d.message.as_str())).collect();
+        assert_eq!(errors, [(1, "Syntax error")]);
+    }
+
+    #[test]
+    fn relative_jump_range() {
+        let (mut assembler, result) = assemble("jr_range", " jr end_\n ds 127\nend_ djnz $\n");
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank[0], 0x18);
+        assert_eq!(assembler.bank[1], 0x7F);
+        assert_eq!(assembler.bank.as_slice()[129..], [0x10, 0xFE]);
+
+        let (_, result) = assemble("jr_out_of_range", " jr end_\n ds 128\nend_\n");
+        match result {
+            Err(e) => {
+                assert!(matches!(e.level, ErrorLevel::Fatal));
+                assert_eq!(e.line_no, 1);
+                assert_eq!(e.message, "Relative jump out of range, distance 128 from 0x0000 to 0x0082 (must be -128..127)");
+            }
+            Ok(_) => panic!("expected RelativeJumpOutOfRange"),
+        }
+    }
+
+    #[test]
+    fn relative_jump_promotion() {
+        let (mut assembler, result) = assemble("jr_promote", " !opt jrpromote on\n jr nz,end_\n jr start\nstart ds 128\nend_\n");
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice()[..5], [0xC2, 0x85, 0x00, 0x18, 0x00]);
+        assert_eq!(assembler.num_warnings(), 1);
+
+        let (_, result) = assemble("djnz_promote", " !opt jrpromote on\n djnz end_\n ds 128\nend_\n");
+        assert!(result.is_err());
+
+        // The JR inside the IF only appears once the first JR is promoted, it must stay a JR
+        let source = " !opt jrpromote on\nback\n ds 130\n jr nz,ahead\n if $ == 0x85\n jr $\n endif\n jr back\n ds 130\nahead\n";
+        let (mut assembler, result) = assemble("jr_promote_if", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice()[130..138], [0xC2, 0x0C, 0x01, 0x18, 0xFE, 0xC3, 0x00, 0x00]);
+        let lines: Vec<isize> = assembler.diagnostics.iter().map(|d| d.line_no).collect();
+        assert_eq!(lines, [4, 8]);
+    }
+
+    #[test]
+    fn paged_output() {
+        let source = " device zxspectrum128\n org 0xC000\n page 1\n db 1\n page 3\n db 3\n mmu 3,4\n db 4\n";
+        let (mut assembler, result) = assemble("paged", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[1, 3, 4]);
+        // Only page 4 is still mapped in, the bytes on pages 1 & 3 are left out
+        let output = assembler.output();
+        assert_eq!(output, [4]);
+
+        let source = " device zxspectrum128\n org 0x8000\n db 2\n org 0xC000\n db 0\n page 1\n db 1\n";
+        let (mut assembler, result) = assemble("paged_view", source);
+        assert!(result.is_ok());
+        let output = assembler.output();
+        // The 0 written at 0xC000 was to page 0 which is no longer mapped
+        assert_eq!(output.len(), 0x4002);
+        assert_eq!([output[0], output[0x4000], output[0x4001]], [2, 0, 1]);
+    }
+}
@@ -1,29 +1,42 @@
 use std::collections::HashMap;
+use std::mem;
 
-use asciimath::{eval, scope};
 use indexmap::map::IndexMap;
 
+use crate::assembler::TokenReader;
 use crate::assembler::error::{Error, ErrorLevel};
 use crate::assembler::error_type::ErrorType;
-use crate::assembler::ForwardReference;
+use crate::assembler::expression::ExpressionParser;
 use crate::assembler::label::Label;
+use crate::assembler::token_reader::{DELIMITERS, OPERATORS};
 
 #[derive(Default)]
 pub struct AssemblerContext {
     pub(super) labels: HashMap<String, isize>,
     pub(super) used: HashMap<String, (bool, isize, String)>,
     pub(super) global_labels: Vec<String>,
+    pub(super) local_labels: Vec<String>,
+    pub(super) physical: HashMap<String, usize>,
     pub(super) constants: HashMap<String, isize>,
+    pub(super) constant_source: HashMap<String, (isize, String)>,
+    pub(super) enum_names: Vec<String>,
     pub(super) size_of: HashMap<String, isize>,
     pub(super) struct_defs: HashMap<String, IndexMap<String, isize>>,
-    pub(super) forward_references: Vec<ForwardReference>,
+    pub(super) prev_labels: HashMap<String, isize>,
+    pub(super) prev_constants: HashMap<String, isize>,
+    pub(super) prev_size_of: HashMap<String, isize>,
     pub(super) line_number: Vec<isize>,
     pub(super) file_name: Vec<String>,
     pub(super) current_pc: isize,
     pub(super) label_context: String,
+    pub(super) modules: Vec<String>,
+    pub(super) temp_labels: HashMap<String, usize>,
     pub(super) asm_pc: isize,
     pub(super) next_label_global: bool,
     pub(super) case_insensitive: bool,
+    pub(super) pass: usize,
+    pub(super) final_pass: bool,
+    pub(super) unresolved: usize,
 }
 
 impl AssemblerContext {
@@ -61,25 +74,27 @@ impl AssemblerContext {
         }
     }
 
-    pub fn enter(&mut self, name: &str, defines: &Vec<String>) {
+    // A -D value that cannot be evaluated stops the build rather than quietly defining 0
+    pub fn enter(&mut self, name: &str, defines: &[String]) -> Result<(), Error> {
         for s in defines {
             if s.contains("=") {
                 let t: Vec<&str> = s.split("=").collect();
                 let label = t[0];
-                let value = match eval(t[1], &scope! {}) {
-                    Ok(n) => n as isize,
-                    Err(e) => {
-                        println!("Error: {}", e);
-                        0
-                    }
+                let mut reader = TokenReader::new(t[1].as_bytes());
+                reader.delimiters(DELIMITERS).operators(OPERATORS);
+                let value = match reader.read_line() {
+                    Ok(mut tokens) => match ExpressionParser::new().eval(self, &mut tokens) {
+                        Ok(n) => n,
+                        Err(e) => return Err(Error::fatal(&format!("{} - {}", ErrorType::BadDefine(label.to_string()), e), -1, name))
+                    },
+                    Err(e) => return Err(Error::fatal(&format!("{} - {}", ErrorType::BadDefine(label.to_string()), e.message), -1, name))
                 };
                 self.constants.insert(label.to_string(), value);
-            } else {
-                println!("Invalid Define {}", s);
             }
         }
         self.file_name.push(name.to_string());
         self.line_number.push(0);
+        Ok(())
     }
 
     pub fn leave(&mut self) {
@@ -87,48 +102,179 @@ impl AssemblerContext {
         self.line_number.pop();
     }
 
+    // Moves the current location to where a replayed line was written, returning the location it replaces
+    pub fn relocate(&mut self, file_name: &str, line_number: isize) -> (String, isize) {
+        let previous = (self.current_file_name(), self.current_line_number());
+        if let (Some(file), Some(line)) = (self.file_name.last_mut(), self.line_number.last_mut()) {
+            *file = file_name.to_string();
+            *line = line_number;
+        }
+        previous
+    }
+
     pub fn next_line(&mut self) {
         let len = self.line_number.len() - 1;
         self.line_number[len] += 1;
     }
 
-    pub fn get_label_or_constant_value(&mut self, name: &str) -> Result<isize, Error> {
-        if let Some(address) = self.get_label(name) {
-            self.mark_label_used(name);
-            return Ok(address);
-        }
-        if let Some(&address) = self.constants.get(name) {
-            return Ok(address);
-        }
-        Err(self.error(ErrorType::LabelNotFound))
-    }
-
-
     pub fn error(&mut self, t: ErrorType) -> Error {
         Error {
             line_no: self.current_line_number(),
             message: t.to_string(),
-            level: ErrorLevel::Fatal,
+            level: ErrorLevel::Error,
             file_name: self.current_file_name(),
         }
     }
 
     pub fn error_text(&mut self, t: ErrorType, text: &str) -> Error {
-        let message = format!("{} - {}", t.to_string(), text);
+        let message = format!("{} - {}", t, text);
         Error {
             line_no: self.current_line_number(),
             message,
-            level: ErrorLevel::Fatal,
+            level: ErrorLevel::Error,
             file_name: self.current_file_name(),
         }
     }
 
+    pub fn begin_pass(&mut self, final_pass: bool) {
+        self.pass += 1;
+        self.final_pass = final_pass;
+        self.prev_labels = mem::take(&mut self.labels);
+        self.prev_constants = mem::take(&mut self.constants);
+        self.prev_size_of = mem::take(&mut self.size_of);
+        self.used.clear();
+        self.global_labels.clear();
+        self.local_labels.clear();
+        self.physical.clear();
+        self.struct_defs.clear();
+        self.constant_source.clear();
+        self.enum_names.clear();
+        self.label_context.clear();
+        self.modules.clear();
+        self.temp_labels.clear();
+        self.line_number.clear();
+        self.file_name.clear();
+        self.next_label_global = false;
+    }
+
+    fn case(&self, name: String) -> String {
+        if self.case_insensitive { name.to_uppercase() } else { name }
+    }
+
+    // Labels and constants defined inside a MODULE are prefixed with the module, @name is always global
+    pub fn scoped_name(&self, name: &str) -> String {
+        match name.strip_prefix('@') {
+            Some(global) => global.to_string(),
+            None if self.modules.is_empty() => name.to_string(),
+            None => format!("{}.{}", self.modules.join("."), name)
+        }
+    }
+
+    // The key of a temporary label definition, 1: or @@ and : for anonymous labels
+    pub fn temp_label(name: &str) -> Option<&str> {
+        match name.strip_suffix(':').unwrap_or(name) {
+            "" | "@@" => Some("@"),
+            number if !number.starts_with('0') && number.chars().all(|c| c.is_ascii_digit()) => Some(number),
+            _ => None
+        }
+    }
+
+    // 1b and 1f are the nearest 1: before or after, each - goes back and each + forward one anonymous label.
+    // Temporary labels are numbered in the order they are defined, which is the same in every pass
+    fn temp_reference(&self, name: &str) -> Option<String> {
+        let defined = |key: &str| *self.temp_labels.get(key).unwrap_or(&0) as isize;
+        let length = name.len() as isize;
+        if length > 0 && name.chars().all(|c| c == '-') {
+            return Some(format!("#@.{}", defined("@") - length + 1));
+        }
+        if length > 0 && name.chars().all(|c| c == '+') {
+            return Some(format!("#@.{}", defined("@") + length));
+        }
+        let direction = name.chars().last()?;
+        let number = &name[..name.len() - direction.len_utf8()];
+        if number.is_empty() || number.starts_with('0') || !number.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        match direction {
+            'b' | 'B' => Some(format!("#{}.{}", number, defined(number))),
+            'f' | 'F' => Some(format!("#{}.{}", number, defined(number) + 1)),
+            _ => None
+        }
+    }
+
+    // A reference looks in the current module, then each enclosing module and finally the global scope
+    pub fn resolve(&self, name: &str) -> String {
+        if let Some(temp) = self.temp_reference(name) {
+            return temp;
+        }
+        if let Some(global) = name.strip_prefix('@') {
+            return self.case(global.to_string());
+        }
+        for depth in (1..=self.modules.len()).rev() {
+            let candidate = self.case(format!("{}.{}", self.modules[..depth].join("."), name));
+            let exists = [&self.labels, &self.constants, &self.prev_labels, &self.prev_constants]
+                .iter().any(|symbols| symbols.contains_key(&candidate));
+            if exists {
+                return candidate;
+            }
+        }
+        self.case(name.to_string())
+    }
 
-    pub fn next_forward_ref(&mut self) -> Option<ForwardReference> {
-        self.forward_references.pop()
+    pub fn forward_reference(&mut self, name: &str) -> Option<isize> {
+        let constant = self.resolve(name);
+        if let Some(&n) = self.prev_constants.get(&constant) {
+            return Some(n);
+        }
+        let label = self.qualified_name(name);
+        let value = self.prev_labels.get(&label).cloned();
+        if value.is_some() && !self.used.contains_key(&label) {
+            self.used.insert(label, (true, 0, String::new()));
+        }
+        value
     }
 
-    pub fn add_forward_ref(&mut self, fw: ForwardReference) {
-        self.forward_references.push(fw);
+    pub fn unstable_symbols(&self) -> Vec<String> {
+        let mut names = vec![];
+        for (current, previous) in &[(&self.labels, &self.prev_labels), (&self.constants, &self.prev_constants)] {
+            for (name, value) in current.iter() {
+                if previous.get(name) != Some(value) {
+                    names.push(name.to_string());
+                }
+            }
+            for name in previous.keys() {
+                if !current.contains_key(name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_context::AssemblerContext;
+    use crate::assembler::error::ErrorLevel;
+
+    #[test]
+    fn define_values() {
+        let mut context = AssemblerContext::default();
+        assert!(context.enter("test.asm", &["X=3+4".to_string()]).is_ok());
+        assert_eq!(context.constants.get("X"), Some(&7));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bad_define_is_fatal() {
+        let mut context = AssemblerContext::default();
+        match context.enter("test.asm", &["X=1/0".to_string()]) {
+            Err(e) => {
+                assert!(matches!(e.level, ErrorLevel::Fatal));
+                assert!(e.message.starts_with("Invalid value for define 'X'"));
+            }
+            Ok(_) => panic!("expected a fatal error"),
+        }
+        assert!(!context.constants.contains_key("X"));
+    }
+}
@@ -1,19 +1,34 @@
 use std::string::ToString;
 
 use crate::assembler::Assembler;
+use crate::assembler::directive::macros::MAX_MACRO_DEPTH;
+use crate::assembler::file_provider::FileProvider;
+use crate::assembler::symbols::SymbolFormat;
 
 pub trait AssemblerOptions {
     fn enable_z80n(&mut self, enabled: bool) -> &mut Assembler;
     fn enable_console(&mut self, enabled: bool) -> &mut Assembler;
     fn enable_cspect(&mut self, enabled: bool) -> &mut Assembler;
     fn enable_debug(&mut self, enabled: bool) -> &mut Assembler;
+    fn enable_jr_promote(&mut self, enabled: bool) -> &mut Assembler;
+    fn enable_listing(&mut self, enabled: bool) -> &mut Assembler;
+    fn listing_file(&mut self, file_name: &str) -> &mut Assembler;
+    fn sld_file(&mut self, file_name: &str) -> &mut Assembler;
+    fn timings_file(&mut self, file_name: &str) -> &mut Assembler;
+    fn max_errors(&mut self, count: usize) -> &mut Assembler;
+    fn max_macro_depth(&mut self, depth: usize) -> &mut Assembler;
+    fn enable_tests(&mut self, enabled: bool) -> &mut Assembler;
+    fn file_provider(&mut self, files: Box<dyn FileProvider>) -> &mut Assembler;
     fn add_include_dirs(&mut self, dirs: Vec<String>) -> &mut Assembler;
     fn add_defines(&mut self, defines: Vec<String>) -> &mut Assembler;
+    fn add_sections(&mut self, sections: Vec<String>) -> &mut Assembler;
     fn export_labels(&mut self, file_name: &str) -> &mut Assembler;
+    fn symbol_format(&mut self, format: SymbolFormat) -> &mut Assembler;
+    fn export_all(&mut self, all: bool) -> &mut Assembler;
     fn origin(&mut self, address: u16) -> &mut Assembler;
     fn max_code_size(&mut self, size: usize) -> &mut Assembler;
     fn case_insensitive(&mut self, ci: bool) -> &mut Assembler;
-    fn display_unused(&mut self);
+    fn warn_unused(&mut self, enabled: bool) -> &mut Assembler;
 }
 
 impl AssemblerOptions for Assembler {
@@ -37,6 +52,51 @@ impl AssemblerOptions for Assembler {
         self
     }
 
+    fn enable_jr_promote(&mut self, enabled: bool) -> &mut Assembler {
+        self.jr_promote = enabled;
+        self
+    }
+
+    fn enable_listing(&mut self, enabled: bool) -> &mut Assembler {
+        self.listing.enable(enabled);
+        self
+    }
+
+    fn listing_file(&mut self, file_name: &str) -> &mut Assembler {
+        self.listing.file_name(file_name);
+        self
+    }
+
+    fn sld_file(&mut self, file_name: &str) -> &mut Assembler {
+        self.sld.file_name(file_name);
+        self
+    }
+
+    fn timings_file(&mut self, file_name: &str) -> &mut Assembler {
+        self.timings_file = file_name.to_string();
+        self
+    }
+
+    fn max_errors(&mut self, count: usize) -> &mut Assembler {
+        self.max_errors = count;
+        self
+    }
+
+    fn max_macro_depth(&mut self, depth: usize) -> &mut Assembler {
+        self.max_macro_depth = if depth > 0 { depth } else { MAX_MACRO_DEPTH };
+        self
+    }
+
+    fn enable_tests(&mut self, enabled: bool) -> &mut Assembler {
+        self.testing = enabled;
+        self
+    }
+
+    fn file_provider(&mut self, files: Box<dyn FileProvider>) -> &mut Assembler {
+        self.files = files;
+        self
+    }
+
     fn add_include_dirs(&mut self, dirs: Vec<String>) -> &mut Assembler {
         self.include_dirs = dirs.clone();
         self
@@ -47,11 +107,27 @@ impl AssemblerOptions for Assembler {
         self
     }
 
+    // Section placements as name=address[,page]
+    fn add_sections(&mut self, sections: Vec<String>) -> &mut Assembler {
+        self.section_options = sections;
+        self
+    }
+
     fn export_labels(&mut self, file_name: &str) -> &mut Assembler {
         self.labels_file = file_name.to_string();
         self
     }
 
+    fn symbol_format(&mut self, format: SymbolFormat) -> &mut Assembler {
+        self.symbol_format = format;
+        self
+    }
+
+    fn export_all(&mut self, all: bool) -> &mut Assembler {
+        self.export_all = all;
+        self
+    }
+
     fn origin(&mut self, address: u16) -> &mut Assembler {
         self.origin = address as isize;
         self.context.pc(self.origin);
@@ -72,11 +148,8 @@ impl AssemblerOptions for Assembler {
         self
     }
 
-    fn display_unused(&mut self) {
-        for (name, (used, line, file)) in self.context.used.clone() {
-            if !used {
-                dark_magenta_ln!("[{}:{}] Warning: unused label {} " ,file,line,name);
-            }
-        }
+    fn warn_unused(&mut self, enabled: bool) -> &mut Assembler {
+        self.warn_unused = enabled;
+        self
     }
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use std::ops::{Index, IndexMut};
 
+use crate::assembler::device::{Device, DeviceType};
 use crate::assembler::error_type::ErrorType;
 use crate::assembler::tokens::{RegPair, Token};
 use crate::assembler::tokens::Token::{IndexIndirect, RegisterIX, RegisterIY, RegisterPair};
@@ -7,6 +8,10 @@ use crate::assembler::tokens::Token::{IndexIndirect, RegisterIX, RegisterIY, Reg
 pub struct Bank {
     bytes: Vec<u8>,
     max_size: usize,
+    segments: Vec<(isize, usize)>,
+    device: Device,
+    reserving: bool,
+    reserved: usize,
 }
 
 impl Bank {
@@ -14,6 +19,10 @@ impl Bank {
         Bank {
             bytes: vec![],
             max_size: 65536,
+            segments: vec![],
+            device: Device::new(DeviceType::None),
+            reserving: false,
+            reserved: 0,
         }
     }
 
@@ -21,11 +30,112 @@ impl Bank {
         self.max_size = size;
     }
 
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.segments.clear();
+        self.device = Device::new(DeviceType::None);
+        self.reserving = false;
+        self.reserved = 0;
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    pub fn device_mut(&mut self) -> &mut Device {
+        &mut self.device
+    }
+
+    pub fn set_device(&mut self, kind: DeviceType) {
+        self.device = Device::new(kind);
+    }
+
+    pub fn address(&self) -> isize {
+        let reserved = self.reserved as isize;
+        match self.segments.last() {
+            Some((address, offset)) => address + (self.bytes.len() - offset) as isize + reserved,
+            None => self.bytes.len() as isize + reserved
+        }
+    }
+
+    // While reserving the address moves on but nothing is stored, used for BSS sections
+    pub fn reserve(&mut self, reserving: bool) {
+        self.reserving = reserving;
+    }
+
+    // Bytes are stored in the order they are assembled, each ORG starts a new segment so the
+    // Z80 address of every byte is known
+    pub fn origin(&mut self, address: isize) {
+        if let Some((_, offset)) = self.segments.last() {
+            if *offset == self.bytes.len() {
+                self.segments.pop();
+            }
+        }
+        self.segments.push((address, self.bytes.len()));
+        self.reserved = 0;
+    }
+
+    fn for_each_byte<F>(&self, mut f: F) where F: FnMut(isize, u8) {
+        for (i, (address, offset)) in self.segments.iter().enumerate() {
+            let end = self.segments.get(i + 1).map(|(_, o)| *o).unwrap_or(self.bytes.len());
+            for (n, b) in self.bytes[*offset..end].iter().enumerate() {
+                f(address + n as isize, *b);
+            }
+        }
+    }
+
+    pub fn first_address(&self) -> Option<isize> {
+        let mut first = None;
+        self.for_each_byte(|address, _| if first.is_none() { first = Some(address) });
+        first
+    }
+
+    pub fn address_range(&self) -> Option<(isize, isize)> {
+        let mut range: Option<(isize, isize)> = None;
+        self.for_each_byte(|address, _| range = match range {
+            Some((first, last)) => Some((first.min(address), last.max(address))),
+            None => Some((address, address))
+        });
+        range
+    }
+
+    pub fn used(&self, address: isize, size: usize) -> bool {
+        let mut used = false;
+        self.for_each_byte(|a, _| used |= a >= address && a < address + size as isize);
+        used
+    }
+
+    // A memory image of an address range, where code overlaps the last bytes written win
+    pub fn read(&self, address: isize, size: usize) -> Vec<u8> {
+        let mut image = vec![0u8; size];
+        self.overlay(address, &mut image);
+        image
+    }
+
+    pub fn overlay(&self, address: isize, image: &mut [u8]) {
+        let size = image.len() as isize;
+        self.for_each_byte(|a, b| if a >= address && a < address + size {
+            image[(a - address) as usize] = b;
+        });
+    }
+
     pub fn as_slice(&mut self) -> &[u8] {
         self.bytes.as_slice()
     }
 
     pub fn append(&mut self, bytes: &mut Vec<u8>) -> Result<(), ErrorType> {
+        if self.reserving {
+            self.reserved += bytes.len();
+            return Ok(());
+        }
+        let address = self.address();
+        for (i, b) in bytes.iter().enumerate() {
+            self.device.write(address + i as isize, *b);
+        }
         self.bytes.append(bytes);
         if self.bytes.len() > self.max_size {
             return Err(ErrorType::CodeSize);
@@ -34,6 +144,12 @@ impl Bank {
     }
 
     pub fn push(&mut self, b: u8) -> Result<(), ErrorType> {
+        if self.reserving {
+            self.reserved += 1;
+            return Ok(());
+        }
+        let address = self.address();
+        self.device.write(address, b);
         self.bytes.push(b);
         if self.bytes.len() > self.max_size {
             return Err(ErrorType::CodeSize);
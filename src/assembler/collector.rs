@@ -1,8 +1,6 @@
 use crate::assembler::Assembler;
 use crate::assembler::error::Error;
-use crate::assembler::get_token::GetToken;
 use crate::assembler::tokens::Token;
-use crate::assembler::tokens::Token::Number;
 
 pub trait Collector {
     fn optional_parameter(&mut self, preceded_by: Option<&Token>) -> Result<Option<isize>, Error>;
@@ -17,10 +15,9 @@ impl Collector for Assembler {
             }
             self.tokens.pop();
         }
-        if let Ok(Number(n)) = self.take_token() {
-            Ok(Some(n))
-        } else {
-            Ok(None)
+        match self.expr.parse(&mut self.context, &mut self.tokens) {
+            Ok(n) => Ok(n),
+            Err(e) => Err(self.context.error(e))
         }
     }
 }
\ No newline at end of file
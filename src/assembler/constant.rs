@@ -6,34 +6,40 @@ pub trait Constant {
     fn is_constant_defined(&self, name: &str) -> bool;
     fn get_constant(&mut self, name: &str) -> Option<isize>;
     fn add_constant(&mut self, name: String, value: isize) -> Result<(), Error>;
+    fn set_variable(&mut self, name: &str, value: isize);
+    fn remove_constant(&mut self, name: &str);
 }
 
 impl Constant for AssemblerContext {
     fn is_constant_defined(&self, name: &str) -> bool {
-        if self.case_insensitive {
-            self.constants.contains_key(&name.to_uppercase())
-        } else {
-            self.constants.contains_key(name)
-        }
+        self.constants.contains_key(&self.resolve(name))
     }
 
     fn get_constant(&mut self, name: &str) -> Option<isize> {
-        if self.case_insensitive {
-            self.constants.get(&name.to_uppercase()).cloned()
-        } else {
-            self.constants.get(name).cloned()
-        }
+        self.constants.get(&self.resolve(name)).cloned()
     }
 
     fn add_constant(&mut self, name: String, value: isize) -> Result<(), Error> {
-        if self.is_constant_defined(name.as_str()) {
+        let name = self.scoped_name(&name);
+        let name = if self.case_insensitive { name.to_uppercase() } else { name };
+        if self.constants.contains_key(&name) {
             return Err(self.error(ErrorType::LabelOrConstantExists));
         }
-        if self.case_insensitive {
-            self.constants.insert(name.to_uppercase(), value);
-        } else {
-            self.constants.insert(name, value);
-        }
+        self.constant_source.insert(name.clone(), (self.current_line_number(), self.current_file_name()));
+        self.constants.insert(name, value);
         Ok(())
     }
+
+    // A constant that can change value, such as a REPT counter
+    fn set_variable(&mut self, name: &str, value: isize) {
+        let name = if self.case_insensitive { name.to_uppercase() } else { name.to_string() };
+        self.constant_source.insert(name.clone(), (self.current_line_number(), self.current_file_name()));
+        self.constants.insert(name, value);
+    }
+
+    fn remove_constant(&mut self, name: &str) {
+        let name = if self.case_insensitive { name.to_uppercase() } else { name.to_string() };
+        self.constant_source.remove(&name);
+        self.constants.remove(&name);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,197 @@
+use std::str::FromStr;
+
+use crate::assembler::error_type::ErrorType;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DeviceType {
+    None,
+    Zx48,
+    Zx128,
+    Next,
+}
+
+impl FromStr for DeviceType {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(DeviceType::None),
+            "zxspectrum48" => Ok(DeviceType::Zx48),
+            "zxspectrum128" => Ok(DeviceType::Zx128),
+            "zxspectrumnext" => Ok(DeviceType::Next),
+            _ => Err(())
+        }
+    }
+}
+
+// A paged memory model, the 64K Z80 address space is split into equal sized slots each of which
+// has one of the device's pages mapped into it. Pages are only allocated once they are written to.
+pub struct Device {
+    kind: DeviceType,
+    page_size: usize,
+    pages: Vec<Option<Vec<u8>>>,
+    written: Vec<Option<Vec<bool>>>,
+    blank: Vec<u8>,
+    slots: Vec<usize>,
+    slot: usize,
+}
+
+impl Device {
+    pub fn new(kind: DeviceType) -> Device {
+        let (page_size, page_count, slots) = match kind {
+            DeviceType::None => (65536, 0, vec![]),
+            DeviceType::Zx48 => (16384, 4, vec![0, 1, 2, 3]),
+            DeviceType::Zx128 => (16384, 8, vec![7, 5, 2, 0]),
+            DeviceType::Next => (8192, 224, vec![14, 15, 10, 11, 4, 5, 0, 1]),
+        };
+        Device {
+            kind,
+            page_size,
+            pages: vec![None; page_count],
+            written: vec![None; page_count],
+            blank: vec![0u8; if page_count > 0 { page_size } else { 0 }],
+            slot: slots.len().saturating_sub(1),
+            slots,
+        }
+    }
+
+    pub fn kind(&self) -> DeviceType {
+        self.kind
+    }
+
+    pub fn is_paged(&self) -> bool {
+        self.kind != DeviceType::None
+    }
+
+    pub fn select_slot(&mut self, slot: isize) -> Result<(), ErrorType> {
+        if slot < 0 || slot as usize >= self.slots.len() {
+            return Err(ErrorType::SlotOutOfRange(slot));
+        }
+        self.slot = slot as usize;
+        Ok(())
+    }
+
+    pub fn map(&mut self, slot: Option<isize>, page: isize) -> Result<(), ErrorType> {
+        if !self.is_paged() {
+            return Err(ErrorType::NoDevice);
+        }
+        if let Some(s) = slot {
+            self.select_slot(s)?;
+        }
+        if page < 0 || page as usize >= self.pages.len() {
+            return Err(ErrorType::PageOutOfRange(page));
+        }
+        self.slots[self.slot] = page as usize;
+        Ok(())
+    }
+
+    pub fn write(&mut self, address: isize, b: u8) {
+        if !self.is_paged() || !(0..=0xFFFF).contains(&address) {
+            return;
+        }
+        let page_size = self.page_size;
+        let page = self.slots[address as usize / page_size];
+        let offset = address as usize % page_size;
+        self.pages[page].get_or_insert_with(|| vec![0u8; page_size])[offset] = b;
+        self.written[page].get_or_insert_with(|| vec![false; page_size])[offset] = true;
+    }
+
+    pub fn layout(&self) -> (usize, usize, usize) {
+        (self.page_size, self.pages.len(), self.slots.len())
+    }
+
+    // The page mapped in at an address
+    pub fn page_at(&self, address: isize) -> Option<usize> {
+        if !self.is_paged() || !(0..=0xFFFF).contains(&address) {
+            return None;
+        }
+        Some(self.slots[address as usize / self.page_size])
+    }
+
+    // The address counting every page in order, as the page mapped in at an address would place it
+    pub fn physical(&self, address: isize) -> Option<usize> {
+        self.page_at(address).map(|page| page * self.page_size + address as usize % self.page_size)
+    }
+
+    pub fn page(&self, page: usize) -> Option<&[u8]> {
+        self.pages.get(page).map(|p| p.as_deref().unwrap_or(&self.blank))
+    }
+
+    pub fn page_used(&self, page: usize) -> bool {
+        matches!(self.pages.get(page), Some(Some(_)))
+    }
+
+    // The 64K address space as the slots currently map it
+    pub fn view(&self) -> Vec<u8> {
+        self.slots.iter().flat_map(|&page| self.page(page).unwrap_or(&self.blank).iter().cloned()).collect()
+    }
+
+    // The lowest & highest address assembled to in the pages currently mapped
+    pub fn view_range(&self) -> Option<(usize, usize)> {
+        let written = |address: &usize| {
+            let page = self.slots[address / self.page_size];
+            matches!(&self.written[page], Some(w) if w[address % self.page_size])
+        };
+        let first = (0..self.slots.len() * self.page_size).find(written)?;
+        let last = (first..self.slots.len() * self.page_size).rev().find(written)?;
+        Some((first, last))
+    }
+
+    // Copies only the bytes that have been assembled into a page over an existing image
+    pub fn overlay_page(&self, page: usize, image: &mut [u8]) {
+        if let (Some(Some(data)), Some(Some(written))) = (self.pages.get(page), self.written.get(page)) {
+            for (i, b) in image.iter_mut().enumerate().take(data.len()) {
+                if written[i] {
+                    *b = data[i];
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::device::{Device, DeviceType};
+
+    #[test]
+    fn pages_are_allocated_when_written() {
+        let mut device = Device::new(DeviceType::Next);
+        assert!(device.pages.iter().all(|p| p.is_none()));
+        device.write(0xC001, 0xAA);
+        assert!(device.page_used(0));
+        assert!(!device.page_used(1));
+        assert_eq!(device.pages.iter().flatten().count(), 1);
+        assert_eq!(device.page(0).unwrap()[..2], [0x00, 0xAA]);
+        assert_eq!(device.page(1).unwrap().len(), 8192);
+        assert!(device.page(224).is_none());
+    }
+
+    #[test]
+    fn slots_and_pages() {
+        let mut device = Device::new(DeviceType::Zx128);
+        device.write(0xC000, 1);
+        assert!(device.map(None, 3).is_ok());
+        device.write(0xC000, 3);
+        assert!(device.map(Some(1), 6).is_ok());
+        device.write(0x4000, 6);
+        assert!(device.map(Some(4), 0).is_err());
+        assert!(device.map(None, 8).is_err());
+        let view = device.view();
+        assert_eq!(view.len(), 65536);
+        assert_eq!([view[0x4000], view[0xC000]], [6, 3]);
+        assert_eq!(device.view_range(), Some((0x4000, 0xC000)));
+        assert!(device.map(Some(3), 0).is_ok());
+        assert_eq!(device.view()[0xC000], 1);
+        assert_eq!(device.physical(0x4001), Some(6 * 16384 + 1));
+        assert_eq!(device.physical(0x10000), None);
+    }
+
+    #[test]
+    fn no_device() {
+        let mut device = Device::new(DeviceType::None);
+        device.write(0, 1);
+        assert!(device.map(None, 0).is_err());
+        assert!(device.view().is_empty());
+        assert_eq!(device.view_range(), None);
+        assert_eq!(device.physical(0), None);
+    }
+}
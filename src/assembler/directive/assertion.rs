@@ -0,0 +1,110 @@
+use crate::assembler::Assembler;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::tokens::Del::Comma;
+use crate::assembler::tokens::Token::{Delimiter, StringLiteral};
+
+pub trait Assertions {
+    fn process_assert(&mut self) -> Result<(), Error>;
+    fn process_error(&mut self) -> Result<(), Error>;
+    fn process_warning(&mut self) -> Result<(), Error>;
+    fn user_message(&mut self) -> Result<String, Error>;
+}
+
+impl Assertions for Assembler {
+    // Only checked in the final pass when every label, including forward references, has its value
+    fn process_assert(&mut self) -> Result<(), Error> {
+        if !self.context.final_pass {
+            self.tokens.clear();
+            return Ok(());
+        }
+        let mut expr = self.expr.get_expression(&mut self.context, &mut self.tokens);
+        if expr.is_empty() {
+            return Err(self.context.error(ErrorType::BadExpression));
+        }
+        let text: Vec<String> = expr.iter().map(|t| t.to_string()).collect();
+        let value = self.expr.eval(&mut self.context, &mut expr);
+        let value = self.context.result(value)?;
+        let message = match self.tokens.last() {
+            Some(Delimiter(Comma)) => {
+                self.take_token()?;
+                self.user_message()?
+            }
+            _ => text.join(" ")
+        };
+        if value == 0 {
+            return Err(self.context.error(ErrorType::AssertionFailed(message)));
+        }
+        Ok(())
+    }
+
+    fn process_error(&mut self) -> Result<(), Error> {
+        let message = self.user_message()?;
+        if self.context.final_pass {
+            return Err(self.context.error(ErrorType::UserError(message)));
+        }
+        Ok(())
+    }
+
+    fn process_warning(&mut self) -> Result<(), Error> {
+        let message = self.user_message()?;
+        if self.context.final_pass {
+            self.warn(ErrorType::UserWarning(message));
+        }
+        Ok(())
+    }
+
+    fn user_message(&mut self) -> Result<String, Error> {
+        match self.take_token()? {
+            StringLiteral(s) => Ok(s),
+            _ => Err(self.context.error(ErrorType::StringExpected))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::error::ErrorLevel;
+    use crate::assembler::test_support::{assemble, errors};
+
+    #[test]
+    fn assertions() {
+        let source = " org 0x8000\n assert finish - start == 2\nstart nop\n nop\nfinish\n warning \"check\"\n";
+        let (assembler, result) = assemble("assert", source);
+        assert!(result.is_ok());
+        let warnings: Vec<(isize, &str)> = assembler.diagnostics().iter()
+            .filter(|d| d.level == ErrorLevel::Warning).map(|d| (d.line_no, d.message.as_str())).collect();
+        assert_eq!(warnings, [(6, "check")]);
+    }
+
+    #[test]
+    fn failed_assertions() {
+        let source = " assert 1 > 2\n assert 0, \"too big\"\n error \"stop\"\n";
+        let (assembler, result) = assemble("assert_fail", source);
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (1, "Assertion failed: 1 > 2".to_string()),
+            (2, "Assertion failed: too big".to_string()),
+            (3, "stop".to_string()),
+        ]);
+
+        let (assembler, result) = assemble("assert_macro", " macro chk\n assert 0\n endm\n chk\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler)[0].1, "Assertion failed: 0 in macro chk (".to_string() + &assembler.diagnostics()[0].file_name + ":2)");
+        assert_eq!(errors(&assembler)[0].0, 4);
+    }
+
+    #[test]
+    fn malformed_assertions() {
+        let (assembler, result) = assemble("assert_syntax", " assert\n assert 1,2\n error\n warning 5\n assert undefined_x\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (1, "Invalid number or expression".to_string()),
+            (2, "Expected a string".to_string()),
+            (3, "Unexpected end of line".to_string()),
+            (4, "Expected a string".to_string()),
+            (5, "Undefined label or constant 'undefined_x'".to_string()),
+        ]);
+    }
+}
@@ -4,38 +4,46 @@ use crate::assembler::error::Error;
 use crate::assembler::error_type::ErrorType;
 use crate::assembler::get_token::GetToken;
 use crate::assembler::IfBlock::{Else, If, SkipEnd};
-use crate::assembler::tokens::Op::Equals;
-use crate::assembler::tokens::Token::{ConstLabel, Operator};
+use crate::assembler::tokens::Token::ConstLabel;
 
 pub trait Conditional {
     fn process_if(&mut self) -> Result<(), Error>;
     fn process_if_def(&mut self, defined: bool) -> Result<(), Error>;
+    fn process_else_if(&mut self) -> Result<(), Error>;
     fn process_endif(&mut self) -> Result<(), Error>;
     fn process_else(&mut self) -> Result<(), Error>;
+    fn skipping(&self) -> bool;
+    fn condition(&mut self) -> Result<bool, Error>;
 }
 
 impl Conditional for Assembler {
+    fn skipping(&self) -> bool {
+        matches!(self.if_level.last(), Some(Else(false)) | Some(If(false)) | Some(SkipEnd))
+    }
+
+    fn condition(&mut self) -> Result<bool, Error> {
+        match self.expr.parse(&mut self.context, &mut self.tokens) {
+            Ok(Some(value)) => Ok(value != 0),
+            Ok(None) => Err(self.context.error(ErrorType::BadExpression)),
+            Err(e) => Err(self.context.error(e))
+        }
+    }
+
+    // The condition is not evaluated inside a skipped block, it may use symbols that are never defined.
+    // A condition in error skips the whole block so its ENDIF still matches
     fn process_if(&mut self) -> Result<(), Error> {
-        let label_value: isize;
-        let const_value: isize;
-        if let ConstLabel(l) = self.take_token()? {
-            label_value = match self.context.get_constant(&l) {
-                None => return Err(self.context.error(ErrorType::LabelNotFound)),
-                Some(n) => n,
-            };
+        if self.skipping() {
+            self.tokens.clear();
+            self.if_level.push(SkipEnd);
         } else {
-            return Err(self.context.error(ErrorType::BadConstant));
-        }
-        self.expect_token(Operator(Equals))?;
-        const_value = self.expect_word(-1)?;
-        let if_true = label_value == const_value;
-        match self.if_level.last() {
-            Some(Else(false)) |
-            Some(If(false)) |
-            Some(SkipEnd) => self.if_level.push(SkipEnd),
-            _ => self.if_level.push(IfBlock::If(if_true))
+            match self.condition() {
+                Ok(if_true) => self.if_level.push(If(if_true)),
+                Err(e) => {
+                    self.if_level.push(SkipEnd);
+                    return Err(e);
+                }
+            }
         }
-
         Ok(())
     }
 
@@ -45,20 +53,43 @@ impl Conditional for Assembler {
             if !defined {
                 exists = !exists;
             }
-            match self.if_level.last() {
-                Some(Else(false)) |
-                Some(If(false)) |
-                Some(SkipEnd) => self.if_level.push(SkipEnd),
-                _ => self.if_level.push(IfBlock::If(exists))
+            if self.skipping() {
+                self.if_level.push(SkipEnd)
+            } else {
+                self.if_level.push(IfBlock::If(exists))
             }
             Ok(())
         } else {
+            self.if_level.push(SkipEnd);
             Err(self.context.error(ErrorType::BadConstant))
         }
     }
 
+    // Only evaluated while no earlier branch has been taken, once one has the rest are skipped
+    fn process_else_if(&mut self) -> Result<(), Error> {
+        match self.if_level.pop() {
+            None => return Err(self.context.error(ErrorType::ElseIfWithoutIf)),
+            Some(If(false)) => match self.condition() {
+                Ok(if_true) => self.if_level.push(If(if_true)),
+                Err(e) => {
+                    self.if_level.push(SkipEnd);
+                    return Err(e);
+                }
+            },
+            Some(If(true)) | Some(SkipEnd) => {
+                self.tokens.clear();
+                self.if_level.push(SkipEnd);
+            }
+            Some(block) => {
+                self.if_level.push(block);
+                return Err(self.context.error(ErrorType::AfterElse("ELSEIF".to_string())));
+            }
+        }
+        Ok(())
+    }
+
     fn process_endif(&mut self) -> Result<(), Error> {
-        if self.if_level.len() == 0 {
+        if self.if_level.is_empty() {
             Err(self.context.error(ErrorType::EndIfWithoutIf))
         } else {
             self.if_level.pop();
@@ -67,13 +98,69 @@ impl Conditional for Assembler {
     }
 
     fn process_else(&mut self) -> Result<(), Error> {
-        if self.if_level.len() == 0 {
-            Err(self.context.error(ErrorType::ElseWithoutIf))
-        } else {
-            if let Some(If(t)) = self.if_level.pop() {
+        match self.if_level.pop() {
+            None => Err(self.context.error(ErrorType::ElseWithoutIf)),
+            Some(If(t)) => {
                 self.if_level.push(Else(!t));
+                Ok(())
+            }
+            Some(SkipEnd) => {
+                self.if_level.push(SkipEnd);
+                Ok(())
+            }
+            Some(block) => {
+                self.if_level.push(block);
+                Err(self.context.error(ErrorType::AfterElse("ELSE".to_string())))
             }
-            Ok(())
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::test_support::{assemble, errors};
+
+    #[test]
+    fn conditional_chains() {
+        let source = "MODE = 2\n if MODE == 1\n db 1\n elseif MODE == 2 && !defined(NONE)\n db 2\n elif MODE == 2\n db 3\n \
+                      else\n db 4\n endif\n if defined(MODE) * 2 == 2\n db 5\n elseif UNDEFINED\n endif\n \
+                      if 0\n if UNDEFINED\n db 6\n endif\n endif\n";
+        let (mut assembler, result) = assemble("if", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[2, 5]);
+    }
+
+    #[test]
+    fn misplaced_branches() {
+        let source = " if 1\n else\n elseif 1\n else\n endif\n elseif 1\n else\n endif\n";
+        let (assembler, result) = assemble("else_if", source);
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (3, "ELSEIF after ELSE".to_string()),
+            (4, "ELSE after ELSE".to_string()),
+            (6, "ELSEIF without IF".to_string()),
+            (7, "ELSE without IF".to_string()),
+            (8, "ENDIF without IF".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn condition_in_error_skips_the_block() {
+        let source = " if\n db 1\n endif\n if 0\n elseif 1 +\n db 2\n else\n db 3\n endif\n ifdef 3\n db 4\n endif\n db 5\n";
+        let (mut assembler, result) = assemble("if_error", source);
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (1, "Invalid number or expression".to_string()),
+            (5, "Missing operand for '+'".to_string()),
+            (10, "Bad constant definition".to_string()),
+        ]);
+        assert_eq!(assembler.bank.as_slice(), &[5]);
+    }
+
+    #[test]
+    fn if_without_endif() {
+        let (assembler, result) = assemble("if_unclosed", " nop\n if 1\n nop\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [(3, "IF without ENDIF".to_string())]);
+    }
+}
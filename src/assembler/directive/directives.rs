@@ -1,15 +1,24 @@
-use std::fs::File;
-use std::io::Read;
 use std::path::Path;
 
 use ascii::AsAsciiStr;
 
 use crate::assembler::Assembler;
 use crate::assembler::assembler_options::AssemblerOptions;
+use crate::assembler::directive::assertion::Assertions;
 use crate::assembler::directive::conditional::Conditional;
 use crate::assembler::directive::enumerator::Enumerator;
+use crate::assembler::directive::module::Module;
+use crate::assembler::directive::paging::Paging;
+use crate::assembler::directive::phase::Phase;
+use crate::assembler::directive::repeat::Repeat;
+use crate::assembler::directive::savenex::SaveNex;
+use crate::assembler::directive::savesna::SaveSnapshot;
+use crate::assembler::directive::savetap::SaveTap;
+use crate::assembler::directive::section::Sections;
+use crate::assembler::directive::unittest::UnitTests;
 use crate::assembler::directive::sizeof::SizeOfHandler;
 use crate::assembler::directive::structure::Structure;
+use crate::assembler::directive::timing::Timing;
 use crate::assembler::emitter::Emitter;
 use crate::assembler::error::Error;
 use crate::assembler::error_type::ErrorType;
@@ -25,6 +34,7 @@ pub trait Directives {
     fn handle_bytes(&mut self, terminator: Option<u8>) -> Result<(), Error>;
     fn handle_words(&mut self) -> Result<(), Error>;
     fn handle_block(&mut self) -> Result<(), Error>;
+    fn handle_align(&mut self) -> Result<(), Error>;
     fn handle_hex(&mut self) -> Result<(), Error>;
     fn handle_define(&mut self) -> Result<(), Error>;
     fn set_option(&mut self) -> Result<(), Error>;
@@ -38,10 +48,13 @@ pub trait Directives {
 
 impl Directives for Assembler {
     fn set_origin(&mut self) -> Result<(), Error> {
-        match self.expr.parse(&mut self.context, &mut self.tokens, 0, -1, false) {
+        if self.phased {
+            return Err(self.context.error(ErrorType::OrgInPhase));
+        }
+        match self.expr.parse(&mut self.context, &mut self.tokens) {
             Ok(Some(mut o)) => {
                 if o > 65535 {
-                    o = o & 0xFFFF;
+                    o &= 0xFFFF;
                     self.warn(ErrorType::AddressTruncated);
                 }
                 self.origin = o
@@ -50,6 +63,7 @@ impl Directives for Assembler {
             Err(e) => return Err(self.context.error(e))
         }
         self.context.pc(self.origin);
+        self.bank.origin(self.origin);
         Ok(())
     }
 
@@ -60,8 +74,8 @@ impl Directives for Assembler {
             Err(e) => return Err(self.context.error_text(ErrorType::NonAscii, &e.to_string()))
         };
         self.emit(ascii_string.as_bytes())?;
-        if terminator.is_some() {
-            self.emit_byte(terminator.unwrap())?;
+        if let Some(t) = terminator {
+            self.emit_byte(t)?;
         }
         Ok(())
     }
@@ -79,7 +93,7 @@ impl Directives for Assembler {
                     continue;
                 }
                 self.tokens.push(t);
-                match self.expr.parse(&mut self.context, &mut self.tokens, 0, 1, false) {
+                match self.expr.parse(&mut self.context, &mut self.tokens) {
                     Ok(Some(n)) => {
                         if !(0..256).contains(&n) {
                             self.warn(ErrorType::IntegerOutOfRange);
@@ -107,7 +121,7 @@ impl Directives for Assembler {
             if expect_comma {
                 self.expect_token(Delimiter(Comma))?
             } else {
-                match self.expr.parse(&mut self.context, &mut self.tokens, 0, 2, false) {
+                match self.expr.parse(&mut self.context, &mut self.tokens) {
                     Ok(Some(n)) => {
                         if !(0..65536).contains(&n) {
                             self.warn(ErrorType::IntegerOutOfRange);
@@ -115,10 +129,7 @@ impl Directives for Assembler {
                         self.emit_word(n)?
                     }
                     Ok(None) => return Err(self.context.error(ErrorType::SyntaxError)),
-                    Err(e) => {
-                        println!("Err({:?})", e.to_string());
-                        return Err(self.context.error(e));
-                    }
+                    Err(e) => return Err(self.context.error(e))
                 }
             }
             expect_comma = !expect_comma;
@@ -127,24 +138,54 @@ impl Directives for Assembler {
     }
 
     fn handle_block(&mut self) -> Result<(), Error> {
-        let size = self.expect_word(-1)? as usize;
+        let mut size = self.expect_word()?;
         let mut fill = 0u8;
         if self.next_token_is(&Delimiter(Comma)) {
             self.tokens.pop();
-            fill = self.expect_byte(-1)? as u8;
+            fill = self.expect_byte()? as u8;
+        }
+        if size < 0 {
+            // may be a forward reference that has not settled yet
+            if self.context.final_pass {
+                return Err(self.context.error(ErrorType::IntegerOutOfRange));
+            }
+            size = 0;
+        }
+        self.emit(vec![fill; size as usize].as_slice())?;
+        Ok(())
+    }
+
+    // Pads from the current $ so it also lines up phased code, the padding is emitted like DS
+    fn handle_align(&mut self) -> Result<(), Error> {
+        let boundary = self.expect_word()?;
+        let mut fill = 0u8;
+        if self.next_token_is(&Delimiter(Comma)) {
+            self.tokens.pop();
+            fill = self.expect_byte()? as u8;
+        }
+        if !(1..=65536).contains(&boundary) {
+            if self.context.final_pass {
+                return Err(self.context.error(ErrorType::BadAlignment));
+            }
+            return Ok(());
+        }
+        let pc = self.context.offset_pc(0);
+        let padding = (boundary - pc.rem_euclid(boundary)) % boundary;
+        if padding > 0 {
+            self.warn(ErrorType::AlignPadding(boundary, padding));
+            self.emit(vec![fill; padding as usize].as_slice())?;
         }
-        self.emit(vec![fill; size].as_slice())?;
         Ok(())
     }
 
     fn handle_hex(&mut self) -> Result<(), Error> {
         if let StringLiteral(s) = self.take_token()? {
-            let mut hex = s.as_str().as_bytes().to_vec();
+            let mut hex = s.as_bytes().to_vec();
             let mut bytes = vec![];
-            while hex.len() > 0 {
+            while !hex.is_empty() {
                 let lo = hex.pop().unwrap() as char;
                 let mut hi = '0';
-                if hex.len() > 0 {
+                if !hex.is_empty() {
                     hi = hex.pop().unwrap() as char;
                 }
                 let x = format!("{}{}", hi, lo);
@@ -180,6 +221,8 @@ impl Directives for Assembler {
             (Opt(OptionType::CSpect), Token::Boolean(b)) => self.enable_cspect(b),
             (Opt(OptionType::Z80n), Token::Boolean(b)) => self.enable_z80n(b),
             (Opt(OptionType::MaxCodeSize), Token::Number(n)) => self.max_code_size(n as usize),
+            (Opt(OptionType::JrPromote), Token::Boolean(b)) => self.enable_jr_promote(b),
+            (Opt(OptionType::Listing), Token::Boolean(b)) => self.enable_listing(b),
             (_, _) => return Err(self.context.error(ErrorType::InvalidOption))
         };
         Ok(())
@@ -198,11 +241,12 @@ impl Directives for Assembler {
         dirs.reverse();
         while let Some(s) = dirs.pop() {
             let path = Path::new(&s).join(file_name);
-            if path.exists() {
-                return Ok(path.to_str().unwrap_or("").to_string());
+            let path = path.to_str().unwrap_or("").to_string();
+            if self.files.exists(&path) {
+                return Ok(path);
             }
         }
-        Err(self.context.error_text(ErrorType::FileNotFound, &file_name))
+        Err(self.context.error_text(ErrorType::FileNotFound, file_name))
     }
 
     fn include_source_file(&mut self) -> Result<(), Error> {
@@ -216,12 +260,14 @@ impl Directives for Assembler {
             return Err(self.context.error(ErrorType::MultipleIncludes));
         }
         self.info(&format!("Including file from {}", file_path));
-        self.first_pass(&file_path)
+        self.assemble_file(&file_path)
     }
 
     fn write_message(&mut self) -> Result<(), Error> {
         if let StringLiteral(s) = self.take_token()? {
-            dark_yellow_ln!("{}", s);
+            if self.context.final_pass {
+                self.messages.push(s);
+            }
         }
         Ok(())
     }
@@ -234,9 +280,8 @@ impl Directives for Assembler {
         };
         let file_path = self.locate_file(&file_name)?;
         self.info(format!("Including binary file from {}", file_path).as_str());
-        let mut b: Vec<u8> = vec![];
-        let mut f = File::open(&file_path)?;
-        let r = f.read_to_end(b.as_mut())? as isize;
+        let mut b = self.files.read(&file_path)?;
+        let r = b.len() as isize;
         self.context.result(self.bank.append(&mut b))?;
         self.context.add_size_of(r);
         let pc = self.context.offset_pc(r);
@@ -258,13 +303,23 @@ impl Directives for Assembler {
             Directive::Opt => self.set_option(),
             Directive::Binary => self.include_binary(),
             Directive::Block => self.handle_block(),
+            Directive::Align => self.handle_align(),
+            Directive::Phase => self.process_phase(),
+            Directive::Dephase => self.process_dephase(),
+            Directive::Section => self.process_section(),
+            Directive::Memory => self.process_memory(),
+            Directive::EndMemory => self.process_end_memory(),
+            Directive::Place => self.process_place(),
+            Directive::Module => self.process_module(),
+            Directive::EndModule => self.process_end_module(),
+            Directive::Local => Err(self.context.error(ErrorType::LocalOutsideMacro)),
             Directive::Macro => self.macros.begin_collect(&mut self.context, &mut self.tokens),
             Directive::StringZero => self.handle_bytes(Some(0)),
             Directive::End => {
                 if self.macros.collecting() {
                     self.macros.end_collect(&mut self.context)
                 } else {
-                    return Err(self.context.error(ErrorType::DanglingEnd));
+                    Err(self.context.error(ErrorType::DanglingEnd))
                 }
             }
             Directive::Hex => self.handle_hex(),
@@ -272,13 +327,60 @@ impl Directives for Assembler {
             Directive::IfDef => self.process_if_def(true),
             Directive::IfNotDef => self.process_if_def(false),
             Directive::Else => self.process_else(),
+            Directive::ElseIf => self.process_else_if(),
             Directive::EndIf => self.process_endif(),
             Directive::Global => self.process_global(),
             Directive::Define => self.handle_define(),
             Directive::Enum => self.begin_process_enum(),
             Directive::EndEnum => self.end_process_enum(),
             Directive::Struct => self.begin_process_struct(),
-            Directive::EndStruct => self.end_process_struct()
+            Directive::EndStruct => self.end_process_struct(),
+            Directive::SaveNex => self.process_savenex(),
+            Directive::Device => self.process_device(),
+            Directive::Slot => self.process_slot(),
+            Directive::Page => self.process_page(),
+            Directive::Mmu => self.process_mmu(),
+            Directive::SaveSna => self.process_savesna(),
+            Directive::SaveTap => self.process_savetap(),
+            Directive::UnitTest => self.process_unit_test(),
+            Directive::AssertReg => self.process_assert_reg(),
+            Directive::AssertMem => self.process_assert_mem(),
+            Directive::Timing => self.process_timing(),
+            Directive::Rept => self.process_rept(),
+            Directive::EndRept => self.process_endr(),
+            Directive::Assert => self.process_assert(),
+            Directive::UserError => self.process_error(),
+            Directive::UserWarning => self.process_warning()
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::assembler::label::Label;
+    use crate::assembler::test_support::{assemble, errors};
+
+    #[test]
+    fn align() {
+        let source = " org 0x8001\n db 1\n align 4,0xff\n align 4\n db 2\n align 8\nlabel\n";
+        let (mut assembler, result) = assemble("align", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[1, 0xFF, 0xFF, 2, 0, 0, 0]);
+        assert_eq!(assembler.context.get_label("label"), Some(0x8008));
+        let warnings: Vec<(isize, &str)> = assembler.diagnostics().iter().map(|d| (d.line_no, d.message.as_str())).collect();
+        assert_eq!(warnings, [(3, "ALIGN 4 wasted 2 byte(s)"), (6, "ALIGN 8 wasted 3 byte(s)")]);
+    }
+
+    #[test]
+    fn align_errors() {
+        let (mut assembler, result) = assemble("align_errors", " org 0x8001\n align 0\n align\n align 4,\n align 2,300\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (2, "Alignment must be between 1 and 65536".to_string()),
+            (3, "Syntax error".to_string()),
+            (4, "Syntax error".to_string()),
+        ]);
+        // An oversized fill is truncated with a warning
+        assert_eq!(assembler.bank.as_slice(), &[0x2C]);
+        assert_eq!(assembler.num_warnings(), 2);
+    }
+}
@@ -23,6 +23,7 @@ impl Enumerator for Assembler {
             if step == 0 {
                 return Err(self.context.error(ErrorType::EnumStepValue));
             }
+            self.context.enum_names.push(name.clone());
             self.collect_enum = Some((name, count, step));
         } else {
             return Err(self.context.error(ErrorType::EnumBadName));
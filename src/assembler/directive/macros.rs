@@ -4,37 +4,79 @@ use crate::assembler::assembler_context::AssemblerContext;
 use crate::assembler::error::Error;
 use crate::assembler::error_type::ErrorType;
 use crate::assembler::token_traits::Tokens;
-use crate::assembler::tokens::{Del, Token};
-use crate::assembler::tokens::Token::{ConstLabel, Delimiter, MacroParam};
+use crate::assembler::tokens::{Del, Directive, Token};
+use crate::assembler::tokens::Token::{ConstLabel, Delimiter, IndirectExpression, MacroParam};
+
+// A line of a macro body as written and where it was defined
+#[derive(Debug, Default, Clone)]
+pub struct MacroSource {
+    pub text: String,
+    pub file_name: String,
+    pub line_number: isize,
+}
 
 #[derive(Debug, Default)]
 pub struct Macro {
     params: Vec<String>,
     tokens: Vec<Vec<Token>>,
+    source: Vec<MacroSource>,
+}
+
+// A REPT block being collected, depth counts the nested REPT blocks collected with it
+#[derive(Debug, Default)]
+pub struct Repeat {
+    pub count: isize,
+    pub counter: Option<String>,
+    body: Macro,
+    depth: usize,
 }
 
+impl Repeat {
+    // The lines of the body in order, ready to translate
+    pub fn lines(&self) -> Vec<(Vec<Token>, MacroSource)> {
+        self.body.tokens.iter().zip(&self.body.source).map(|(line, source)| {
+            (line.iter().rev().cloned().collect(), source.clone())
+        }).collect()
+    }
+}
+
+// How deep macros can call other macros unless --max-macro-depth says otherwise
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+// One level of the expansion stack, line is the body line being assembled
 #[derive(Debug, Default)]
 pub struct MacroExpansion {
+    name: String,
     params: HashMap<String, Vec<Token>>,
     tokens: Vec<Vec<Token>>,
+    source: Vec<MacroSource>,
+    line: MacroSource,
 }
 
 pub struct MacroHandler {
     collecting: bool,
-    is_expanding: bool,
     collecting_name: String,
+    nested: usize,
     macros: HashMap<String, Macro>,
-    expanding: MacroExpansion,
+    expanding: Vec<MacroExpansion>,
+    max_depth: usize,
+    expansion_count: HashMap<String, usize>,
+    repeat: Option<Repeat>,
+    repeating: usize,
 }
 
 impl MacroHandler {
-    pub fn new() -> MacroHandler {
+    pub fn new(max_depth: usize) -> MacroHandler {
         MacroHandler {
             collecting: false,
-            is_expanding: false,
             collecting_name: String::default(),
+            nested: 0,
             macros: HashMap::default(),
-            expanding: Default::default(),
+            expanding: vec![],
+            max_depth,
+            expansion_count: HashMap::default(),
+            repeat: None,
+            repeating: 0,
         }
     }
 
@@ -42,20 +84,29 @@ impl MacroHandler {
         self.collecting
     }
 
-    pub fn expanding(&self) -> bool { self.is_expanding }
+    pub fn expanding(&self) -> bool { !self.expanding.is_empty() || self.repeating > 0 }
+
+    // Whether a line belongs to the MACRO or REPT body being collected rather than ending it
+    pub fn collects(&self, tokens: &[Token]) -> bool {
+        match &self.repeat {
+            Some(r) => r.depth > 0 || !tokens.contains(&Token::Directive(Directive::EndRept)),
+            None => self.collecting && (self.nested > 0 || tokens.first() != Some(&Token::Directive(Directive::End)))
+        }
+    }
 
     pub fn macro_defined(&self, name: &str) -> bool {
         self.macros.contains_key(name)
     }
 
-    fn parse_params(&mut self, context: &mut AssemblerContext, name: &str, tokens: &mut Vec<Token>) -> Result<(), Error> {
+    fn parse_params(&self, context: &mut AssemblerContext, name: &str, tokens: &mut Vec<Token>) -> Result<HashMap<String, Vec<Token>>, Error> {
         let mac = &self.macros[name];
+        let mut params = HashMap::new();
         let mut param_count = 0;
-        let mut param_name = if mac.params.len() > 0 { &mac.params[param_count] } else { "" };
+        let mut param_name = if !mac.params.is_empty() { &mac.params[param_count] } else { "" };
         let mut expr = vec![];
         while let Some(t) = tokens.pop() {
             if t == Delimiter(Del::Comma) {
-                self.expanding.params.insert(param_name.to_string(), expr.clone());
+                params.insert(param_name.to_string(), expr.clone());
                 expr.clear();
                 param_count += 1;
                 param_name = &mac.params[param_count];
@@ -66,64 +117,161 @@ impl MacroHandler {
             }
         }
         param_count += 1;
-        if mac.params.len() > 0 && param_count != mac.params.len() {
+        if !mac.params.is_empty() && param_count != mac.params.len() {
             return Err(context.error(ErrorType::MacroParamCount));
         }
-        self.expanding.params.insert(param_name.to_string(), expr.clone());
-        Ok(())
+        params.insert(param_name.to_string(), expr.clone());
+        Ok(params)
     }
 
-    fn unique_label(name: &str) -> String {
-        format!("{}_{:x}", name, rand::random::<u64>()).to_string()
+    // Numbered by how many times the macro has been expanded so the names are the same every build,
+    // .loop in the third expansion of border is border.3.loop
+    pub fn unique_label(&mut self, name: &str) -> String {
+        let count = self.expansion_count.entry(name.to_string()).or_insert(0);
+        *count += 1;
+        format!("{}.{}", name, count)
+    }
+
+    // Which lines of a macro body define another macro, they are left for that macro to expand
+    fn nested_lines(lines: &[Vec<Token>]) -> Vec<bool> {
+        let mut depth = 0;
+        lines.iter().map(|line| {
+            if depth > 0 && line.last() == Some(&Token::Directive(Directive::End)) {
+                depth -= 1;
+                return true;
+            }
+            if line.contains(&Token::Directive(Directive::Macro)) {
+                depth += 1;
+            }
+            depth > 0
+        }).collect()
+    }
+
+    // The names given by LOCAL lines in a macro body
+    fn local_names(lines: &[Vec<Token>], nested: &[bool]) -> Vec<String> {
+        lines.iter().zip(nested)
+            .filter(|(line, nested)| !**nested && line.last() == Some(&Token::Directive(Directive::Local)))
+            .flat_map(|(line, _)| line.iter().filter_map(|t| match t {
+                ConstLabel(l) => Some(l.clone()),
+                _ => None
+            }))
+            .collect()
+    }
+
+    // The names of the macros a body defines, which it may call before they exist
+    fn defined_names(lines: &[Vec<Token>]) -> Vec<String> {
+        lines.iter().filter_map(|line| {
+            let at = line.iter().rposition(|t| *t == Token::Directive(Directive::Macro))?;
+            match line.get(at.checked_sub(1)?) {
+                Some(ConstLabel(l)) => Some(l.clone()),
+                _ => None
+            }
+        }).collect()
+    }
+
+    // LOCAL names become local labels of the expansion wherever they are used
+    fn localise(token: Token, locals: &[String]) -> Token {
+        match token {
+            ConstLabel(l) if locals.contains(&l.trim_end_matches(':').to_string()) => ConstLabel(format!(".{}", l)),
+            IndirectExpression(inner) => IndirectExpression(inner.into_iter().map(|t| Self::localise(t, locals)).collect()),
+            t => t
+        }
     }
 
     pub fn begin_expand(&mut self, context: &mut AssemblerContext, name: &str, tokens: &mut Vec<Token>) -> Result<(), Error> {
-        self.expanding = Default::default();
-        self.parse_params(context, name, tokens)?;
+        if self.expanding.len() >= self.max_depth {
+            return Err(context.error(ErrorType::MacroTooDeep(self.max_depth)));
+        }
+        let mut expansion = MacroExpansion { name: name.to_string(), ..Default::default() };
+        expansion.params = self.parse_params(context, name, tokens)?;
         let mut uses_label = false;
         let mac = &self.macros[name];
         let mut sub = mac.tokens.clone();
+        let mut sources = mac.source.clone();
+        let mut nested = Self::nested_lines(&mac.tokens);
+        let locals = Self::local_names(&mac.tokens, &nested);
+        let defined = Self::defined_names(&mac.tokens);
         while let Some(mut line) = sub.pop() {
+            let source = sources.pop().unwrap_or_default();
+            let in_definition = nested.pop().unwrap_or(false);
+            if !in_definition && line.last() == Some(&Token::Directive(Directive::Local)) {
+                uses_label = true;
+                continue;
+            }
             let mut first_token = true;
             let mut new_line: Vec<Token> = vec![];
-            while let Some(tok) = line.pop() {
+            while let Some(mut tok) = line.pop() {
+                if !in_definition {
+                    tok = Self::localise(tok, &locals);
+                }
                 match (first_token, &tok) {
+                    (true, ConstLabel(_)) if in_definition => new_line.push(tok),
                     (true, ConstLabel(label)) =>
                         if label.starts_with(".") {
                             uses_label = true;
                             new_line.push(tok);
+                        } else if AssemblerContext::temp_label(label).is_some() || self.macros.contains_key(label) || defined.contains(label) {
+                            new_line.push(tok);
                         } else {
                             return Err(context.error(ErrorType::MacroLabel));
                         }
-                    (false, MacroParam(name)) => new_line.append(&mut self.expanding.params[&*name].clone()),
+                    (false, MacroParam(name)) => new_line.append(&mut expansion.params[name].clone()),
                     _ => new_line.push(tok)
                 }
                 first_token = false;
             }
-            self.expanding.tokens.push(new_line);
+            expansion.tokens.push(new_line);
+            expansion.source.push(source);
         }
+        // Lines are expanded from the end, the label comes first so local labels belong to it
         if uses_label {
-            self.expanding.tokens.insert(0, vec![ConstLabel(Self::unique_label(name).to_string())])
+            let label = self.unique_label(name);
+            expansion.tokens.push(vec![ConstLabel(label)]);
+            expansion.source.push(MacroSource::default());
         }
-        self.is_expanding = true;
+        self.expanding.push(expansion);
         Ok(())
     }
 
-    pub fn expand(&mut self) -> Option<Vec<Token>> {
-        let token = self.expanding.tokens.pop();
-        if token.is_none() {
-            self.is_expanding = false;
+    // The next line of the innermost expansion, which is finished when there are none left
+    pub fn expand(&mut self) -> Option<(Vec<Token>, MacroSource)> {
+        let expansion = self.expanding.last_mut()?;
+        match expansion.tokens.pop() {
+            Some(line) => {
+                expansion.line = expansion.source.pop().unwrap_or_default();
+                Some((line, expansion.line.clone()))
+            }
+            None => {
+                self.expanding.pop();
+                None
+            }
+        }
+    }
+
+    // Where each expansion has got to, innermost first, repeated levels of a recursive macro are counted
+    pub fn trace(&self) -> String {
+        let mut levels: Vec<(String, usize)> = vec![];
+        for expansion in self.expanding.iter().rev() {
+            let level = format!("{} ({}:{})", expansion.name, expansion.line.file_name, expansion.line.line_number);
+            match levels.last_mut() {
+                Some((last, count)) if *last == level => *count += 1,
+                _ => levels.push((level, 1))
+            }
         }
-        token
+        let levels: Vec<String> = levels.iter().map(|(level, count)| match count {
+            1 => level.clone(),
+            _ => format!("{} x{}", level, count)
+        }).collect();
+        levels.join(" called from ")
     }
 
     pub fn add_define(&mut self, name: &str, tokens: &mut Vec<Token>) -> Result<(), Error> {
-        let mut mtokens = vec![];
-        mtokens.push(tokens.clone());
+        let mtokens = vec![tokens.clone()];
         tokens.clear();
         let m = Macro {
             params: vec![],
             tokens: mtokens,
+            source: vec![],
         };
         self.macros.insert(name.to_string(), m);
         Ok(())
@@ -133,13 +281,16 @@ impl MacroHandler {
         if self.collecting {
             return Err(context.error(ErrorType::NestedMacro));
         }
+        // A definition in error is still collected up to its END so the body is not assembled,
+        // nothing is kept while collecting_name is empty
+        self.collecting = true;
+        self.collecting_name.clear();
         let name = match tokens.pop() {
             Some(Token::ConstLabel(l)) => if self.macros.contains_key(&l) {
                 return Err(context.error(ErrorType::BadMacroName));
             } else { l },
             _ => return Err(context.error(ErrorType::BadMacroName)) // invalid macro name
         };
-        self.collecting_name = name;
         let mut params = vec![];
         let mut expect_comma = false;
         while let Some(t) = tokens.pop() {
@@ -151,17 +302,42 @@ impl MacroHandler {
             }
             expect_comma = !expect_comma;
         }
-        self.collecting = true;
-        self.macros.insert(self.collecting_name.clone(), Macro { params, tokens: vec![] });
+        self.collecting_name = name;
+        self.macros.insert(self.collecting_name.clone(), Macro { params, tokens: vec![], source: vec![] });
         Ok(())
     }
 
-    pub fn collect(&mut self, context: &mut AssemblerContext, tokens: &mut Vec<Token>) -> Result<(), Error> {
+    pub fn collect(&mut self, context: &mut AssemblerContext, tokens: &mut Vec<Token>, source: MacroSource) -> Result<(), Error> {
+        if !self.expanding() {
+            context.next_line();
+        }
+        if let Some(r) = &mut self.repeat {
+            if tokens.contains(&Token::Directive(Directive::Rept)) {
+                r.depth += 1;
+            } else if tokens.contains(&Token::Directive(Directive::EndRept)) {
+                r.depth -= 1;
+            }
+            r.body.tokens.push(tokens.drain(..).rev().collect());
+            r.body.source.push(source);
+            return Ok(());
+        }
         if !self.collecting {
             return Err(context.error(ErrorType::NestedMacro));
         }
+        // A macro defined inside this one is collected with it, up to its own END
+        if tokens.contains(&Token::Directive(Directive::Macro)) {
+            self.nested += 1;
+        } else if self.nested > 0 && tokens.first() == Some(&Token::Directive(Directive::End)) {
+            self.nested -= 1;
+        }
+        let m = match self.macros.get_mut(&self.collecting_name) {
+            Some(m) => m,
+            None => {
+                tokens.clear();
+                return Ok(());
+            }
+        };
         let mut tok = vec![];
-        let m = self.macros.get_mut(&self.collecting_name).unwrap();
         while let Some(t) = tokens.pop() {
             match &t {
                 Token::ConstLabel(l) => {
@@ -175,11 +351,30 @@ impl MacroHandler {
             }
         }
         m.tokens.push(tok);
-
-        context.next_line();
+        m.source.push(source);
         Ok(())
     }
 
+    pub fn begin_repeat(&mut self, count: isize, counter: Option<String>) {
+        self.repeat = Some(Repeat { count, counter, ..Default::default() });
+    }
+
+    pub fn end_repeat(&mut self, context: &mut AssemblerContext) -> Result<Repeat, Error> {
+        match self.repeat.take() {
+            Some(r) => Ok(r),
+            None => Err(context.error(ErrorType::DanglingEndRepeat))
+        }
+    }
+
+    // Lines replayed from a REPT body do not move on the source line
+    pub fn begin_replay(&mut self) {
+        self.repeating += 1;
+    }
+
+    pub fn end_replay(&mut self) {
+        self.repeating -= 1;
+    }
+
 
     pub fn end_collect(&mut self, context: &mut AssemblerContext) -> Result<(), Error> {
         if !self.collecting {
@@ -189,4 +384,71 @@ impl MacroHandler {
         self.collecting = false;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::label::Label;
+    use crate::assembler::test_support::{assemble, errors};
+
+    #[test]
+    fn macro_labels() {
+        let source = " org 0x8000\n macro border\n.loop djnz .loop\n endm\n macro wait count\n local again\n ld b,count\n\
+                      again halt\n djnz again\n endm\n border\n wait 2\n border\n wait 3\n";
+        let (mut assembler, result) = assemble("macro_labels", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[0x10, 0xFE, 0x06, 0x02, 0x76, 0x10, 0xFD,
+            0x10, 0xFE, 0x06, 0x03, 0x76, 0x10, 0xFD]);
+        assert_eq!(assembler.context.get_label("border.2.loop"), Some(0x8007));
+        assert_eq!(assembler.context.get_label("wait.2.again"), Some(0x800B));
+    }
+
+    #[test]
+    fn macro_label_errors() {
+        let source = " local again\n macro wait\nagain halt\n endm\n nop\n wait\n";
+        let (assembler, result) = assemble("macro_label_errors", source);
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (1, "LOCAL can only be used inside a macro".to_string()),
+            (6, "Only local labels are permitted inside macros".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn nested_macro_labels() {
+        let source = " org 0x8000\n macro inner\n.l2 nop\n jr .l2\n endm\n macro outer\n.l1 nop\n inner\n jr .l1\n endm\n\
+                      main outer\n.after jr .after\n";
+        let (mut assembler, result) = assemble("nested_macro_labels", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[0x00, 0x00, 0x18, 0xFD, 0x18, 0xFA, 0x18, 0xFE]);
+        assert_eq!(assembler.context.get_label("outer.1.l1"), Some(0x8000));
+        assert_eq!(assembler.context.get_label("inner.1.l2"), Some(0x8001));
+        assert_eq!(assembler.context.get_label("main.after"), Some(0x8006));
+    }
+
+    #[test]
+    fn macro_defining_a_macro() {
+        let source = " org 0x8000\n macro outer\n nop\n macro inner\n halt\n endm\n inner\n endm\n outer\n outer\n ld a,1\n";
+        let (mut assembler, result) = assemble("macro_defining_a_macro", source);
+        assert!(result.is_err());
+        // The second expansion cannot define inner again, its body is skipped rather than assembled
+        let errors = errors(&assembler);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 10);
+        assert!(errors[0].1.starts_with("Invalid or missing macro name in macro outer"));
+        assert_eq!(assembler.bank.as_slice(), &[0x00, 0x76, 0x00, 0x76, 0x3E, 0x01]);
+    }
+
+    #[test]
+    fn bad_definitions_are_skipped() {
+        let source = " macro wait\n nop\n endm\n macro wait\n halt\n endm\n macro\n xor a\n endm\n macro pause 1\n endm\n wait\n";
+        let (mut assembler, result) = assemble("bad_definitions", source);
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (4, "Invalid or missing macro name".to_string()),
+            (7, "Invalid or missing macro name".to_string()),
+            (10, "Invalid or missing macro parameter name".to_string()),
+        ]);
+        assert_eq!(assembler.bank.as_slice(), &[0x00]);
+    }
+}
@@ -1,6 +1,17 @@
+pub(super) mod assertion;
 pub(super) mod conditional;
 pub(super) mod directives;
 pub(super) mod enumerator;
 pub(super) mod macros;
+pub(super) mod module;
+pub(super) mod paging;
+pub(super) mod phase;
+pub(super) mod repeat;
+pub(super) mod savenex;
+pub(super) mod savesna;
+pub(super) mod savetap;
+pub(super) mod section;
 pub(super) mod sizeof;
-pub(super) mod structure;
\ No newline at end of file
+pub(super) mod structure;
+pub(super) mod timing;
+pub(super) mod unittest;
\ No newline at end of file
@@ -0,0 +1,78 @@
+use crate::assembler::Assembler;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::tokens::Token::ConstLabel;
+
+pub trait Module {
+    fn process_module(&mut self) -> Result<(), Error>;
+    fn process_end_module(&mut self) -> Result<(), Error>;
+}
+
+impl Module for Assembler {
+    // MODULE name ... ENDMODULE, modules can be nested and the same module opened more than once
+    fn process_module(&mut self) -> Result<(), Error> {
+        match self.take_token()? {
+            ConstLabel(name) if !name.is_empty() && !name.contains(['.', '@', ':']) => {
+                self.context.modules.push(name);
+                self.context.label_context.clear();
+                Ok(())
+            }
+            _ => Err(self.context.error(ErrorType::BadModuleName))
+        }
+    }
+
+    fn process_end_module(&mut self) -> Result<(), Error> {
+        if self.context.modules.pop().is_none() {
+            return Err(self.context.error(ErrorType::EndModuleWithoutModule));
+        }
+        self.context.label_context.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::label::Label;
+    use crate::assembler::test_support::{assemble, errors};
+
+    #[test]
+    fn modules() {
+        let source = " org 0x8000\ninit ret\n module gfx\ninit call @init\n module sprite\ninit call init\n call gfx.init\n \
+                      endmodule\n endmodule\n call gfx.init\n call gfx.sprite.init\n module gfx\n@top jp init\n endmodule\n";
+        let (mut assembler, result) = assemble("modules", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[0xC9, 0xCD, 0x00, 0x80, 0xCD, 0x04, 0x80, 0xCD, 0x01, 0x80,
+            0xCD, 0x01, 0x80, 0xCD, 0x04, 0x80, 0xC3, 0x01, 0x80]);
+        assert_eq!(assembler.context.get_label("gfx.sprite.init"), Some(0x8004));
+        assert_eq!(assembler.context.get_label("top"), Some(0x8010));
+    }
+
+    #[test]
+    fn only_dot_labels_are_local() {
+        let source = " module gfx\nstart nop\n.loop jr .loop\n endmodule\n macro border\n.wait djnz .wait\n endm\nmain border\n";
+        let (assembler, result) = assemble("module_locals", source);
+        assert!(result.is_ok());
+        let locals: Vec<(String, bool)> = assembler.context.symbols(true).into_iter().map(|s| (s.name, s.local)).collect();
+        assert_eq!(locals, [
+            ("gfx.start".to_string(), false),
+            ("gfx.start.loop".to_string(), true),
+            ("border.1".to_string(), false),
+            ("border.1.wait".to_string(), true),
+            ("main".to_string(), false),
+        ]);
+    }
+
+    #[test]
+    fn module_errors() {
+        let (assembler, result) = assemble("module_errors", " endmodule\n module gfx.sprite\n module\n module @x\n module gfx\n nop\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (1, "ENDMODULE without MODULE".to_string()),
+            (2, "Invalid module name".to_string()),
+            (3, "Unexpected end of line".to_string()),
+            (4, "Invalid module name".to_string()),
+            (6, "MODULE without ENDMODULE".to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,66 @@
+use std::str::FromStr;
+
+use crate::assembler::Assembler;
+use crate::assembler::collector::Collector;
+use crate::assembler::device::DeviceType;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::tokens::Del::Comma;
+use crate::assembler::tokens::Token::{ConstLabel, Delimiter};
+
+pub trait Paging {
+    fn process_device(&mut self) -> Result<(), Error>;
+    fn process_slot(&mut self) -> Result<(), Error>;
+    fn process_page(&mut self) -> Result<(), Error>;
+    fn process_mmu(&mut self) -> Result<(), Error>;
+    fn expect_number(&mut self) -> Result<isize, Error>;
+}
+
+impl Paging for Assembler {
+    fn process_device(&mut self) -> Result<(), Error> {
+        let kind = match self.take_token()? {
+            ConstLabel(name) => DeviceType::from_str(&name).map_err(|_| self.context.error_text(ErrorType::UnknownDevice, &name))?,
+            _ => return Err(self.context.error(ErrorType::UnknownDevice))
+        };
+        self.bank.set_device(kind);
+        Ok(())
+    }
+
+    fn process_slot(&mut self) -> Result<(), Error> {
+        if !self.bank.device().is_paged() {
+            return Err(self.context.error(ErrorType::NoDevice));
+        }
+        let slot = self.expect_number()?;
+        let r = self.bank.device_mut().select_slot(slot);
+        self.context.result(r)
+    }
+
+    fn process_page(&mut self) -> Result<(), Error> {
+        let page = self.expect_number()?;
+        let r = self.bank.device_mut().map(None, page);
+        self.context.result(r)
+    }
+
+    fn process_mmu(&mut self) -> Result<(), Error> {
+        let slot = self.expect_number()?;
+        self.expect_token(Delimiter(Comma))?;
+        let page = self.expect_number()?;
+        let r = self.bank.device_mut().map(Some(slot), page);
+        self.context.result(r)?;
+        if let Some(address) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            self.origin = address;
+            self.context.pc(address);
+            self.bank.origin(address);
+        }
+        Ok(())
+    }
+
+    fn expect_number(&mut self) -> Result<isize, Error> {
+        match self.expr.parse(&mut self.context, &mut self.tokens) {
+            Ok(Some(n)) => Ok(n),
+            Ok(None) => Err(self.context.error(ErrorType::SyntaxError)),
+            Err(e) => Err(self.context.error(e))
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::assembler::Assembler;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+
+pub trait Phase {
+    fn process_phase(&mut self) -> Result<(), Error>;
+    fn process_dephase(&mut self) -> Result<(), Error>;
+}
+
+impl Phase for Assembler {
+    // Only $ moves, the bytes carry on from the load address in the bank
+    fn process_phase(&mut self) -> Result<(), Error> {
+        if self.phased {
+            return Err(self.context.error(ErrorType::NestedPhase));
+        }
+        let address = self.expect_word()?;
+        if !(0..=65535).contains(&address) {
+            return Err(self.context.error(ErrorType::IntegerOutOfRange));
+        }
+        self.phased = true;
+        self.context.pc(address);
+        Ok(())
+    }
+
+    fn process_dephase(&mut self) -> Result<(), Error> {
+        if !self.phased {
+            return Err(self.context.error(ErrorType::DephaseWithoutPhase));
+        }
+        self.phased = false;
+        self.context.pc(self.bank.address());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::label::Label;
+    use crate::assembler::test_support::{assemble, errors};
+
+    #[test]
+    fn phase() {
+        let source = " org 0x8000\n ld hl,start\nstart\n phase 0x4000\nfast djnz fast\n jp fast\n dephase\nafter\n";
+        let (mut assembler, result) = assemble("phase", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[0x21, 0x03, 0x80, 0x10, 0xFE, 0xC3, 0x00, 0x40]);
+        assert_eq!(assembler.context.get_label("after"), Some(0x8008));
+        // Phased instructions are checked at the address they run at
+        assert!(assembler.run_tests()[0].passed());
+    }
+
+    #[test]
+    fn phase_errors() {
+        let source = " phase 0x4000\n org 0x8000\n dephase\n dephase\n phase 0x4000\n phase 0x5000\n dephase\n phase\n phase 0x10000\n";
+        let (assembler, result) = assemble("phase_errors", source);
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (2, "ORG inside PHASE, missing DEPHASE".to_string()),
+            (4, "DEPHASE without PHASE".to_string()),
+            (6, "PHASE inside PHASE, missing DEPHASE".to_string()),
+            (8, "Syntax error".to_string()),
+            (9, "Integer out of range".to_string()),
+        ]);
+
+        let (assembler, result) = assemble("phase_unclosed", " phase 0x4000\n nop\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [(2, "PHASE without DEPHASE".to_string())]);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::assembler::Assembler;
+use crate::assembler::constant::Constant;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::tokens::Del::Comma;
+use crate::assembler::tokens::Token::{ConstLabel, Delimiter};
+
+pub trait Repeat {
+    fn process_rept(&mut self) -> Result<(), Error>;
+    fn process_endr(&mut self) -> Result<(), Error>;
+    fn repeat_header(&mut self) -> Result<(isize, Option<String>), Error>;
+}
+
+impl Repeat for Assembler {
+    // REPT count[,counter] starts collecting the lines up to the matching ENDR, a REPT in error
+    // still collects them so the body is skipped rather than assembled once
+    fn process_rept(&mut self) -> Result<(), Error> {
+        match self.repeat_header() {
+            Ok((count, counter)) => {
+                self.macros.begin_repeat(count, counter);
+                Ok(())
+            }
+            Err(e) => {
+                self.macros.begin_repeat(0, None);
+                Err(e)
+            }
+        }
+    }
+
+    // Replays the body once per iteration, each with its own scope for local labels. Each line
+    // reports the line it was written on rather than the ENDR
+    fn process_endr(&mut self) -> Result<(), Error> {
+        let repeat = self.macros.end_repeat(&mut self.context)?;
+        let lines = repeat.lines();
+        let scope = self.context.label_context.clone();
+        let outer_source = self.macro_source.take();
+        self.macros.begin_replay();
+        for i in 0..repeat.count {
+            if let Some(counter) = &repeat.counter {
+                self.context.set_variable(counter, i);
+            }
+            self.context.label_context = self.macros.unique_label("rept");
+            for (line, source) in &lines {
+                let (file_name, line_number) = self.context.relocate(&source.file_name, source.line_number);
+                let entry = self.listing_begin(self.context.current_line_number(), &source.text, true);
+                self.macro_source = Some(source.clone());
+                let result = self.assemble_line(&mut line.clone(), source.clone());
+                self.listing_end(entry);
+                self.context.relocate(&file_name, line_number);
+                if let Err(e) = result {
+                    self.report(e)?;
+                }
+            }
+        }
+        self.macros.end_replay();
+        self.macro_source = outer_source;
+        self.context.label_context = scope;
+        if let Some(counter) = &repeat.counter {
+            self.context.remove_constant(counter);
+        }
+        Ok(())
+    }
+
+    // The count and optional counter name
+    fn repeat_header(&mut self) -> Result<(isize, Option<String>), Error> {
+        let count = match self.expr.parse(&mut self.context, &mut self.tokens) {
+            Ok(Some(n)) if n >= 0 => n,
+            Ok(Some(_)) => return Err(self.context.error(ErrorType::BadRepeatCount)),
+            Ok(None) => return Err(self.context.error(ErrorType::BadExpression)),
+            Err(e) => return Err(self.context.error(e))
+        };
+        let mut counter = None;
+        if self.next_token_is(&Delimiter(Comma)) {
+            self.tokens.pop();
+            match self.take_token()? {
+                ConstLabel(l) if !l.starts_with('.') => {
+                    if self.context.is_constant_defined(&l) {
+                        return Err(self.context.error(ErrorType::LabelOrConstantExists));
+                    }
+                    counter = Some(l)
+                }
+                _ => return Err(self.context.error(ErrorType::InvalidLabel))
+            }
+        }
+        Ok((count, counter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::test_support::{assemble, errors};
+
+    #[test]
+    fn repeat_blocks() {
+        let source = " rept 3,n\n db n\n endr\n dup 2\n rept 2,j\n db j+0x10\n edup\n endr\n rept 0\n nop\n endr\n";
+        let (mut assembler, result) = assemble("rept", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[0, 1, 2, 0x10, 0x11, 0x10, 0x11]);
+    }
+
+    #[test]
+    fn repeat_local_labels() {
+        let source = " org 0x8000\nmain\n rept 2\n.wait djnz .wait\n endr\n jr .end\n.end\n";
+        let (mut assembler, result) = assemble("rept_labels", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[0x10, 0xFE, 0x10, 0xFE, 0x18, 0x00]);
+    }
+
+    #[test]
+    fn repeat_errors() {
+        let (assembler, result) = assemble("rept_counter", " rept 2,n\n db n\n endr\n db n\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler).len(), 1);
+        assert_eq!(errors(&assembler)[0].0, 4);
+
+        // A REPT in error skips its body and the matching ENDR, only a stray ENDR is another error
+        let (mut assembler, result) = assemble("rept_count", " rept -1\n nop\n endr\n endr\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (1, "REPT count must not be negative".to_string()),
+            (4, "Encountered ENDR without REPT".to_string()),
+        ]);
+        assert!(assembler.bank.as_slice().is_empty());
+    }
+
+    #[test]
+    fn errors_are_reported_on_the_body_line() {
+        // Once for each iteration, nested repeats included
+        let source = " nop\n rept 2\n nop\n ld q,1\n rept 1\n jp\n endr\n endr\n";
+        let (assembler, result) = assemble("rept_error_lines", source);
+        assert!(result.is_err());
+        let lines: Vec<isize> = errors(&assembler).iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [4, 6, 4, 6]);
+    }
+}
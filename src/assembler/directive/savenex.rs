@@ -0,0 +1,195 @@
+use std::str::FromStr;
+
+use crate::assembler::Assembler;
+use crate::assembler::collector::Collector;
+use crate::assembler::device::DeviceType;
+use crate::assembler::directive::directives::Directives;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::nex::{BANK_SIZE, MAX_BANKS, Nex, NexScreen};
+use crate::assembler::tokens::Del::Comma;
+use crate::assembler::tokens::Token::{ConstLabel, Delimiter, StringLiteral};
+
+// The banks paged in at 0x4000, 0x8000 & 0xC000 after a NEX file is loaded
+const DEFAULT_BANKS: [(u8, isize); 3] = [(5, 0x4000), (2, 0x8000), (0, 0xC000)];
+
+pub trait SaveNex {
+    fn process_savenex(&mut self) -> Result<(), Error>;
+    fn nex_open(&mut self) -> Result<(), Error>;
+    fn nex_core(&mut self) -> Result<(), Error>;
+    fn nex_config(&mut self) -> Result<(), Error>;
+    fn nex_banks(&mut self) -> Result<(), Error>;
+    fn nex_screen(&mut self) -> Result<(), Error>;
+    fn read_binary(&mut self) -> Result<Vec<u8>, Error>;
+    fn nex_bank_used(&mut self, bank: u8) -> bool;
+    fn nex_bank_data(&mut self, bank: u8) -> Option<Vec<u8>>;
+    fn write_nex(&mut self, file_name: &str) -> Result<(), Error>;
+}
+
+impl SaveNex for Assembler {
+    fn process_savenex(&mut self) -> Result<(), Error> {
+        if !self.z80n_enabled {
+            return Err(self.context.error(ErrorType::Z80NDisabled));
+        }
+        let command = match self.take_token()? {
+            ConstLabel(c) => c.to_lowercase(),
+            _ => return Err(self.context.error(ErrorType::NexBadCommand))
+        };
+        if command != "open" && !self.nex.open {
+            return Err(self.context.error(ErrorType::NexNotOpen));
+        }
+        match command.as_str() {
+            "open" => self.nex_open(),
+            "core" => self.nex_core(),
+            "cfg" => self.nex_config(),
+            "bank" => self.nex_banks(),
+            "screen" => self.nex_screen(),
+            "close" => {
+                self.nex.open = false;
+                Ok(())
+            }
+            _ => Err(self.context.error(ErrorType::NexBadCommand))
+        }
+    }
+
+    fn nex_open(&mut self) -> Result<(), Error> {
+        if self.nex.open || !self.nex.file_name.is_empty() {
+            return Err(self.context.error(ErrorType::NexAlreadyOpen));
+        }
+        self.nex.file_name = match self.take_token()? {
+            StringLiteral(s) => s,
+            _ => return Err(self.context.error(ErrorType::FileNotFound))
+        };
+        self.nex.open = true;
+        self.nex.start = self.optional_parameter(Some(&Delimiter(Comma)))?;
+        if let Some(stack) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            self.nex.stack = stack;
+        }
+        if let Some(bank) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            if !(0..MAX_BANKS as isize).contains(&bank) {
+                return Err(self.context.error(ErrorType::NexBadBank(bank)));
+            }
+            self.nex.entry_bank = bank as u8;
+        }
+        if let Some(handle) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            self.nex.file_handle = handle;
+        }
+        Ok(())
+    }
+
+    fn nex_core(&mut self) -> Result<(), Error> {
+        for i in 0..3 {
+            if i > 0 {
+                self.expect_token(Delimiter(Comma))?;
+            }
+            self.nex.core[i] = self.expect_byte()? as u8;
+        }
+        Ok(())
+    }
+
+    fn nex_config(&mut self) -> Result<(), Error> {
+        self.nex.border = self.expect_number_in_range(0..8, ErrorType::IntegerOutOfRange)? as u8;
+        if let Some(handle) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            self.nex.file_handle = handle;
+        }
+        if let Some(preserve) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            self.nex.preserve_registers = preserve != 0;
+        }
+        if let Some(ram) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            self.nex.ram_2mb = ram != 0;
+        }
+        Ok(())
+    }
+
+    fn nex_banks(&mut self) -> Result<(), Error> {
+        loop {
+            let bank = self.expect_number_in_range(0..MAX_BANKS as isize, ErrorType::IntegerOutOfRange)?;
+            if !(0..MAX_BANKS as isize).contains(&bank) {
+                return Err(self.context.error(ErrorType::NexBadBank(bank)));
+            }
+            if !self.nex.banks.contains(&(bank as u8)) {
+                self.nex.banks.push(bank as u8);
+            }
+            if !self.next_token_is(&Delimiter(Comma)) {
+                break;
+            }
+            self.tokens.pop();
+        }
+        Ok(())
+    }
+
+    fn nex_screen(&mut self) -> Result<(), Error> {
+        let screen = match self.take_token()? {
+            ConstLabel(s) => NexScreen::from_str(&s).map_err(|_| self.context.error(ErrorType::NexBadScreen))?,
+            _ => return Err(self.context.error(ErrorType::NexBadScreen))
+        };
+        self.expect_token(Delimiter(Comma))?;
+        let data = self.read_binary()?;
+        let palette = if self.next_token_is(&Delimiter(Comma)) {
+            self.tokens.pop();
+            Some(self.read_binary()?)
+        } else {
+            None
+        };
+        self.nex.screen = Some((screen, data, palette));
+        Ok(())
+    }
+
+    fn read_binary(&mut self) -> Result<Vec<u8>, Error> {
+        let file_name = match self.take_token()? {
+            StringLiteral(s) => s,
+            _ => return Err(self.context.error(ErrorType::FileNotFound))
+        };
+        let file_path = self.locate_file(&file_name)?;
+        self.files.read(&file_path)
+    }
+
+    fn nex_bank_used(&mut self, bank: u8) -> bool {
+        let device = self.bank.device();
+        match device.kind() {
+            DeviceType::Next => device.page_used(bank as usize * 2) || device.page_used(bank as usize * 2 + 1),
+            DeviceType::Zx128 => device.page_used(bank as usize),
+            _ => match DEFAULT_BANKS.iter().find(|(b, _)| *b == bank) {
+                Some((_, address)) => self.bank.used(*address, BANK_SIZE),
+                None => false
+            }
+        }
+    }
+
+    // Next pages are 8K so a 16K bank is made of two of them, without a paged memory model
+    // only the banks at their default 48K addresses hold code
+    fn nex_bank_data(&mut self, bank: u8) -> Option<Vec<u8>> {
+        let device = self.bank.device();
+        match device.kind() {
+            DeviceType::Next => {
+                let mut data = device.page(bank as usize * 2)?.to_vec();
+                data.extend_from_slice(device.page(bank as usize * 2 + 1)?);
+                Some(data)
+            }
+            DeviceType::Zx128 => device.page(bank as usize).map(|p| p.to_vec()),
+            _ => {
+                let (_, address) = DEFAULT_BANKS.iter().find(|(b, _)| *b == bank)?;
+                Some(self.bank.read(*address, BANK_SIZE))
+            }
+        }
+    }
+
+    fn write_nex(&mut self, file_name: &str) -> Result<(), Error> {
+        let mut banks = self.nex.banks.clone();
+        if banks.is_empty() {
+            banks = (0..MAX_BANKS as u8).filter(|b| self.nex_bank_used(*b)).collect();
+        }
+        let order = Nex::bank_order();
+        banks.sort_by_key(|b| order.iter().position(|n| n == b));
+        let mut data = vec![];
+        for bank in banks {
+            match self.nex_bank_data(bank) {
+                Some(d) => data.push((bank, d)),
+                None => return Err(Error::fatal(&ErrorType::NexBankNotMapped(bank).to_string(), -1, file_name))
+            }
+        }
+        let start = self.nex.start.unwrap_or(self.bank.first_address().unwrap_or(0));
+        self.nex.write(file_name, start, &data)
+    }
+}
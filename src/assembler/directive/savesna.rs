@@ -0,0 +1,77 @@
+use crate::assembler::Assembler;
+use crate::assembler::collector::Collector;
+use crate::assembler::device::DeviceType;
+use crate::assembler::directive::directives::Directives;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::snapshot::{BANK_SIZE, RAM_48K, Snapshot};
+use crate::assembler::tokens::Del::Comma;
+use crate::assembler::tokens::Token::{Delimiter, StringLiteral};
+
+pub trait SaveSnapshot {
+    fn process_savesna(&mut self) -> Result<(), Error>;
+    fn write_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), Error>;
+}
+
+impl SaveSnapshot for Assembler {
+    fn process_savesna(&mut self) -> Result<(), Error> {
+        let mut snapshot = match self.take_token()? {
+            StringLiteral(s) => Snapshot::new(&s),
+            _ => return Err(self.context.error(ErrorType::FileNotFound))
+        };
+        if !Snapshot::is_snapshot(&snapshot.file_name) {
+            return Err(self.context.error(ErrorType::SnapshotType));
+        }
+        snapshot.start = self.optional_parameter(Some(&Delimiter(Comma)))?;
+        if let Some(stack) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            snapshot.stack = stack;
+        }
+        if let Some(border) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            snapshot.border = border as u8;
+        }
+        if let Some(bank) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            if !(0..8).contains(&bank) {
+                return Err(self.context.error(ErrorType::PageOutOfRange(bank)));
+            }
+            snapshot.bank = bank as u8;
+        }
+        if self.next_token_is(&Delimiter(Comma)) {
+            self.tokens.pop();
+            if let StringLiteral(s) = self.take_token()? {
+                snapshot.ram_image = self.locate_file(&s)?;
+            } else {
+                return Err(self.context.error(ErrorType::FileNotFound));
+            }
+        }
+        self.snapshots.push(snapshot);
+        Ok(())
+    }
+
+    // Memory not assembled into is zero or taken from the RAM image, 48K from 0x4000 or
+    // the eight 128K banks in order
+    fn write_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        let mut image = vec![];
+        if !snapshot.ram_image.is_empty() {
+            image = self.files.read(&snapshot.ram_image)?;
+        }
+        let start = snapshot.start.unwrap_or(self.bank.first_address().unwrap_or(0));
+        match self.bank.device().kind() {
+            DeviceType::Zx128 => {
+                image.resize(BANK_SIZE * 8, 0);
+                let banks: Vec<Vec<u8>> = image.chunks(BANK_SIZE).enumerate().map(|(bank, data)| {
+                    let mut data = data.to_vec();
+                    self.bank.device().overlay_page(bank, &mut data);
+                    data
+                }).collect();
+                snapshot.write_128k(start, &banks)
+            }
+            DeviceType::Next => Err(Error::fatal(&ErrorType::SnapshotDevice.to_string(), -1, &snapshot.file_name)),
+            _ => {
+                image.resize(RAM_48K, 0);
+                self.bank.overlay(0x4000, &mut image);
+                snapshot.write_48k(start, &mut image)
+            }
+        }
+    }
+}
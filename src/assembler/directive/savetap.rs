@@ -0,0 +1,39 @@
+use crate::assembler::Assembler;
+use crate::assembler::collector::Collector;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::tap::Tap;
+use crate::assembler::tokens::Del::Comma;
+use crate::assembler::tokens::Token::{Delimiter, StringLiteral};
+
+pub trait SaveTap {
+    fn process_savetap(&mut self) -> Result<(), Error>;
+    fn write_tap(&mut self, tap: &Tap) -> Result<(), Error>;
+}
+
+impl SaveTap for Assembler {
+    fn process_savetap(&mut self) -> Result<(), Error> {
+        let mut tap = match self.take_token()? {
+            StringLiteral(s) => Tap::new(&s),
+            _ => return Err(self.context.error(ErrorType::FileNotFound))
+        };
+        tap.start = self.optional_parameter(Some(&Delimiter(Comma)))?;
+        if let Some(loader) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            tap.loader = loader != 0;
+        }
+        self.taps.push(tap);
+        Ok(())
+    }
+
+    // The CODE block covers every address assembled into, loading at the lowest
+    fn write_tap(&mut self, tap: &Tap) -> Result<(), Error> {
+        let (first, last) = match self.bank.address_range() {
+            Some(range) => range,
+            None => return Err(Error::fatal(&ErrorType::NothingToSave.to_string(), -1, &tap.file_name))
+        };
+        let code = self.bank.read(first, (last - first + 1) as usize);
+        let start = tap.start.unwrap_or(self.bank.first_address().unwrap_or(first));
+        tap.write(first, &code, start)
+    }
+}
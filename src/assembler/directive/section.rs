@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use crate::assembler::{Assembler, TokenReader};
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::token_reader::{DELIMITERS, OPERATORS};
+use crate::assembler::tokens::Del::Comma;
+use crate::assembler::tokens::Token::{ConstLabel, Delimiter, StringLiteral};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SectionKind {
+    Code,
+    Data,
+    Bss,
+}
+
+// A start address and optional page from a PLACE line or --section
+pub type Placement = (isize, Option<isize>);
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: SectionKind,
+    pub start: isize,
+    pub page: Option<isize>,
+    pub pc: isize,
+    file_name: String,
+    line_no: isize,
+}
+
+impl Section {
+    fn overlaps(&self, other: &Section) -> bool {
+        let same_page = self.page.is_none() || other.page.is_none() || self.page == other.page;
+        same_page && self.pc > self.start && other.pc > other.start && self.start < other.pc && other.start < self.pc
+    }
+}
+
+pub trait Sections {
+    fn process_section(&mut self) -> Result<(), Error>;
+    fn process_memory(&mut self) -> Result<(), Error>;
+    fn process_end_memory(&mut self) -> Result<(), Error>;
+    fn process_place(&mut self) -> Result<(), Error>;
+    fn read_placement(&mut self) -> Result<(String, Placement), Error>;
+    fn parse_section_options(&mut self) -> Result<(), Error>;
+    fn section_kind(&mut self, name: &str) -> Result<SectionKind, Error>;
+    fn check_sections(&mut self) -> Result<(), Error>;
+}
+
+impl Sections for Assembler {
+    // SECTION name[,CODE|DATA|BSS], re-opening a section carries on where it left off
+    fn process_section(&mut self) -> Result<(), Error> {
+        if self.phased {
+            return Err(self.context.error(ErrorType::SectionInPhase));
+        }
+        let name = match self.take_token()? {
+            ConstLabel(n) | StringLiteral(n) => n,
+            _ => return Err(self.context.error(ErrorType::SyntaxError))
+        };
+        let kind = if self.next_token_is(&Delimiter(Comma)) {
+            self.tokens.pop();
+            match self.take_token()? {
+                ConstLabel(k) => self.section_kind(&k)?,
+                _ => return Err(self.context.error(ErrorType::SyntaxError))
+            }
+        } else {
+            self.section_kind(&name).unwrap_or(SectionKind::Code)
+        };
+
+        if let Some(current) = self.section {
+            self.sections[current].pc = self.context.offset_pc(0);
+        }
+        let index = match self.sections.iter().position(|s| s.name == name) {
+            Some(i) => i,
+            None => {
+                let placement = self.section_layout.get(&name).or_else(|| self.layout.get(&name)).copied();
+                let (start, page) = match placement {
+                    Some(p) => p,
+                    None => return Err(self.context.error(ErrorType::SectionNotPlaced(name)))
+                };
+                self.sections.push(Section {
+                    name,
+                    kind,
+                    start,
+                    page,
+                    pc: start,
+                    file_name: self.context.current_file_name(),
+                    line_no: self.context.current_line_number(),
+                });
+                self.sections.len() - 1
+            }
+        };
+        self.section = Some(index);
+
+        let section = self.sections[index].clone();
+        if let Some(page) = section.page {
+            let (page_size, _, _) = self.bank.device().layout();
+            let slot = if page_size > 0 { section.start / page_size as isize } else { 0 };
+            let r = self.bank.device_mut().map(Some(slot), page);
+            self.context.result(r)?;
+        }
+        self.bank.reserve(section.kind == SectionKind::Bss);
+        self.context.pc(section.pc);
+        self.bank.origin(section.pc);
+        Ok(())
+    }
+
+    fn process_memory(&mut self) -> Result<(), Error> {
+        if self.memory_block {
+            return Err(self.context.error(ErrorType::NestedMemory));
+        }
+        self.memory_block = true;
+        Ok(())
+    }
+
+    fn process_end_memory(&mut self) -> Result<(), Error> {
+        if !self.memory_block {
+            return Err(self.context.error(ErrorType::EndMemoryWithoutMemory));
+        }
+        self.memory_block = false;
+        Ok(())
+    }
+
+    fn process_place(&mut self) -> Result<(), Error> {
+        if !self.memory_block {
+            return Err(self.context.error(ErrorType::PlaceOutsideMemory));
+        }
+        let (name, placement) = self.read_placement()?;
+        if self.layout.contains_key(&name) {
+            return Err(self.context.error(ErrorType::SectionPlaced(name)));
+        }
+        self.layout.insert(name, placement);
+        Ok(())
+    }
+
+    // name,address[,page]
+    fn read_placement(&mut self) -> Result<(String, Placement), Error> {
+        let name = match self.take_token()? {
+            ConstLabel(n) | StringLiteral(n) => n,
+            _ => return Err(self.context.error(ErrorType::SyntaxError))
+        };
+        self.expect_token(Delimiter(Comma))?;
+        let address = self.expect_word()?;
+        let mut page = None;
+        if self.next_token_is(&Delimiter(Comma)) {
+            self.tokens.pop();
+            page = Some(self.expect_byte()?);
+        }
+        Ok((name, (address, page)))
+    }
+
+    // --section name=address[,page], these take the place of any PLACE for the same section
+    fn parse_section_options(&mut self) -> Result<(), Error> {
+        let mut layout = HashMap::new();
+        for option in self.section_options.clone() {
+            let line = option.replacen('=', ",", 1);
+            let mut reader = TokenReader::new(line.as_bytes());
+            reader.delimiters(DELIMITERS).operators(OPERATORS);
+            self.tokens = reader.read_line()?;
+            self.tokens.reverse();
+            match self.read_placement() {
+                Ok((name, placement)) if self.tokens.is_empty() => {
+                    layout.insert(name, placement);
+                }
+                _ => {
+                    let message = ErrorType::BadSectionOption(option).to_string();
+                    return Err(Error::fatal(&message, -1, "--section"));
+                }
+            }
+        }
+        self.tokens.clear();
+        self.section_layout = layout;
+        Ok(())
+    }
+
+    fn section_kind(&mut self, name: &str) -> Result<SectionKind, Error> {
+        match name.to_lowercase().as_str() {
+            "code" => Ok(SectionKind::Code),
+            "data" => Ok(SectionKind::Data),
+            "bss" => Ok(SectionKind::Bss),
+            _ => Err(self.context.error(ErrorType::SyntaxError))
+        }
+    }
+
+    // Every section must fit below 64K and must not share any addresses with another
+    fn check_sections(&mut self) -> Result<(), Error> {
+        if let Some(current) = self.section {
+            self.sections[current].pc = self.context.offset_pc(0);
+        }
+        let sections = self.sections.clone();
+        for (i, section) in sections.iter().enumerate() {
+            let mut errors = vec![];
+            if section.pc > 0x10000 {
+                errors.push(ErrorType::SectionOverflow(section.name.clone(), section.pc - 0x10000));
+            }
+            for other in sections.iter().skip(i + 1).filter(|o| section.overlaps(o)) {
+                errors.push(ErrorType::SectionOverlap(section.name.clone(), other.name.clone()));
+            }
+            for e in errors {
+                self.report(Error::error(&e.to_string(), section.line_no, &section.file_name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_options::AssemblerOptions;
+    use crate::assembler::label::Label;
+    use crate::assembler::test_support::{assemble, assemble_with, errors};
+
+    #[test]
+    fn sections() {
+        let source = " memory\n place code,0x8000\n place data,0x8008\n place bss,0x9000\n endmemory\n\
+                      section code\n ld hl,message\n section bss\nbuffer ds 16\n section data\nmessage db 1\n\
+                      section code\n ld (buffer),hl\n";
+        let (mut assembler, result) = assemble("sections", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.assembled().bytes, [0x21, 0x08, 0x80, 0x22, 0x00, 0x90, 0, 0, 1]);
+        assert_eq!(assembler.context.get_label("buffer"), Some(0x9000));
+    }
+
+    #[test]
+    fn section_options() {
+        let source = " memory\n place code,0x8000\n endmemory\n section code\nstart nop\n";
+        let (mut assembler, result) = assemble_with("section_option", source, |a| {
+            a.add_sections(vec!["code=0x6000".to_string()]);
+        });
+        assert!(result.is_ok());
+        assert_eq!(assembler.context.get_label("start"), Some(0x6000));
+
+        let (_, result) = assemble_with("section_option_error", source, |a| {
+            a.add_sections(vec!["code".to_string()]);
+        });
+        let error = result.err().unwrap();
+        assert!(error.is_fatal());
+        assert_eq!(error.message, "Invalid section placement code, expected name=address[,page]");
+    }
+
+    #[test]
+    fn section_errors() {
+        let overlap = " memory\n place one,0x8000\n place two,0x8001\n endmemory\n section one\n dw 0\n section two\n nop\n";
+        let overflow = " memory\n place one,0xFFFF\n endmemory\n section one,bss\n ds 2\n";
+        let unplaced = " section one\n nop\n";
+        let outside = " place one,0x8000\n";
+        for (name, source, line, message) in [("overlap", overlap, 5, "Section one overlaps section two"),
+            ("overflow", overflow, 4, "Section one overflows the end of memory by 1 byte(s)"),
+            ("unplaced", unplaced, 1, "Section one has no address, use PLACE or --section"),
+            ("outside", outside, 1, "PLACE outside of a MEMORY block")] {
+            let (assembler, result) = assemble(name, source);
+            assert!(result.is_err());
+            assert_eq!(errors(&assembler), [(line, message.to_string())]);
+        }
+    }
+
+    #[test]
+    fn memory_block_errors() {
+        let source = " memory\n memory\n endmemory\n endmemory\n section\n phase 1\n section one\n dephase\n memory\n";
+        let (assembler, result) = assemble("memory_errors", source);
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (2, "MEMORY inside MEMORY, missing ENDMEMORY".to_string()),
+            (4, "ENDMEMORY without MEMORY".to_string()),
+            (5, "Unexpected end of line".to_string()),
+            (7, "SECTION inside PHASE, missing DEPHASE".to_string()),
+            (9, "MEMORY without ENDMEMORY".to_string()),
+        ]);
+    }
+}
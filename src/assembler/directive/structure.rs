@@ -88,7 +88,7 @@ impl Structure for Assembler {
 			let len = def.len() - 1;
 			for (k, v) in def.clone() {
 				self.context.add_label(format!(".{}", k), false)?;
-				if let Ok(Some(n)) = self.expr.parse(&mut self.context, &mut self.tokens, 0, 0, false) {
+				if let Ok(Some(n)) = self.expr.parse(&mut self.context, &mut self.tokens) {
 					match v {
 						1 => self.emit_byte(n as u8)?,
 						2 => self.emit_word(n)?,
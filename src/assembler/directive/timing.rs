@@ -0,0 +1,97 @@
+use crate::assembler::Assembler;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::timing::{self, TStates};
+use crate::assembler::tokens::Directive::End;
+use crate::assembler::tokens::Token::{ConstLabel, Directive, StringLiteral};
+use crate::disassembler::Disassembler;
+
+pub trait Timing {
+    fn process_timing(&mut self) -> Result<(), Error>;
+    fn end_timing(&mut self) -> Result<(), Error>;
+    fn write_timings(&self) -> Result<(), Error>;
+}
+
+impl Timing for Assembler {
+    // TIMING START [name] ... TIMING END, regions can be nested
+    fn process_timing(&mut self) -> Result<(), Error> {
+        match self.take_token()? {
+            ConstLabel(l) if l.eq_ignore_ascii_case("start") => {
+                let name = match self.tokens.pop() {
+                    Some(ConstLabel(n)) | Some(StringLiteral(n)) => n,
+                    Some(_) => return Err(self.context.error(ErrorType::SyntaxError)),
+                    None => String::new()
+                };
+                self.timing_regions.push((name, self.instructions.len()));
+                Ok(())
+            }
+            ConstLabel(l) if l.eq_ignore_ascii_case("end") => self.end_timing(),
+            Directive(End) => self.end_timing(),
+            _ => Err(self.context.error(ErrorType::SyntaxError))
+        }
+    }
+
+    // Instructions are only recorded in the final pass, which is when the total is shown
+    fn end_timing(&mut self) -> Result<(), Error> {
+        let (name, first) = match self.timing_regions.pop() {
+            Some(region) => region,
+            None => return Err(self.context.error(ErrorType::TimingWithoutStart))
+        };
+        if !self.context.final_pass {
+            return Ok(());
+        }
+        let total = self.instructions[first..].iter().fold(TStates::default(), |t, i| t + i.t_states);
+        let name = if name.is_empty() { String::from("TIMING") } else { name };
+        let message = if total.is_conditional() {
+            format!("{}: {} T-states, {} with no branches taken", name, total.taken, total.not_taken)
+        } else {
+            format!("{}: {} T-states", name, total.taken)
+        };
+        self.messages.push(format!("[{}:{}] {}", self.context.current_file_name(), self.context.current_line_number(), message));
+        Ok(())
+    }
+
+    fn write_timings(&self) -> Result<(), Error> {
+        if self.timings_file.is_empty() {
+            return Ok(());
+        }
+        let mut disassembler = Disassembler::new();
+        disassembler.enable_z80n(self.z80n_enabled).enable_cspect(self.c_spect_enabled);
+        timing::write_report(&self.timings_file, &self.instructions, &mut disassembler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::assembler::assembler_options::AssemblerOptions;
+    use crate::assembler::test_support::{assemble, assemble_with, errors, temp_file};
+
+    #[test]
+    fn timings() {
+        let timings = temp_file("timings", "txt");
+        let source = " org 0x8000\n timing start loop\n ld b,2\n.l1 djnz .l1\n timing end\n";
+        let (assembler, result) = assemble_with("timings", source, |a| { a.timings_file(timings.to_str().unwrap()); });
+        assert!(result.is_ok());
+        let text = fs::read_to_string(&timings).unwrap();
+        fs::remove_file(&timings).ok();
+        let lines: Vec<&str> = text.lines().skip(1).map(|l| l.trim_start_matches(|c| c != ' ').trim_start()).collect();
+        assert_eq!(lines, ["3  8000         7  ld b,0x02", "4  8002      13/8  djnz 0x8002"]);
+        assert_eq!(assembler.messages().len(), 1);
+        assert!(assembler.messages()[0].ends_with(":5] loop: 20 T-states, 15 with no branches taken"));
+    }
+
+    #[test]
+    fn timing_errors() {
+        let (assembler, result) = assemble("timing_errors", " org 0x8000\n timing end\n timing start 1\n timing\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (2, "TIMING END without a TIMING START".to_string()),
+            (3, "Syntax error".to_string()),
+            (4, "Unexpected end of line".to_string()),
+        ]);
+        assert!(assembler.messages().is_empty());
+    }
+}
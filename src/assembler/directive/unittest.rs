@@ -0,0 +1,253 @@
+use crate::assembler::Assembler;
+use crate::assembler::collector::Collector;
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+use crate::assembler::get_token::GetToken;
+use crate::assembler::timing::TStates;
+use crate::assembler::tokens::{Cnd, Ir, IxU, IyU, Reg, RegPair, Token};
+use crate::assembler::tokens::Del::Comma;
+use crate::assembler::tokens::Token::{ConstLabel, Delimiter};
+use crate::assembler::unit_test::{Assertion, EncodedInstruction, Expectation, TestResult, UnitTest};
+use crate::emulator::{Emulator, Registers, Stop};
+
+const INSTRUCTION_LIMIT: usize = 10_000_000;
+
+pub trait UnitTests {
+    fn process_unit_test(&mut self) -> Result<(), Error>;
+    fn process_assert_reg(&mut self) -> Result<(), Error>;
+    fn process_assert_mem(&mut self) -> Result<(), Error>;
+    fn add_assertion(&mut self, expect: Expectation) -> Result<(), Error>;
+    fn record_instruction(&mut self, offset: usize);
+    fn memory_image(&self) -> Vec<u8>;
+    fn execute_tests(&mut self) -> Vec<TestResult>;
+    fn check_encoding(&mut self) -> TestResult;
+}
+
+fn register_name(token: &Token) -> Option<String> {
+    let name = match token {
+        Token::Register(r) if *r != Reg::_HL_ => ["b", "c", "d", "e", "h", "l", "", "a"][r.clone() as usize],
+        Token::Condition(Cnd::C) => "c",
+        Token::RegisterPair(rp) => match rp {
+            RegPair::Bc => "bc",
+            RegPair::De => "de",
+            RegPair::Hl => "hl",
+            RegPair::Sp => "sp",
+            RegPair::Ix => "ix",
+            RegPair::Iy => "iy",
+            RegPair::Af => "af",
+            RegPair::_Af => "af'",
+        },
+        Token::RegisterIX(IxU::Ixh) => "ixh",
+        Token::RegisterIX(IxU::Ixl) => "ixl",
+        Token::RegisterIY(IyU::Iyh) => "iyh",
+        Token::RegisterIY(IyU::Iyl) => "iyl",
+        Token::RegisterIR(Ir::I) => "i",
+        Token::RegisterIR(Ir::R) => "r",
+        ConstLabel(l) if Registers::default().get(l).is_some() => return Some(l.to_lowercase()),
+        _ => return None
+    };
+    Some(name.to_string())
+}
+
+impl UnitTests for Assembler {
+    fn process_unit_test(&mut self) -> Result<(), Error> {
+        let name = match self.tokens.last() {
+            Some(ConstLabel(l)) => l.clone(),
+            _ => return Err(self.context.error(ErrorType::BadTest))
+        };
+        let entry = self.expect_word()?;
+        self.tests.push(UnitTest {
+            name,
+            entry,
+            file_name: self.context.current_file_name(),
+            line_no: self.context.current_line_number(),
+            assertions: vec![],
+        });
+        Ok(())
+    }
+
+    fn process_assert_reg(&mut self) -> Result<(), Error> {
+        let token = self.take_token()?;
+        let name = match register_name(&token) {
+            Some(name) => name,
+            None => return Err(self.context.error(ErrorType::UnknownRegister))
+        };
+        self.expect_token(Delimiter(Comma))?;
+        let value = self.expect_word()?;
+        self.add_assertion(Expectation::Register(name, value))
+    }
+
+    fn process_assert_mem(&mut self) -> Result<(), Error> {
+        let address = self.expect_word()?;
+        let mut bytes = vec![];
+        while let Some(b) = self.optional_parameter(Some(&Delimiter(Comma)))? {
+            bytes.push(b as u8);
+        }
+        if bytes.is_empty() {
+            return Err(self.context.error(ErrorType::SyntaxError));
+        }
+        self.add_assertion(Expectation::Memory(address, bytes))
+    }
+
+    fn add_assertion(&mut self, expect: Expectation) -> Result<(), Error> {
+        let assertion = Assertion {
+            expect,
+            file_name: self.context.current_file_name(),
+            line_no: self.context.current_line_number(),
+        };
+        match self.tests.last_mut() {
+            Some(test) => test.assertions.push(assertion),
+            None => return Err(self.context.error(ErrorType::AssertWithoutTest))
+        }
+        Ok(())
+    }
+
+    fn record_instruction(&mut self, offset: usize) {
+        let bytes = self.bank.as_slice()[offset..].to_vec();
+        if self.context.final_pass && !bytes.is_empty() {
+            self.instructions.push(EncodedInstruction {
+                address: self.context.asm_pc(),
+                t_states: TStates::of(&bytes),
+                bytes,
+                file_name: self.context.current_file_name(),
+                line_no: self.context.current_line_number(),
+            });
+        }
+    }
+
+    // With a paged device the tests see the pages as they are mapped at the end of the assembly
+    fn memory_image(&self) -> Vec<u8> {
+        let device = self.bank.device();
+        if device.is_paged() {
+            return device.view();
+        }
+        let mut image = vec![0u8; 65536];
+        self.bank.overlay(0, &mut image);
+        image
+    }
+
+    // Each test is called with a fresh copy of memory, zeroed registers and the stack at 0x0000
+    fn execute_tests(&mut self) -> Vec<TestResult> {
+        let image = self.memory_image();
+        let mut results = vec![];
+        for test in &self.tests {
+            let mut emulator = Emulator::new(&image);
+            emulator.cspect = self.c_spect_enabled;
+            emulator.z80n = self.z80n_enabled;
+            let mut failures = vec![];
+            if let Stop::InstructionLimit = emulator.call(test.entry as u16, INSTRUCTION_LIMIT) {
+                failures.push(format!("Did not finish after {} instructions", INSTRUCTION_LIMIT));
+            }
+            for assertion in &test.assertions {
+                let failure = match &assertion.expect {
+                    Expectation::Register(name, expected) => {
+                        let actual = emulator.regs.get(name).unwrap_or(0);
+                        if actual as isize != *expected & 0xFFFF {
+                            Some(format!("{} is 0x{:x}, expected 0x{:x}", name, actual, expected))
+                        } else { None }
+                    }
+                    Expectation::Memory(address, expected) => {
+                        let actual: Vec<u8> = (0..expected.len()).map(|i| emulator.read((*address as usize + i) as u16)).collect();
+                        if actual != *expected {
+                            Some(format!("memory at 0x{:04x} is {:x?}, expected {:x?}", address, actual, expected))
+                        } else { None }
+                    }
+                };
+                if let Some(message) = failure {
+                    failures.push(format!("[{} : {}] {}", assertion.file_name, assertion.line_no, message));
+                }
+            }
+            results.push(TestResult {
+                name: test.name.clone(),
+                file_name: test.file_name.clone(),
+                line_no: test.line_no,
+                failures,
+            });
+        }
+        results
+    }
+
+    // Steps the bytes the encoder produced for every instruction on their own, at the address
+    // they were assembled for, the emulator must decode them as one valid instruction that uses
+    // every byte
+    fn check_encoding(&mut self) -> TestResult {
+        let mut emulator = Emulator::new(&[]);
+        emulator.cspect = self.c_spect_enabled;
+        emulator.z80n = self.z80n_enabled;
+        let mut failures = vec![];
+        for instruction in &self.instructions {
+            let address = instruction.address as u16;
+            for (i, b) in instruction.bytes.iter().enumerate() {
+                emulator.write(address.wrapping_add(i as u16), *b);
+            }
+            emulator.regs.pc = address;
+            let fetched = emulator.step();
+            let location = format!("[{} : {}]", instruction.file_name, instruction.line_no);
+            if emulator.invalid {
+                failures.push(format!("{} emulator does not recognise {:02x?} at 0x{:04x}", location, instruction.bytes, address));
+            } else if fetched != instruction.bytes.len() {
+                failures.push(format!("{} emulator decoded {} bytes at 0x{:04x}, {} were assembled",
+                                      location, fetched, address, instruction.bytes.len()));
+            }
+        }
+        TestResult {
+            name: format!("{} instructions", self.instructions.len()),
+            file_name: String::new(),
+            line_no: -1,
+            failures,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_options::AssemblerOptions;
+    use crate::assembler::test_support::{assemble, assemble_with, errors};
+
+    #[test]
+    fn unit_tests() {
+        let source = " org 0x8000\n unittest sum\n assert_reg a,7\n unittest store\n assert_mem 0x9000,2\n\
+                      sum ld a,3\n add a,4\n ret\nstore ld a,1\n ld (0x9000),a\n ret\n";
+        let (mut assembler, result) = assemble("unittest", source);
+        assert!(result.is_ok());
+        let results = assembler.run_tests();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].passed() && results[1].passed());
+        assert_eq!(results[2].name, "store");
+        assert_eq!((results[2].line_no, results[2].failures.len()), (4, 1));
+        assert!(results[2].failures[0].ends_with(" : 5] memory at 0x9000 is [1], expected [2]"));
+    }
+
+    #[test]
+    fn tests_see_the_mapped_pages() {
+        // Page 1 is written last but page 3 is mapped in when the tests run
+        let source = " device zxspectrum128\n org 0xC000\n page 3\nget ld a,3\n ret\n page 1\n org 0xC000\n ld a,1\n ret\n\
+                      page 3\n unittest get\n assert_reg a,3\n";
+        let (mut assembler, result) = assemble("unittest_paged", source);
+        assert!(result.is_ok());
+        assert!(assembler.run_tests().iter().all(|r| r.passed()));
+    }
+
+    #[test]
+    fn cspect_exit_needs_cspect() {
+        let source = " org 0x8000\n unittest stop\n assert_reg a,1\nstop ld a,1\n exit\n ld a,2\n ret\n";
+        let (mut assembler, result) = assemble_with("unittest_cspect", source, |a| { a.enable_cspect(true); });
+        assert!(result.is_ok());
+        assert!(assembler.run_tests()[1].passed());
+        let (_, result) = assemble("unittest_no_cspect", source);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unit_test_errors() {
+        let source = " org 0x8000\n assert_reg a,1\n unittest\n unittest t1\n assert_reg q,1\n assert_mem 0x9000\nt1 ret\n";
+        let (assembler, result) = assemble("unittest_errors", source);
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (2, "Assertion does not follow a UNITTEST".to_string()),
+            (3, "UNITTEST expects the label of the routine to test".to_string()),
+            (5, "Unknown register".to_string()),
+            (6, "Syntax error".to_string()),
+        ]);
+    }
+}
@@ -9,10 +9,12 @@ pub trait Emitter {
     fn emit_byte(&mut self, b: u8) -> Result<(), Error>;
     fn emit_word(&mut self, word: isize) -> Result<(), Error>;
     fn emit_instr(&mut self, prefix: Option<u8>, instr: u8, expr: &[Token], byte: bool) -> Result<(), Error>;
+    fn trace(&mut self);
 }
 
 impl Emitter for Assembler {
     fn emit(&mut self, b: &[u8]) -> Result<(), Error> {
+        self.trace();
         let pc = self.context.offset_pc(b.len() as isize);
         if pc > 65535 {
             self.warn(ErrorType::PCOverflow)
@@ -22,6 +24,7 @@ impl Emitter for Assembler {
     }
 
     fn emit_byte(&mut self, b: u8) -> Result<(), Error> {
+        self.trace();
         let pc = self.context.offset_pc(1);
         if pc > 65535 {
             self.warn(ErrorType::PCOverflow)
@@ -32,9 +35,10 @@ impl Emitter for Assembler {
     }
 
     fn emit_word(&mut self, word: isize) -> Result<(), Error> {
-        if word < 0 || word > 65535 {
+        if !(0..=65535).contains(&word) {
             self.warn(ErrorType::WordTruncated);
         }
+        self.trace();
         let w = word as u16;
         let pc = self.context.offset_pc(2);
         if pc > 65535 {
@@ -46,11 +50,11 @@ impl Emitter for Assembler {
     }
 
     fn emit_instr(&mut self, prefix: Option<u8>, instr: u8, expr: &[Token], byte: bool) -> Result<(), Error> {
-        if prefix.is_some() {
-            self.emit_byte(prefix.unwrap())?;
+        if let Some(p) = prefix {
+            self.emit_byte(p)?;
         }
         self.emit_byte(instr)?;
-        let a = match self.expr.parse(&mut self.context, &mut expr.to_vec(), 0, 2, false) {
+        let a = match self.expr.parse(&mut self.context, &mut expr.to_vec()) {
             Ok(Some(addr)) => addr,
             Ok(None) => 0,
             Err(e) => return Err(self.context.error(e))
@@ -60,4 +64,13 @@ impl Emitter for Assembler {
         }
         self.emit_word(a)
     }
+
+    // Records the file and line of the instruction or directive being emitted in the debug file
+    fn trace(&mut self) {
+        if self.sld.recording() {
+            let address = self.context.asm_pc();
+            let page = self.bank.device().page_at(address);
+            self.sld.trace(&self.context, self.macro_source.as_ref(), page, address);
+        }
+    }
 }
\ No newline at end of file
@@ -1,11 +1,14 @@
 use std::fmt;
 use std::fmt::Formatter;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ErrorLevel {
+    Warning,
+    Error,
     Fatal,
 }
 
+#[derive(Clone)]
 pub struct Error {
     pub line_no: isize,
     pub message: String,
@@ -15,14 +18,31 @@ pub struct Error {
 
 
 impl Error {
-    pub fn fatal(message: &str, line_no: isize, file_name: &str) -> Error {
+    pub fn new(level: ErrorLevel, message: &str, line_no: isize, file_name: &str) -> Error {
         Error {
             line_no,
             message: message.to_string(),
-            level: ErrorLevel::Fatal,
+            level,
             file_name: file_name.to_string(),
         }
     }
+
+    pub fn fatal(message: &str, line_no: isize, file_name: &str) -> Error {
+        Error::new(ErrorLevel::Fatal, message, line_no, file_name)
+    }
+
+    #[allow(clippy::self_named_constructors)]
+    pub fn error(message: &str, line_no: isize, file_name: &str) -> Error {
+        Error::new(ErrorLevel::Error, message, line_no, file_name)
+    }
+
+    pub fn warning(message: &str, line_no: isize, file_name: &str) -> Error {
+        Error::new(ErrorLevel::Warning, message, line_no, file_name)
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        self.level == ErrorLevel::Fatal
+    }
 }
 
 impl fmt::Display for Error {
@@ -61,4 +81,54 @@ impl std::convert::From<std::io::Error> for Error {
             file_name: "FantASM".to_string(),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::assembler::assembler_options::AssemblerOptions;
+    use crate::assembler::test_support::{assemble, assemble_with, errors, temp_file};
+
+    #[test]
+    fn every_error_is_reported() {
+        let source = " ld a,(hl+1)\n nop\n jp\n ld q,1\n";
+        let (assembler, result) = assemble("errors", source);
+        match result {
+            Err(e) => assert_eq!(e.message, "Assembly failed with 3 error(s)"),
+            Ok(_) => panic!("expected errors"),
+        }
+        let lines: Vec<isize> = assembler.diagnostics.iter().map(|d| d.line_no).collect();
+        assert_eq!(lines, [1, 3, 4]);
+
+        let (assembler, result) = assemble_with("max_errors", source, |a| { a.max_errors(2); });
+        match result {
+            Err(e) => assert_eq!(e.message, "Too many errors, stopped after 2"),
+            Ok(_) => panic!("expected errors"),
+        }
+        assert_eq!(assembler.num_errors(), 2);
+    }
+
+    #[test]
+    fn errors_name_their_file() {
+        let include = temp_file("errors_include", "asm");
+        fs::write(&include, " nop\n ld q,1\n").unwrap();
+        let source = format!(" include \"{}\"\n jp\n", include.to_str().unwrap());
+        let (assembler, result) = assemble("errors_file", &source);
+        fs::remove_file(&include).ok();
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [(2, "Syntax error".to_string()), (2, "Syntax error".to_string())]);
+        assert_eq!(assembler.diagnostics[0].file_name, include.to_str().unwrap());
+        assert!(assembler.diagnostics[1].file_name.contains("errors_file"));
+    }
+
+    #[test]
+    fn missing_include_is_not_fatal() {
+        let (assembler, result) = assemble("errors_include_missing", " jp\n include \"missing_file.asm\"\n ld q,1\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (1, "Syntax error".to_string()),
+            (2, "File not found - missing_file.asm".to_string()),
+            (3, "Syntax error".to_string()),
+        ]);
+    }
+}
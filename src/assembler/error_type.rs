@@ -1,7 +1,8 @@
+use std::fmt;
+
 pub enum ErrorType {
     PCOverflow,
     InvalidLabel,
-    LabelNotFound,
     FileNotFound,
     SyntaxError,
     BadConstant,
@@ -27,7 +28,10 @@ pub enum ErrorType {
     //UnhandledDirective,
     DanglingEnd,
     EndIfWithoutIf,
+    IfWithoutEndIf,
     ElseWithoutIf,
+    ElseIfWithoutIf,
+    AfterElse(String),
     //ExpectedParenthesis,
 
     BadMacroName,
@@ -37,6 +41,7 @@ pub enum ErrorType {
     MacroParamCount,
     MacroLabel,
     MacroExists,
+    MacroTooDeep(usize),
 
     CodeSize,
     UnknownSizeOf,
@@ -56,11 +61,77 @@ pub enum ErrorType {
     StructMemberName,
     StructMemberSize,
     StructExists,
+
+    UndefinedSymbol(String),
+    MissingOperand(String),
+    UnexpectedInExpression(String),
+    OperandOutOfRange(String),
+    ExpressionOverflow(String),
+    DivideByZero(String),
+    ShiftOutOfRange(String),
+    BadDefine(String),
+    DefineWithoutValue(String),
+    UnusedLabel(String),
+    ValueNotConverged(usize, String),
+
+    RelativeJumpOutOfRange(isize, isize, isize),
+    JumpPromoted,
+
+    NexNotOpen,
+    NexAlreadyOpen,
+    NexBadCommand,
+    NexBadBank(isize),
+    NexBadScreen,
+    NexBankNotMapped(u8),
+
+    UnknownDevice,
+    NoDevice,
+    SlotOutOfRange(isize),
+    PageOutOfRange(isize),
+
+    SnapshotType,
+    SnapshotDevice,
+    SnapshotStack(isize),
+
+    NothingToSave,
+    BadTest,
+    UnknownRegister,
+    AssertWithoutTest,
+    TimingWithoutStart,
+    BadRepeatCount,
+    DanglingEndRepeat,
+    StringExpected,
+    AssertionFailed(String),
+    UserError(String),
+    UserWarning(String),
+    BadAlignment,
+    AlignPadding(isize, isize),
+    NestedPhase,
+    DephaseWithoutPhase,
+    PhaseWithoutDephase,
+    OrgInPhase,
+    SectionInPhase,
+    SectionNotPlaced(String),
+    SectionPlaced(String),
+    PlaceOutsideMemory,
+    EndMemoryWithoutMemory,
+    NestedMemory,
+    MemoryWithoutEndMemory,
+    BadSectionOption(String),
+    SectionOverflow(String, isize),
+    SectionOverlap(String, String),
+    BadModuleName,
+    EndModuleWithoutModule,
+    ModuleWithoutEndModule,
+    LocalOutsideMacro,
+
+    TooManyErrors(usize),
+    AssemblyFailed(usize),
 }
 
-impl ToString for ErrorType {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for ErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
             ErrorType::BadExpression => String::from("Invalid number or expression"),
             ErrorType::InvalidInstruction => String::from("Invalid instruction"),
             ErrorType::BitTruncated => String::from("Bit number is out of range will and will be truncated"),
@@ -71,7 +142,6 @@ impl ToString for ErrorType {
             ErrorType::BadConstant => String::from("Bad constant definition"),
             ErrorType::IntegerOutOfRange => String::from("Integer out of range"),
             ErrorType::FileNotFound => String::from("File not found"),
-            ErrorType::LabelNotFound => String::from("Undefined label or constant"),
             ErrorType::AddressTruncated => String::from("Address is out of range, the value has been truncated"),
             ErrorType::LabelOrConstantExists => String::from("Attempt to redefine label or constant"),
             ErrorType::InvalidCondition => String::from("Invalid condition"),
@@ -97,9 +167,13 @@ impl ToString for ErrorType {
             ErrorType::NonAscii => String::from("String contains non-ascii characters"),
             ErrorType::CodeSize => String::from("Maximum code size exceeded"),
             ErrorType::EndIfWithoutIf => String::from("ENDIF without IF"),
+            ErrorType::IfWithoutEndIf => String::from("IF without ENDIF"),
             ErrorType::ElseWithoutIf => String::from("ELSE without IF"),
+            ErrorType::ElseIfWithoutIf => String::from("ELSEIF without IF"),
+            ErrorType::AfterElse(d) => format!("{} after ELSE", d),
             ErrorType::UnknownSizeOf => String::from("SizeOf cannot be determined"),
             ErrorType::MacroExists => String::from("Macro already defined"),
+            ErrorType::MacroTooDeep(n) => format!("Macros called more than {} deep", n),
             ErrorType::EnumBadName => String::from("ENUM name expected"),
             ErrorType::EnumBadEnd => String::from("ENDE without ENUM"),
             ErrorType::EnumMemberName => String::from("Enum member name is invalid"),
@@ -109,6 +183,70 @@ impl ToString for ErrorType {
             ErrorType::StructMemberName => String::from("STRUCT member name is invalid"),
             ErrorType::StructMemberSize => String::from("STRUCT member size suffix is invalid"),
             ErrorType::StructExists => String::from("STRUCT already defined"),
-        }
+            ErrorType::UndefinedSymbol(s) => format!("Undefined label or constant '{}'", s),
+            ErrorType::MissingOperand(s) => if s.is_empty() {
+                String::from("Missing operand in expression")
+            } else {
+                format!("Missing operand for '{}'", s)
+            },
+            ErrorType::UnexpectedInExpression(s) => format!("Unexpected '{}' in expression", s),
+            ErrorType::OperandOutOfRange(s) => format!("Operand '{}' does not fit in 32 bits", s),
+            ErrorType::ExpressionOverflow(s) => format!("Integer overflow evaluating '{}'", s),
+            ErrorType::DivideByZero(s) => format!("Division by zero in '{}'", s),
+            ErrorType::ShiftOutOfRange(s) => format!("Shift count for '{}' must be between 0 and 31", s),
+            ErrorType::BadDefine(s) => format!("Invalid value for define '{}'", s),
+            ErrorType::DefineWithoutValue(s) => format!("Define '{}' has no value and is ignored, use name=value", s),
+            ErrorType::UnusedLabel(s) => format!("Unused label {}", s),
+            ErrorType::RelativeJumpOutOfRange(d, from, to) => format!("Relative jump out of range, distance {} from 0x{:04X} to 0x{:04X} (must be -128..127)", d, from, to),
+            ErrorType::JumpPromoted => String::from("Relative jump out of range, promoted to JP"),
+            ErrorType::NexNotOpen => String::from("SAVENEX OPEN expected"),
+            ErrorType::NexAlreadyOpen => String::from("A NEX file has already been opened"),
+            ErrorType::NexBadCommand => String::from("Expected SAVENEX OPEN, CORE, CFG, BANK, SCREEN or CLOSE"),
+            ErrorType::NexBadBank(n) => format!("Invalid NEX bank {}, must be 0..111", n),
+            ErrorType::NexBadScreen => String::from("Expected a NEX screen type L2, SCR, LR, SHR, SHC, L2_320 or L2_640"),
+            ErrorType::NexBankNotMapped(n) => format!("Bank {} is not mapped to a Z80 address", n),
+            ErrorType::UnknownDevice => String::from("Unknown device, expected ZXSPECTRUM48, ZXSPECTRUM128, ZXSPECTRUMNEXT or NONE"),
+            ErrorType::NoDevice => String::from("No paged memory DEVICE selected"),
+            ErrorType::SlotOutOfRange(n) => format!("Slot {} does not exist on this device", n),
+            ErrorType::PageOutOfRange(n) => format!("Page {} does not exist on this device", n),
+            ErrorType::SnapshotType => String::from("Snapshot file names must end in .sna or .z80"),
+            ErrorType::SnapshotDevice => String::from("Snapshots can only be written for 48K or 128K devices"),
+            ErrorType::SnapshotStack(n) => format!("The stack 0x{:04X} leaves no room in RAM to store the start address", n),
+            ErrorType::NothingToSave => String::from("No code has been assembled"),
+            ErrorType::BadTest => String::from("UNITTEST expects the label of the routine to test"),
+            ErrorType::UnknownRegister => String::from("Unknown register"),
+            ErrorType::AssertWithoutTest => String::from("Assertion does not follow a UNITTEST"),
+            ErrorType::TimingWithoutStart => String::from("TIMING END without a TIMING START"),
+            ErrorType::BadRepeatCount => String::from("REPT count must not be negative"),
+            ErrorType::DanglingEndRepeat => String::from("Encountered ENDR without REPT"),
+            ErrorType::StringExpected => String::from("Expected a string"),
+            ErrorType::AssertionFailed(m) => format!("Assertion failed: {}", m),
+            ErrorType::UserError(m) => m.to_string(),
+            ErrorType::UserWarning(m) => m.to_string(),
+            ErrorType::BadAlignment => String::from("Alignment must be between 1 and 65536"),
+            ErrorType::AlignPadding(n, w) => format!("ALIGN {} wasted {} byte(s)", n, w),
+            ErrorType::NestedPhase => String::from("PHASE inside PHASE, missing DEPHASE"),
+            ErrorType::DephaseWithoutPhase => String::from("DEPHASE without PHASE"),
+            ErrorType::PhaseWithoutDephase => String::from("PHASE without DEPHASE"),
+            ErrorType::OrgInPhase => String::from("ORG inside PHASE, missing DEPHASE"),
+            ErrorType::SectionInPhase => String::from("SECTION inside PHASE, missing DEPHASE"),
+            ErrorType::SectionNotPlaced(s) => format!("Section {} has no address, use PLACE or --section", s),
+            ErrorType::SectionPlaced(s) => format!("Section {} has already been placed", s),
+            ErrorType::PlaceOutsideMemory => String::from("PLACE outside of a MEMORY block"),
+            ErrorType::EndMemoryWithoutMemory => String::from("ENDMEMORY without MEMORY"),
+            ErrorType::NestedMemory => String::from("MEMORY inside MEMORY, missing ENDMEMORY"),
+            ErrorType::MemoryWithoutEndMemory => String::from("MEMORY without ENDMEMORY"),
+            ErrorType::BadSectionOption(s) => format!("Invalid section placement {}, expected name=address[,page]", s),
+            ErrorType::SectionOverflow(s, n) => format!("Section {} overflows the end of memory by {} byte(s)", s, n),
+            ErrorType::SectionOverlap(a, b) => format!("Section {} overlaps section {}", a, b),
+            ErrorType::BadModuleName => String::from("Invalid module name"),
+            ErrorType::EndModuleWithoutModule => String::from("ENDMODULE without MODULE"),
+            ErrorType::ModuleWithoutEndModule => String::from("MODULE without ENDMODULE"),
+            ErrorType::LocalOutsideMacro => String::from("LOCAL can only be used inside a macro"),
+            ErrorType::TooManyErrors(n) => format!("Too many errors, stopped after {}", n),
+            ErrorType::AssemblyFailed(n) => format!("Assembly failed with {} error(s)", n),
+            ErrorType::ValueNotConverged(n, s) => format!("Value did not converge after {} passes, unstable symbols: {}", n, s),
+        };
+        f.write_str(&message)
     }
 }
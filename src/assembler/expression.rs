@@ -1,107 +1,304 @@
-extern crate asciimath;
-
 use crate::assembler::assembler_context::AssemblerContext;
 use crate::assembler::constant::Constant;
 use crate::assembler::directive::sizeof::SizeOfHandler;
 use crate::assembler::error_type::ErrorType;
-use crate::assembler::ForwardReference;
 use crate::assembler::label::Label;
 use crate::assembler::token_traits::Tokens;
 use crate::assembler::tokens::{Op, Token};
-use crate::assembler::tokens::Functions::SizeOf;
-use crate::assembler::tokens::Token::{ConstLabel, Function, Number, Operator};
-
-use self::asciimath::{eval, scope};
+use crate::assembler::tokens::Functions::{Defined, SizeOf};
+use crate::assembler::tokens::Token::{ConstLabel, Function, IndirectExpression, Number, Operator};
 
 pub struct ExpressionParser {}
 
+// Precedence climbing evaluator over an already tokenised expression, all arithmetic is
+// performed on 32 bit signed integers and any overflow is reported as an error.
+struct Evaluator<'a> {
+    context: &'a mut AssemblerContext,
+    tokens: &'a [Token],
+    pos: usize,
+    last_op: String,
+}
+
+impl<'a> Evaluator<'a> {
+    fn binary_precedence(op: &Op) -> Option<u8> {
+        match op {
+            Op::LogicalOr => Some(1),
+            Op::LogicalAnd => Some(2),
+            Op::Pipe => Some(3),
+            Op::Caret => Some(4),
+            Op::Ampersand => Some(5),
+            Op::Equals | Op::Eq | Op::Ne => Some(6),
+            Op::Lt | Op::Gt | Op::Le | Op::Ge => Some(7),
+            Op::Shl | Op::Shr => Some(8),
+            Op::Add | Op::Sub => Some(9),
+            Op::Mul | Op::Div | Op::Mod => Some(10),
+            _ => None
+        }
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let t = self.tokens.get(self.pos);
+        self.pos += 1;
+        t
+    }
+
+    fn to_i32(value: isize, operand: &str) -> Result<i32, ErrorType> {
+        if value >= i32::MIN as isize && value <= i32::MAX as isize {
+            Ok(value as i32)
+        } else if value > 0 && value <= u32::MAX as isize {
+            Ok(value as u32 as i32)
+        } else {
+            Err(ErrorType::OperandOutOfRange(operand.to_string()))
+        }
+    }
+
+    fn expression(&mut self, min_precedence: u8) -> Result<i32, ErrorType> {
+        let mut lhs = self.unary()?;
+        while let Some(Operator(op)) = self.peek() {
+            let precedence = match Self::binary_precedence(op) {
+                Some(p) if p >= min_precedence => p,
+                _ => break
+            };
+            self.pos += 1;
+            self.last_op = op.to_string();
+            let rhs = self.expression(precedence + 1)?;
+            lhs = Self::apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i32, ErrorType> {
+        if let Some(Operator(op)) = self.peek() {
+            let op = op.clone();
+            match op {
+                Op::Sub | Op::Add | Op::Tilde | Op::Not => {
+                    self.pos += 1;
+                    self.last_op = op.to_string();
+                    let v = self.unary()?;
+                    return match op {
+                        Op::Sub => v.checked_neg().ok_or(ErrorType::ExpressionOverflow(op.to_string())),
+                        Op::Tilde => Ok(!v),
+                        Op::Not => Ok((v == 0) as i32),
+                        _ => Ok(v)
+                    };
+                }
+                _ => {}
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<i32, ErrorType> {
+        let token = match self.next() {
+            Some(t) => t,
+            None => return Err(ErrorType::MissingOperand(self.last_op.clone()))
+        };
+        match token {
+            Number(n) => Self::to_i32(*n, &token.to_string()),
+            Operator(Op::AsmPc) => Self::to_i32(self.context.asm_pc(), &token.to_string()),
+            ConstLabel(l) => {
+                if let Some(n) = self.context.get_constant(l) {
+                    Self::to_i32(n, l)
+                } else if let Some(n) = self.context.get_label(l) {
+                    Self::to_i32(n, l)
+                } else if let Some(n) = self.context.forward_reference(l) {
+                    Self::to_i32(n, l)
+                } else if self.context.final_pass {
+                    Err(ErrorType::UndefinedSymbol(l.to_string()))
+                } else {
+                    self.context.unresolved += 1;
+                    Ok(0)
+                }
+            }
+            Function(SizeOf(label)) => match self.context.get_size_of(label) {
+                Some(n) => Self::to_i32(n, label),
+                None => match self.context.prev_size_of.get(label) {
+                    Some(n) => Self::to_i32(*n, label),
+                    None if self.context.final_pass => Err(ErrorType::UnknownSizeOf),
+                    None => {
+                        self.context.unresolved += 1;
+                        Ok(0)
+                    }
+                }
+            },
+            Function(Defined(name)) => {
+                let defined = self.context.is_constant_defined(name) || self.context.is_label_defined(name);
+                Ok(defined as i32)
+            }
+            IndirectExpression(inner) => {
+                let mut nested = Evaluator { context: self.context, tokens: inner.as_slice(), pos: 0, last_op: "(".to_string() };
+                nested.evaluate()
+            }
+            Operator(Op::LParens) => {
+                self.last_op = "(".to_string();
+                let v = self.expression(0)?;
+                match self.next() {
+                    Some(Operator(Op::RParens)) => Ok(v),
+                    _ => Err(ErrorType::UnclosedParentheses)
+                }
+            }
+            Operator(Op::RParens) => Err(ErrorType::UnexpectedClose),
+            Operator(op) if Self::binary_precedence(op).is_some() => Err(ErrorType::MissingOperand(op.to_string())),
+            _ => Err(ErrorType::UnexpectedInExpression(token.to_string()))
+        }
+    }
+
+    fn apply(op: &Op, lhs: i32, rhs: i32) -> Result<i32, ErrorType> {
+        let overflow = || ErrorType::ExpressionOverflow(op.to_string());
+        match op {
+            Op::Add => lhs.checked_add(rhs).ok_or_else(overflow),
+            Op::Sub => lhs.checked_sub(rhs).ok_or_else(overflow),
+            Op::Mul => lhs.checked_mul(rhs).ok_or_else(overflow),
+            Op::Div | Op::Mod => {
+                if rhs == 0 {
+                    return Err(ErrorType::DivideByZero(op.to_string()));
+                }
+                if *op == Op::Div { lhs.checked_div(rhs) } else { lhs.checked_rem(rhs) }.ok_or_else(overflow)
+            }
+            Op::Shl | Op::Shr => {
+                if !(0..32).contains(&rhs) {
+                    return Err(ErrorType::ShiftOutOfRange(op.to_string()));
+                }
+                Ok(if *op == Op::Shl { lhs << rhs } else { lhs >> rhs })
+            }
+            Op::Ampersand => Ok(lhs & rhs),
+            Op::Pipe => Ok(lhs | rhs),
+            Op::Caret => Ok(lhs ^ rhs),
+            Op::Lt => Ok((lhs < rhs) as i32),
+            Op::Gt => Ok((lhs > rhs) as i32),
+            Op::Le => Ok((lhs <= rhs) as i32),
+            Op::Ge => Ok((lhs >= rhs) as i32),
+            Op::Equals | Op::Eq => Ok((lhs == rhs) as i32),
+            Op::Ne => Ok((lhs != rhs) as i32),
+            Op::LogicalAnd => Ok((lhs != 0 && rhs != 0) as i32),
+            Op::LogicalOr => Ok((lhs != 0 || rhs != 0) as i32),
+            _ => Err(ErrorType::UnexpectedInExpression(op.to_string()))
+        }
+    }
+
+    fn evaluate(&mut self) -> Result<i32, ErrorType> {
+        if self.tokens.is_empty() {
+            return Err(ErrorType::BadExpression);
+        }
+        let v = self.expression(0)?;
+        match self.peek() {
+            None => Ok(v),
+            Some(Operator(Op::RParens)) => Err(ErrorType::UnexpectedClose),
+            Some(t) => Err(ErrorType::UnexpectedInExpression(t.to_string()))
+        }
+    }
+}
+
 impl ExpressionParser {
     pub fn new() -> ExpressionParser {
         ExpressionParser {}
     }
 
-    pub fn get_expression(&mut self, context: &mut AssemblerContext, tokens: &mut Vec<Token>) -> (bool, Vec<Token>) {
+    pub fn get_expression(&mut self, context: &mut AssemblerContext, tokens: &mut Vec<Token>) -> Vec<Token> {
         let mut expr = vec![];
-        let mut has_forward_ref = false;
         while tokens.last().unwrap_or(&Token::None).is_expression() {
             if let Some(mut t) = tokens.pop() {
                 if t == Operator(Op::AsmPc) {
                     t = Number(context.asm_pc())
                 }
-                expr.push(t.clone());
-            }
-            let last = expr.last().unwrap_or(&Token::None).clone();
-            if let ConstLabel(l) = last {
-                if l.to_lowercase().eq(&"asmpc".to_string()) {
-                    expr.pop();
-                    expr.push(Number(context.asm_pc()));
-                } else if !context.is_constant_defined(&l) && !context.is_label_defined(&l) {
-                    has_forward_ref = true;
-                }
-            } else if let Function(SizeOf(label)) = last {
-                if !context.is_label_defined(&label) {
-                    has_forward_ref = true;
+                if let ConstLabel(l) = &t {
+                    if l.to_lowercase().eq(&"asmpc".to_string()) {
+                        t = Number(context.asm_pc());
+                    }
                 }
+                expr.push(t);
             }
         }
-        (has_forward_ref, expr)
+        expr
     }
 
     pub fn eval(&self, context: &mut AssemblerContext, expr: &mut Vec<Token>) -> Result<isize, ErrorType> {
-        let mut strings = vec![];
-        for token in expr {
-            match &token {
-                Function(SizeOf(label)) => if let Some(size) = context.get_size_of(label) {
-                    strings.push(format!("{}", size));
-                } else {
-                    return Err(ErrorType::UnknownSizeOf);
-                }
-                ConstLabel(l) => {
-                    if let Some(n) = context.get_constant(l) {
-                        strings.push(format!("{}", n));
-                    } else if let Some(n) = context.get_label(l) {
-                        context.mark_label_used(l);
-                        strings.push(format!("{}", n));
-                    } else {
-                        return Err(ErrorType::BadConstant);
-                    }
-                }
-                Number(_) | Operator(_) => strings.push(token.to_string()),
-                _ => return Err(ErrorType::BadExpression)
-            }
-        }
-        let string_expr = strings.join("");
-        match eval(&string_expr, &scope! {}) {
-            Ok(r) => {
-                Ok(r as isize)
+        // Nothing but - or + refers to an anonymous label
+        for op in &[Op::Sub, Op::Add] {
+            if !expr.is_empty() && expr.iter().all(|t| *t == Operator(op.clone())) {
+                let name = op.to_string().repeat(expr.len());
+                return self.eval(context, &mut vec![ConstLabel(name)]);
             }
-            Err(_e) => return Err(ErrorType::BadExpression),
         }
+        let mut evaluator = Evaluator { context, tokens: expr.as_slice(), pos: 0, last_op: String::new() };
+        Ok(evaluator.evaluate()? as isize)
     }
 
-    pub fn parse(&mut self, context: &mut AssemblerContext, tokens: &mut Vec<Token>, offset: isize, count: isize, is_relative: bool) -> Result<Option<isize>, ErrorType> {
-        let (has_forward_ref, mut expr) = self.get_expression(context, tokens);
-        if has_forward_ref && count < 0 {
-            return Err(ErrorType::BadConstant);
-        }
-        if has_forward_ref {
-            let fw = ForwardReference {
-                is_expression: true,
-                pc: context.offset_pc(offset),
-                label: context.label_context.clone(),
-                expression: expr,
-                is_relative,
-                byte_count: count,
-                line_no: context.current_line_number(),
-                file_name: context.current_file_name().to_string(),
-            };
-            context.add_forward_ref(fw);
-            return Ok(Some(0));
+    pub fn parse(&mut self, context: &mut AssemblerContext, tokens: &mut Vec<Token>) -> Result<Option<isize>, ErrorType> {
+        let mut expr = self.get_expression(context, tokens);
+        if expr.is_empty() {
+            return Ok(None);
         }
+        Ok(Some(self.eval(context, expr.as_mut())?))
+    }
+}
 
-        match self.eval(context, expr.as_mut()) {
-            Ok(n) => Ok(Some(n)),
-            Err(e) => return Err(e),
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_context::AssemblerContext;
+    use crate::assembler::error_type::ErrorType;
+    use crate::assembler::expression::ExpressionParser;
+    use crate::assembler::token_reader::{DELIMITERS, OPERATORS};
+    use crate::assembler::TokenReader;
+
+    fn eval(text: &str) -> Result<isize, ErrorType> {
+        let mut reader = TokenReader::new(text.as_bytes());
+        reader.delimiters(DELIMITERS).operators(OPERATORS);
+        let mut tokens = reader.read_line().ok().unwrap();
+        ExpressionParser::new().eval(&mut AssemblerContext::default(), &mut tokens)
+    }
+
+    fn value(text: &str) -> isize {
+        match eval(text) {
+            Ok(n) => n,
+            Err(e) => panic!("'{}' failed: {}", text, e),
         }
     }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(value("2+3*4"), 14);
+        assert_eq!(value("(2+3)*4"), 20);
+        assert_eq!(value("10-4-3"), 3);
+        assert_eq!(value("100/10/5"), 2);
+        assert_eq!(value("1+2<<3"), 24);
+        assert_eq!(value("1|6&3"), 3);
+        assert_eq!(value("5^1|2"), 6);
+        assert_eq!(value("1+1==2"), 1);
+        assert_eq!(value("2<3&&3<2"), 0);
+        assert_eq!(value("0||7>6"), 1);
+        assert_eq!(value("17%5*2"), 4);
+        assert_eq!(value("-2*3"), -6);
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(matches!(eval("2147483647+1"), Err(ErrorType::ExpressionOverflow(_))));
+        assert!(matches!(eval("65536*65536"), Err(ErrorType::ExpressionOverflow(_))));
+        assert!(matches!(eval("4294967296"), Err(ErrorType::OperandOutOfRange(_))));
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        assert!(matches!(eval("1/0"), Err(ErrorType::DivideByZero(_))));
+        assert!(matches!(eval("1%(2-2)"), Err(ErrorType::DivideByZero(_))));
+    }
+
+    #[test]
+    fn shift_range() {
+        assert_eq!(value("1<<31"), i32::MIN as isize);
+        assert_eq!(value("256>>8"), 1);
+        assert!(matches!(eval("1<<32"), Err(ErrorType::ShiftOutOfRange(_))));
+        assert!(matches!(eval("1>>-1"), Err(ErrorType::ShiftOutOfRange(_))));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(matches!(eval("1+"), Err(ErrorType::MissingOperand(_))));
+        assert!(matches!(eval("*2"), Err(ErrorType::MissingOperand(_))));
+    }
 }
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+
+// Where source files, includes and binaries are read from, the default reads from disk
+pub trait FileProvider {
+    fn exists(&self, path: &str) -> bool;
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error>;
+}
+
+pub struct DiskFiles;
+
+impl FileProvider for DiskFiles {
+    fn exists(&self, path: &str) -> bool {
+        Path::new(path).exists()
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let mut data = vec![];
+        File::open(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+// Files held in memory, paths are matched without any leading ./
+#[derive(Default)]
+pub struct MemoryFiles {
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryFiles {
+    pub fn new() -> MemoryFiles {
+        Default::default()
+    }
+
+    pub fn add(&mut self, path: &str, data: &[u8]) -> &mut MemoryFiles {
+        self.files.insert(MemoryFiles::normalise(path), data.to_vec());
+        self
+    }
+
+    fn normalise(path: &str) -> String {
+        let mut path = path;
+        while let Some(p) = path.strip_prefix("./") {
+            path = p;
+        }
+        path.to_string()
+    }
+}
+
+impl FileProvider for MemoryFiles {
+    fn exists(&self, path: &str) -> bool {
+        self.files.contains_key(&MemoryFiles::normalise(path))
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        match self.files.get(&MemoryFiles::normalise(path)) {
+            Some(data) => Ok(data.clone()),
+            None => Err(Error::fatal(&ErrorType::FileNotFound.to_string(), -1, path))
+        }
+    }
+}
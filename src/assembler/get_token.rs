@@ -6,44 +6,50 @@ use crate::assembler::error_type::ErrorType;
 use crate::assembler::tokens::Token;
 
 pub trait GetToken {
-    fn relative(&mut self) -> Result<u8, Error>;
-    fn expect_byte(&mut self, instr_size: isize) -> Result<isize, Error>;
-    fn expect_word(&mut self, instr_size: isize) -> Result<isize, Error>;
-    fn expect_number_in_range(&mut self, range: Range<isize>, count: isize, error_type: ErrorType, instr_size: isize) -> Result<isize, Error>;
+    fn relative(&mut self, target: isize) -> Result<u8, Error>;
+    fn index_offset(&mut self, expr: &[Token]) -> Result<u8, Error>;
+    fn expect_byte(&mut self) -> Result<isize, Error>;
+    fn expect_word(&mut self) -> Result<isize, Error>;
+    fn expect_number_in_range(&mut self, range: Range<isize>, error_type: ErrorType) -> Result<isize, Error>;
     fn take_token(&mut self) -> Result<Token, Error>;
     fn next_token_is(&mut self, tok: &Token) -> bool;
     fn expect_token(&mut self, tok: Token) -> Result<(), Error>;
 }
 
 impl GetToken for Assembler {
-    fn relative(&mut self) -> Result<u8, Error> {
-        let addr = match self.expr.parse(&mut self.context, &mut self.tokens, 1, 1, true) {
-            Ok(Some(n)) => n,
-            Ok(None) => 0,
-            Err(e) => return Err(self.context.error(e)),
-        };
-        let pc = (self.context.offset_pc(2)) as isize;
-        Ok((addr - pc) as u8)
+    fn relative(&mut self, target: isize) -> Result<u8, Error> {
+        let offset = target - self.context.offset_pc(2);
+        if !(-128..128).contains(&offset) && self.context.final_pass {
+            // Always fatal, a relative jump can't be assembled to anywhere else
+            let message = ErrorType::RelativeJumpOutOfRange(offset, self.context.asm_pc(), target).to_string();
+            return Err(Error::fatal(&message, self.context.current_line_number(), &self.context.current_file_name()));
+        }
+        Ok(offset as u8)
+    }
+
+    fn index_offset(&mut self, expr: &[Token]) -> Result<u8, Error> {
+        let offset = self.expr.eval(&mut self.context, &mut expr.to_vec());
+        Ok(self.context.result(offset)? as u8)
     }
 
-    fn expect_byte(&mut self, instr_size: isize) -> Result<isize, Error> {
-        self.expect_number_in_range(0..256, 1, ErrorType::ByteTruncated, instr_size)
+    fn expect_byte(&mut self) -> Result<isize, Error> {
+        self.expect_number_in_range(0..256, ErrorType::ByteTruncated)
     }
 
-    fn expect_word(&mut self, instr_size: isize) -> Result<isize, Error> {
-        self.expect_number_in_range(0..65536, 2, ErrorType::WordTruncated, instr_size)
+    fn expect_word(&mut self) -> Result<isize, Error> {
+        self.expect_number_in_range(0..65536, ErrorType::WordTruncated)
     }
 
-    fn expect_number_in_range(&mut self, range: Range<isize>, count: isize, error_type: ErrorType, instr_size: isize) -> Result<isize, Error> {
-        match self.expr.parse(&mut self.context, &mut self.tokens, instr_size, count, false) {
+    fn expect_number_in_range(&mut self, range: Range<isize>, error_type: ErrorType) -> Result<isize, Error> {
+        match self.expr.parse(&mut self.context, &mut self.tokens) {
             Ok(Some(n)) => {
                 if !range.contains(&n) {
                     self.warn(error_type);
                 }
                 Ok(n)
             }
-            Ok(None) => return Err(self.context.error(ErrorType::SyntaxError)),
-            Err(e) => return Err(self.context.error(e))
+            Ok(None) => Err(self.context.error(ErrorType::SyntaxError)),
+            Err(e) => Err(self.context.error(e))
         }
     }
 
@@ -7,7 +7,7 @@ use crate::assembler::get_token::GetToken;
 use crate::assembler::reg_pair::HighLow;
 use crate::assembler::reg_pair::RegPairValue;
 use crate::assembler::token_traits::Tokens;
-use crate::assembler::tokens::{AluOp, Cnd, Ir, Op, Reg, RegPairInd, RotOp, Token};
+use crate::assembler::tokens::{AluOp, Cnd, Ir, Reg, RegPairInd, RotOp, Token};
 use crate::assembler::tokens::Del::Comma;
 use crate::assembler::tokens::Op::{LParens, RParens};
 use crate::assembler::tokens::Reg::_HL_;
@@ -25,6 +25,7 @@ pub(crate) trait InstructionEncoder {
     fn inc_dec(&mut self, q: u8) -> Result<(), Error>;
     fn io_op(&mut self, y: u8) -> Result<(), Error>;
     fn jr(&mut self, djnz: bool) -> Result<(), Error>;
+    fn promote_jump(&mut self, target: isize, resolved: bool) -> bool;
     fn push_pop(&mut self, z: u8) -> Result<(), Error>;
     fn ret(&mut self) -> Result<(), Error>;
     fn rot(&mut self, a: RotOp) -> Result<(), Error>;
@@ -45,17 +46,16 @@ impl InstructionEncoder for Assembler {
         let size = self.context.result(self.bank.emit_prefix(&tok))?;
         self.context.pc_add(size);
         match tok {
-            IndexIndirect(_, n) => if let Ok(byte) = self.expr.eval(&mut self.context, &mut n.clone()) {
-                self.emit(&[alu!(a, Reg::_HL_ as u8), byte as u8])
-            } else {
-                Err(self.context.error(ErrorType::BadExpression))
+            IndexIndirect(_, n) => {
+                let byte = self.index_offset(&n)?;
+                self.emit(&[alu!(a, Reg::_HL_ as u8), byte])
             }
             RegisterIX(r) => self.emit_byte(alu!(a, r as u8)),
             RegisterIY(r) => self.emit_byte(alu!(a, r as u8)),
             Register(r) => self.emit_byte(alu!(a, r as u8)),
             _ => {
                 self.tokens.push(tok);
-                let b = self.expect_byte(1)? as u8;
+                let b = self.expect_byte()? as u8;
                 self.emit(&[alu_imm!(a), b])
             }
         }
@@ -93,22 +93,20 @@ impl InstructionEncoder for Assembler {
             (RegisterPair(_), ConstLabel(_), false) => return Err(self.context.error(ErrorType::Z80NDisabled)),
 
             (RegisterPair(rp), Register(Reg::A), true) => return self.emit(&[0xED, 0x31 + rp.nrp()?]),
-            (RegisterPair(rp), _, true) => {
-                if rhs.is_expression() {
-                    self.tokens.push(rhs.clone());
-                    self.emit(&[0xED, 0x34 + rp.nrp()?])?;
-                    let addr = self.expect_word(0)?;
-                    return self.emit_word(addr);
-                }
+            (RegisterPair(rp), _, true) if rhs.is_expression() => {
+                self.tokens.push(rhs.clone());
+                self.emit(&[0xED, 0x34 + rp.nrp()?])?;
+                let addr = self.expect_word()?;
+                return self.emit_word(addr);
             }
             _ => {}
         }
-        return Err(self.context.error(ErrorType::InvalidInstruction));
+        Err(self.context.error(ErrorType::InvalidInstruction))
     }
 
     fn bit_res_set(&mut self, x: u8) -> Result<(), Error> {
-        let bit = self.expect_byte(1)?;
-        if bit < 0 || bit > 7 {
+        let bit = self.expect_byte()?;
+        if !(0..=7).contains(&bit) {
             self.warn(ErrorType::BitTruncated);
         }
         self.expect_token(Delimiter(Comma))?;
@@ -122,25 +120,19 @@ impl InstructionEncoder for Assembler {
                 if self.next_token_is(&Delimiter(Comma)) {
                     self.tokens.pop();
                     if let Register(r) = self.take_token()? {
-                        if let Ok(byte) = self.expr.eval(&mut self.context, &mut n.clone()) {
-                            self.emit(&[0xCB, byte as u8, xyz!(x, bit as u8, r as u8)])?
-                        } else {
-                            return Err(self.context.error(ErrorType::BadExpression));
-                        }
+                        let byte = self.index_offset(&n)?;
+                        self.emit(&[0xCB, byte, xyz!(x, bit as u8, r as u8)])?
                     } else {
                         return Err(self.context.error(ErrorType::RegisterExpected));
                     }
                 } else {
-                    if let Ok(byte) = self.expr.eval(&mut self.context, &mut n.clone()) {
-                        self.emit(&[0xCB, byte as u8, xyz!(x, bit as u8, _HL_ as u8)])?
-                    } else {
-                        return Err(self.context.error(ErrorType::BadExpression));
-                    }
+                    let byte = self.index_offset(&n)?;
+                    self.emit(&[0xCB, byte, xyz!(x, bit as u8, _HL_ as u8)])?
                 }
             }
-            RegisterIX(_) => self.emit(&[0xCb, xyz!(x, bit as u8, _HL_ as u8)])?,
-            RegisterIY(_) => self.emit(&[0xCb, xyz!(x, bit as u8, _HL_ as u8)])?,
-            Register(r) => self.emit(&[0xCb, xyz!(x, bit as u8, r as u8)])?,
+            RegisterIX(_) => self.emit(&[0xCB, xyz!(x, bit as u8, _HL_ as u8)])?,
+            RegisterIY(_) => self.emit(&[0xCB, xyz!(x, bit as u8, _HL_ as u8)])?,
+            Register(r) => self.emit(&[0xCB, xyz!(x, bit as u8, r as u8)])?,
             _ => return Err(self.context.error(ErrorType::InvalidInstruction))
         };
         Ok(())
@@ -157,19 +149,25 @@ impl InstructionEncoder for Assembler {
             instr = xpqz!(3, 0, q, z);
         }
 
-        let addr = self.expect_word(1)?;
+        let addr = self.expect_word()?;
         self.emit(&[instr, addr.lo(), addr.hi()])
     }
 
     fn jp(&mut self) -> Result<(), Error> {
         if let Some(bytes) = match self.tokens.last() {
             Some(IndexIndirect(i, _)) => {
-                let ixy = (*i as u8) - 4 << 5;
+                let ixy = ((*i as u8) - 4) << 5;
                 Some(vec![0xDD | ixy, xpqz!(3, 2, 1, 1)])
             }
             Some(Register(_HL_)) => {
                 Some(vec![xpqz!(3, 2, 1, 1)])
             }
+            Some(RegisterIndirect(RegPairInd::C)) => {
+                if !self.z80n_enabled {
+                    return Err(self.context.error(ErrorType::Z80NDisabled));
+                }
+                Some(vec![0xED, 0x98])
+            }
             _ => None
         } {
             self.tokens.pop();
@@ -182,7 +180,7 @@ impl InstructionEncoder for Assembler {
         let lhs = &self.take_token()?;
         self.expect_token(Delimiter(Comma))?;
         let rhs = &self.take_token()?;
-        let n = self.context.result(self.bank.emit_prefix(&rhs))?;
+        let n = self.context.result(self.bank.emit_prefix(rhs))?;
         self.context.pc_add(n);
 
         match (lhs, rhs) {
@@ -220,11 +218,8 @@ impl InstructionEncoder for Assembler {
 
         match tok {
             IndexIndirect(_reg, n) => {
-                if let Ok(byte) = self.expr.eval(&mut self.context, &mut n.clone()) {
-                    self.emit(&[xyz!(0, _HL_ as u8, q + 4), byte as u8])
-                } else {
-                    return Err(self.context.error(ErrorType::BadExpression));
-                }
+                let byte = self.index_offset(&n)?;
+                self.emit(&[xyz!(0, _HL_ as u8, q + 4), byte])
             }
             RegisterPair(Ix) => self.emit_byte(xpqz!(0, 2, q, 3)),
             RegisterPair(Iy) => self.emit_byte(xpqz!(0, 2, q, 3)),
@@ -269,25 +264,49 @@ impl InstructionEncoder for Assembler {
 
     fn jr(&mut self, djnz: bool) -> Result<(), Error> {
         let token = self.tokens.last().unwrap_or(&Token::EndOfFile).clone();
-        match token {
-            Operator(Op::AsmPc) | Number(_) | ConstLabel(_) => {
-                let offset = self.relative()?;
-                if djnz {
-                    return self.emit(&[0x10, offset]);
-                }
-                return self.emit(&[xyz!(0, 3, 0), offset]);
-            }
+        let opcode = match token {
+            Operator(_) | Number(_) | ConstLabel(_) => if djnz { 0x10 } else { xyz!(0, 3, 0) },
             Condition(c) => match &c {
                 Cnd::Z | Cnd::C | Cnd::Nz | Cnd::NC => {
                     self.take_token()?;
                     self.expect_token(Delimiter(Comma))?;
-                    let offset = self.relative()?;
-                    return self.emit(&[xyz!(0, c.clone() as u8 + 4, 0), offset]);
+                    xyz!(0, c as u8 + 4, 0)
                 }
-                _ => Err(self.context.error(ErrorType::InvalidCondition))
+                _ => return Err(self.context.error(ErrorType::InvalidCondition))
             }
-            _ => Err(self.context.error(ErrorType::SyntaxError))
+            _ => return Err(self.context.error(ErrorType::SyntaxError))
+        };
+        let unresolved = self.context.unresolved;
+        let target = self.expect_word()?;
+        if !djnz && self.promote_jump(target, unresolved == self.context.unresolved) {
+            let jp = match opcode {
+                0x18 => 0xC3,
+                _ => xyz!(3, (opcode >> 3) - 4, 2)
+            };
+            return self.emit(&[jp, target.lo(), target.hi()]);
         }
+        let offset = self.relative(target)?;
+        self.emit(&[opcode, offset])
+    }
+
+    // Keyed by source location so a JR added or removed by IF or REPT in a later pass can't move a promotion
+    fn promote_jump(&mut self, target: isize, resolved: bool) -> bool {
+        let line = (self.context.current_file_name(), self.context.current_line_number());
+        let count = self.relative_jumps.entry(line.clone()).or_insert(0);
+        let id = (line.0, line.1, *count);
+        *count += 1;
+        if !self.jr_promote {
+            return false;
+        }
+        if !self.promoted_jumps.contains(&id) {
+            let offset = target - self.context.offset_pc(2);
+            if !resolved || (-128..128).contains(&offset) {
+                return false;
+            }
+            self.promoted_jumps.insert(id);
+        }
+        self.warn(ErrorType::JumpPromoted);
+        true
     }
 
     fn push_pop(&mut self, z: u8) -> Result<(), Error> {
@@ -298,7 +317,7 @@ impl InstructionEncoder for Assembler {
             RegisterPair(r) => self.emit_byte(xpqz!(3, r.rp2()?, 0, z)),
             _ => if self.z80n_enabled {
                 self.tokens.push(tok);
-                let n = self.expect_word(2)?;
+                let n = self.expect_word()?;
                 self.emit(&[0xED, 0x8A, n.hi(), n.lo()])
             } else {
                 Err(self.context.error(ErrorType::InvalidInstruction))
@@ -307,10 +326,10 @@ impl InstructionEncoder for Assembler {
     }
 
     fn ret(&mut self) -> Result<(), Error> {
-        if self.tokens.len() > 0 {
+        if !self.tokens.is_empty() {
             let tok = self.take_token()?;
             if let Condition(c) = tok {
-                return self.emit_byte(xyz!(3, c.clone() as u8, 0));
+                return self.emit_byte(xyz!(3, c as u8, 0));
             }
             self.tokens.push(tok);
         }
@@ -326,22 +345,16 @@ impl InstructionEncoder for Assembler {
                 if self.next_token_is(&Delimiter(Comma)) {
                     self.tokens.pop();
                     if let Register(r) = self.take_token()? {
-                        if let Ok(byte) = self.expr.eval(&mut self.context, &mut n.clone()) {
-                            return self.emit(&[0xCB, byte as u8, rot_encode!(a, r.clone() as u8)]);
-                        } else {
-                            return Err(self.context.error(ErrorType::BadExpression));
-                        }
+                        let byte = self.index_offset(&n)?;
+                        return self.emit(&[0xCB, byte, rot_encode!(a, r.clone() as u8)]);
                     }
                 } else {
-                    if let Ok(byte) = self.expr.eval(&mut self.context, &mut n.clone()) {
-                        return self.emit(&[0xCB, byte as u8, rot_encode!(a, Reg::_HL_ as u8)]);
-                    } else {
-                        return Err(self.context.error(ErrorType::BadExpression));
-                    }
+                    let byte = self.index_offset(&n)?;
+                    return self.emit(&[0xCB, byte, rot_encode!(a, Reg::_HL_ as u8)]);
                 }
                 Err(self.context.error(ErrorType::SyntaxError))
             }
-            Register(r) => return self.emit(&[0xCB, rot_encode!(a, r.clone() as u8)]),
+            Register(r) => self.emit(&[0xCB, rot_encode!(a, r.clone() as u8)]),
             _ => Err(self.context.error(ErrorType::SyntaxError))
         }
     }
@@ -363,9 +376,7 @@ impl InstructionEncoder for Assembler {
         self.expect_token(Delimiter(Comma))?;
         let rhs = self.indirect_expression()?;
 
-        if self.context.result(self.bank.emit_prefix(&lhs))? == 1 {
-            self.context.pc_add(1);
-        } else if self.context.result(self.bank.emit_prefix(&rhs))? == 1 {
+        if self.context.result(self.bank.emit_prefix(&lhs))? == 1 || self.context.result(self.bank.emit_prefix(&rhs))? == 1 {
             self.context.pc_add(1);
         }
 
@@ -417,32 +428,20 @@ impl InstructionEncoder for Assembler {
             (IndirectExpression(tokens), RegisterPair(r)) => self.emit_instr(Some(0xED), xpqz!(1, r.rp1()?, 0, 3), tokens.as_slice(), false),
 
             (Register(r), IndexIndirect(_reg, o)) => {
-                if let Ok(byte) = self.expr.eval(&mut self.context, &mut o.clone()) {
-                    self.emit(&[xyz!(1, r.clone() as u8, Reg::_HL_ as u8), byte as u8])
-                } else {
-                    Err(self.context.error(ErrorType::BadExpression))
-                }
+                let byte = self.index_offset(o)?;
+                self.emit(&[xyz!(1, r.clone() as u8, Reg::_HL_ as u8), byte])
             }
 
             (IndexIndirect(_rp, i), Number(n)) => {
-                if let Ok(byte) = self.expr.eval(&mut self.context, &mut i.clone()) {
-                    self.emit(&[0x36, byte as u8, n.clone() as u8])
-                } else {
-                    Err(self.context.error(ErrorType::BadExpression))
-                }
+                let byte = self.index_offset(i)?;
+                self.emit(&[0x36, byte, *n as u8])
             }
             (IndexIndirect(_rp, o), Register(r)) => {
-                if let Ok(byte) = self.expr.eval(&mut self.context, &mut o.clone()) {
-                    self.emit(&[xyz!(1, Reg::_HL_ as u8, r.clone() as u8), byte as u8])
-                } else {
-                    Err(self.context.error(ErrorType::BadExpression))
-                }
+                let byte = self.index_offset(o)?;
+                self.emit(&[xyz!(1, Reg::_HL_ as u8, r.clone() as u8), byte])
             }
 
-            _ => {
-                println!("load_indirect: {:?},{:?}", dst, src);
-                Err(self.context.error(ErrorType::InvalidInstruction))
-            }
+            _ => Err(self.context.error(ErrorType::InvalidInstruction))
         }
     }
 
@@ -458,7 +457,7 @@ impl InstructionEncoder for Assembler {
         } else if src.is_expression() {
             self.tokens.push(src.clone());
             self.emit_byte(xyz!(0, r, 6))?;
-            let addr = self.expect_byte(0)?;
+            let addr = self.expect_byte()?;
             return self.emit_byte(addr as u8);
         }
         Err(self.context.error(ErrorType::SyntaxError))
@@ -472,7 +471,7 @@ impl InstructionEncoder for Assembler {
         } else {
             self.emit_byte(xpqz!(0, rp, 0, 1))?;
         }
-        let addr = self.expect_word(0)?;
+        let addr = self.expect_word()?;
         self.emit_word(addr)
     }
 
@@ -480,11 +479,11 @@ impl InstructionEncoder for Assembler {
         match (dst, src) {
             (RegisterPair(Sp), ConstLabel(l)) => {
                 self.tokens.push(ConstLabel(l.to_string()));
-                let addr = self.expect_word(1)?;
+                let addr = self.expect_word()?;
                 return self.emit(&[xpqz!(0, 3, 0, 1), addr.lo(), addr.hi()]);
             }
             (RegisterPair(Sp), Number(n)) => if (0..65536).contains(n) {
-                let addr = n.clone() as u16;
+                let addr = *n as u16;
                 return self.emit(&[xpqz!(0, 3, 0, 1), addr.lo(), addr.hi()]);
             } else {
                 return Err(self.context.error(ErrorType::IntegerOutOfRange));
@@ -534,20 +533,20 @@ impl InstructionEncoder for Assembler {
         if !self.z80n_enabled {
             return Err(self.context.error(ErrorType::Z80NDisabled));
         }
-        let reg = self.expect_byte(2)? as u8;
+        let reg = self.expect_byte()? as u8;
         self.expect_token(Delimiter(Comma))?;
         if let Some(Register(Reg::A)) = self.tokens.last() {
             self.tokens.pop();
             return self.emit(&[0xED, 0x92, reg]);
         }
-        let n = self.expect_byte(2)? as u8;
-        self.emit(&[0xED, 0x91, reg, n as u8])
+        let n = self.expect_byte()? as u8;
+        self.emit(&[0xED, 0x91, reg, n])
     }
 
     fn indirect_expression(&mut self) -> Result<Token, Error> {
         let lhs = self.take_token()?;
         if lhs == Operator(LParens) {
-            let (_, mut tokens) = self.expr.get_expression(&mut self.context, &mut self.tokens);
+            let mut tokens = self.expr.get_expression(&mut self.context, &mut self.tokens);
             match tokens.last() {
                 Some(Operator(RParens)) => tokens.pop(),
                 _ => return Err(self.context.error(ErrorType::UnclosedParentheses))
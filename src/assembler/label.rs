@@ -1,19 +1,20 @@
-use std::cmp::max;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::BufWriter;
 use std::path::Path;
 
-use pad::PadStr;
-
 use crate::assembler::assembler_context::AssemblerContext;
 use crate::assembler::error::Error;
 use crate::assembler::error_type::ErrorType;
+use crate::assembler::symbols::{Symbol, SymbolFormat, SymbolKind};
 
 pub trait Label {
     fn add_label(&mut self, name: String, global: bool) -> Result<(), Error>;
+    fn add_temp_label(&mut self, key: &str);
+    fn qualified_name(&self, name: &str) -> String;
     fn get_label(&mut self, name: &str) -> Option<isize>;
     fn is_label_defined(&self, name: &str) -> bool;
-    fn export_labels(&mut self, file_name: &str) -> Result<(), Error>;
+    fn export_labels(&mut self, file_name: &str, format: SymbolFormat, all: bool) -> Result<(), Error>;
+    fn symbols(&self, all: bool) -> Vec<Symbol>;
     fn mark_label_used(&mut self, name: &str);
 }
 
@@ -24,7 +25,9 @@ impl Label for AssemblerContext {
         if label_name.ends_with(":") {
             label_name = name.replace(":", "");
         }
-        if !label_name.starts_with(".") {
+        let local = label_name.starts_with(".");
+        if !local {
+            label_name = self.scoped_name(&label_name);
             self.label_context = label_name.clone();
         } else {
             label_name = self.label_context.clone() + &label_name.clone();
@@ -34,27 +37,43 @@ impl Label for AssemblerContext {
             label_name = label_name.to_uppercase()
         }
 
-        if self.is_label_defined(label_name.as_str()) {
+        if self.labels.contains_key(&label_name) {
             return Err(self.error(ErrorType::LabelOrConstantExists));
         }
         self.labels.insert(label_name.to_string(), self.current_pc);
         if global {
             self.global_labels.push(label_name.to_string());
         }
-        self.used.insert(label_name, (false, self.current_line_number(), self.current_file_name()));
+        if local {
+            self.local_labels.push(label_name.to_string());
+        }
+        let used = self.used.get(&label_name).map(|(u, _, _)| *u).unwrap_or(false);
+        self.used.insert(label_name, (used, self.current_line_number(), self.current_file_name()));
         Ok(())
     }
 
-    fn get_label(&mut self, name: &str) -> Option<isize> {
-        let mut label_name = name.to_string();
+    // Not exported and never reported as unused
+    fn add_temp_label(&mut self, key: &str) {
+        let count = self.temp_labels.entry(key.to_string()).or_insert(0);
+        *count += 1;
+        let name = format!("#{}.{}", key, count);
+        self.labels.insert(name, self.current_pc);
+    }
 
-        if label_name.starts_with(".") {
-            label_name = self.label_context.clone() + &label_name.clone();
+    fn qualified_name(&self, name: &str) -> String {
+        if !name.starts_with(".") {
+            return self.resolve(name);
         }
-
+        let label_name = self.label_context.clone() + name;
         if self.case_insensitive {
-            label_name = label_name.to_uppercase()
+            label_name.to_uppercase()
+        } else {
+            label_name
         }
+    }
+
+    fn get_label(&mut self, name: &str) -> Option<isize> {
+        let label_name = self.qualified_name(name);
         match self.labels.get(&label_name).cloned() {
             None => None,
             Some(s) => {
@@ -64,33 +83,48 @@ impl Label for AssemblerContext {
         }
     }
     fn is_label_defined(&self, name: &str) -> bool {
-        let mut label_name = name.to_string();
-
-        if label_name.starts_with(".") {
-            label_name = self.label_context.clone() + &label_name.clone();
-        }
-        if self.case_insensitive {
-            label_name = label_name.to_uppercase()
-        }
-        self.labels.contains_key(&label_name)
+        self.labels.contains_key(&self.qualified_name(name))
     }
 
-    fn export_labels(&mut self, file_name: &str) -> Result<(), Error> {
-        if file_name.len() > 0 {
+    fn export_labels(&mut self, file_name: &str, format: SymbolFormat, all: bool) -> Result<(), Error> {
+        if !file_name.is_empty() {
             let path = Path::new(file_name);
-            let mut file = BufWriter::new(File::create(&path)?);
+            let mut file = BufWriter::new(File::create(path)?);
+            format.exporter().export(&self.symbols(all), &mut file)?;
+        }
+        Ok(())
+    }
 
-            let mut m = 0;
-            for (l, _) in &self.labels {
-                m = max(m, l.len() + 1);
-            }
-            for g in self.global_labels.clone() {
-                let s = self.get_label(&g.clone()).unwrap_or(-1);
-                let line = format!("{} = 0x{:x}\n", g.pad_to_width(m), s);
-                file.write(line.as_bytes())?;
+    // Labels are ordered by address, constants by name
+    fn symbols(&self, all: bool) -> Vec<Symbol> {
+        let mut labels = vec![];
+        for (name, value) in self.labels.iter().filter(|(name, _)| !name.starts_with('#')) {
+            let global = self.global_labels.contains(name);
+            if !all && !global {
+                continue;
             }
+            let (used, line, file) = self.used.get(name).cloned().unwrap_or((false, 0, String::new()));
+            let physical = self.physical.get(name).cloned();
+            let local = self.local_labels.contains(name);
+            labels.push(Symbol { name: name.to_string(), value: *value, kind: SymbolKind::Label, file, line, global, local, used: Some(used), physical });
         }
-        Ok(())
+        labels.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+        let mut constants = vec![];
+        for (name, value) in &self.constants {
+            let prefix = name.split('.').next().unwrap_or("");
+            let kind = if name.contains('.') && self.struct_defs.contains_key(prefix) {
+                SymbolKind::StructMember
+            } else if name.contains('.') && self.enum_names.iter().any(|e| e == prefix) {
+                SymbolKind::EnumMember
+            } else {
+                SymbolKind::Constant
+            };
+            let (line, file) = self.constant_source.get(name).cloned().unwrap_or((0, String::new()));
+            constants.push(Symbol { name: name.to_string(), value: *value, kind, file, line, global: false, local: false, used: None, physical: None });
+        }
+        constants.sort_by(|a, b| a.name.cmp(&b.name));
+        labels.append(&mut constants);
+        labels
     }
 
     fn mark_label_used(&mut self, name: &str) {
@@ -100,3 +134,48 @@ impl Label for AssemblerContext {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_options::AssemblerOptions;
+    use crate::assembler::label::Label;
+    use crate::assembler::test_support::{assemble, assemble_with, errors};
+
+    #[test]
+    fn temporary_labels() {
+        let source = " org 0x8000\n ld b,8\n1: rrca\n djnz 1b\n jr z,1f\n inc a\n1: jr 1b\n";
+        let (mut assembler, result) = assemble("temporary", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[0x06, 0x08, 0x0F, 0x10, 0xFD, 0x28, 0x01, 0x3C, 0x18, 0xFE]);
+
+        let source = " org 0x8000\n@@ jr +\n jr ++\n@@ jr -\n:\n jr --\n";
+        let (mut assembler, result) = assemble("anonymous", source);
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[0x18, 0x02, 0x18, 0x02, 0x18, 0xFE, 0x18, 0xFC]);
+
+        // Only binary numbers with a leading zero take the b suffix, 1b is a temporary label
+        let (mut assembler, result) = assemble("binary", " db 0101b,%11\n");
+        assert!(result.is_ok());
+        assert_eq!(assembler.bank.as_slice(), &[5, 3]);
+    }
+
+    #[test]
+    fn temporary_labels_are_not_symbols() {
+        let (assembler, result) = assemble_with("temporary_symbols", "1: jr 1b\n@@ jr -\n", |a| { a.warn_unused(true); });
+        assert!(result.is_ok());
+        assert!(assembler.context.symbols(true).is_empty());
+        assert!(assembler.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn missing_temporary_labels() {
+        let (assembler, result) = assemble("temporary_missing", " jr 1b\n jr 1f\n jr -\n jr +\n1: nop\n jr 2f\n");
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [
+            (1, "Undefined label or constant '1b'".to_string()),
+            (3, "Undefined label or constant '-'".to_string()),
+            (4, "Undefined label or constant '+'".to_string()),
+            (6, "Undefined label or constant '2f'".to_string()),
+        ]);
+    }
+}
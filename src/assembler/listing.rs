@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::assembler::assembler_context::AssemblerContext;
+use crate::assembler::error::Error;
+
+const BYTES_PER_ROW: usize = 4;
+
+struct ListingLine {
+    file_name: String,
+    line_number: isize,
+    depth: usize,
+    address: isize,
+    start: usize,
+    end: usize,
+    source: String,
+    expanded: bool,
+}
+
+#[derive(Default)]
+pub struct Listing {
+    file_name: String,
+    enabled: bool,
+    recording: bool,
+    lines: Vec<ListingLine>,
+}
+
+impl Listing {
+    pub fn new() -> Listing {
+        Default::default()
+    }
+
+    pub fn file_name(&mut self, file_name: &str) {
+        self.file_name = file_name.to_string();
+        self.enabled = !file_name.is_empty();
+    }
+
+    pub fn enable(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn begin_pass(&mut self, final_pass: bool) {
+        self.lines.clear();
+        self.recording = final_pass;
+    }
+
+    // Lines are only recorded in the final pass, the bytes are looked up when the listing is
+    // written so every value appears as it was finally resolved
+    pub fn begin_line(&mut self, context: &mut AssemblerContext, offset: usize, line_number: isize, source: &str, expanded: bool) -> Option<usize> {
+        if !self.recording || !self.enabled {
+            return None;
+        }
+        self.lines.push(ListingLine {
+            file_name: context.current_file_name(),
+            line_number,
+            depth: context.file_name.len().saturating_sub(1),
+            address: context.offset_pc(0),
+            start: offset,
+            end: offset,
+            source: source.trim_end().to_string(),
+            expanded,
+        });
+        Some(self.lines.len() - 1)
+    }
+
+    // A line that expands a macro or includes a file owns only the bytes emitted before the
+    // first nested line
+    pub fn end_line(&mut self, entry: Option<usize>, offset: usize) {
+        if let Some(index) = entry {
+            let end = match self.lines.get(index + 1) {
+                Some(nested) => nested.start,
+                None => offset
+            };
+            self.lines[index].end = end;
+        }
+    }
+
+    fn default_file_name(source: &str) -> String {
+        Path::new(source).with_extension("lst").to_string_lossy().to_string()
+    }
+
+    pub fn write(&self, source: &str, bytes: &[u8]) -> Result<(), Error> {
+        if self.lines.is_empty() {
+            return Ok(());
+        }
+        let file_name = if self.file_name.is_empty() { Listing::default_file_name(source) } else { self.file_name.clone() };
+        let mut file = File::create(file_name)?;
+        writeln!(file, "{:<24}{:>6} {:>2}  {:<4}  {:<12} Source", "File", "Line", "D", "Addr", "Bytes")?;
+        for line in &self.lines {
+            let code = &bytes[line.start.min(bytes.len())..line.end.min(bytes.len())];
+            let mut rows = code.chunks(BYTES_PER_ROW);
+            let first = rows.next().unwrap_or(&[]);
+            let marker = if line.expanded { '+' } else { ' ' };
+            writeln!(file, "{:<24}{:>6} {:>2}{} {:04X}  {:<12} {}",
+                     line.file_name, line.line_number, line.depth, marker, line.address, Listing::hex(first), line.source)?;
+            let mut address = line.address;
+            for row in rows {
+                address += BYTES_PER_ROW as isize;
+                writeln!(file, "{:<24}{:>6} {:>2}{} {:04X}  {}", "", "", "", marker, address, Listing::hex(row))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::assembler::assembler_options::AssemblerOptions;
+    use crate::assembler::test_support::{assemble_with, errors, temp_file};
+
+    #[test]
+    fn listing() {
+        let listing = temp_file("listing", "lst");
+        let source = " org 0x8000\n macro two\n nop\n nop\n endm\n db 1,2,3,4,5\n two\n";
+        let (_, result) = assemble_with("listing", source, |a| { a.listing_file(listing.to_str().unwrap()); });
+        assert!(result.is_ok());
+        let text = fs::read_to_string(&listing).unwrap();
+        fs::remove_file(&listing).ok();
+        // The temporary file name is too long for its column, so compare what follows it
+        let lines: Vec<&str> = text.lines().map(|l| l.trim_start_matches(|c| c != ' ').trim_start()).collect();
+        assert_eq!(lines, [
+            "Line  D  Addr  Bytes        Source",
+            "1  0  0000                org 0x8000",
+            "2  0  8000                macro two",
+            "3  0  8000                nop",
+            "4  0  8000                nop",
+            "5  0  8000                endm",
+            "6  0  8000  01 02 03 04   db 1,2,3,4,5",
+            "8004  05",
+            "7  0  8005                two",
+            "7  0+ 8005  00            nop",
+            "7  0+ 8006  00            nop",
+        ]);
+    }
+
+    #[test]
+    fn no_listing_after_errors() {
+        let listing = temp_file("listing_errors", "lst");
+        let (assembler, result) = assemble_with("listing_errors", " nop\n ld q,1\n", |a| { a.listing_file(listing.to_str().unwrap()); });
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [(2, "Syntax error".to_string())]);
+        assert!(!listing.exists());
+    }
+
+    #[test]
+    fn listing_file_cannot_be_created() {
+        let listing = temp_file("listing_missing", "dir").join("out.lst");
+        let (_, result) = assemble_with("listing_missing", " nop\n", |a| { a.listing_file(listing.to_str().unwrap()); });
+        assert!(result.is_err());
+    }
+}
@@ -1,17 +1,33 @@
+use std::collections::{HashMap, HashSet};
+
 use crate::assembler::assembler_context::AssemblerContext;
 use crate::assembler::bank::Bank;
 use crate::assembler::directive::macros::MacroHandler;
+use crate::assembler::error::Error;
+use crate::assembler::file_provider::FileProvider;
 use crate::assembler::expression::ExpressionParser;
+use crate::assembler::listing::Listing;
+use crate::assembler::nex::Nex;
+use crate::assembler::snapshot::Snapshot;
+use crate::assembler::symbols::SymbolFormat;
+use crate::assembler::tap::Tap;
+use crate::assembler::sld::Sld;
+use crate::assembler::directive::macros::MacroSource;
+use crate::assembler::directive::section::{Placement, Section};
+use crate::assembler::symbols::Symbol;
 use crate::assembler::tokens::Token;
+use crate::assembler::unit_test::{EncodedInstruction, UnitTest};
 
-pub(super) mod assembler_options;
-pub(super) mod error;
+pub mod assembler_options;
+pub mod error;
+pub mod file_provider;
 mod token_reader;
 mod error_type;
 mod tokens;
 mod token_traits;
 mod number_parser;
 mod instruction_encoder;
+#[allow(clippy::module_inception)]
 mod assembler;
 mod reg_pair;
 mod directive;
@@ -26,6 +42,17 @@ mod label;
 mod constant;
 mod emitter;
 mod get_token;
+mod listing;
+mod nex;
+mod device;
+pub mod snapshot;
+pub mod tap;
+pub mod symbols;
+mod sld;
+pub mod unit_test;
+pub mod timing;
+#[cfg(test)]
+mod test_support;
 
 struct TokenReader<R> {
     reader: R,
@@ -38,20 +65,22 @@ struct TokenReader<R> {
     preceding_token: Token,
     file_name: String,
     whitespace_at_start: bool,
+    source_line: String,
 }
 
-#[derive(Debug)]
-pub struct ForwardReference {
-    is_expression: bool,
-    pc: isize,
-    label: String,
-    expression: Vec<Token>,
-    is_relative: bool,
-    byte_count: isize,
-    line_no: isize,
-    file_name: String,
+#[derive(Clone, Copy, Default)]
+struct PassOptions {
+    origin: isize,
+    z80n_enabled: bool,
+    c_spect_enabled: bool,
+    console_output: bool,
+    max_code_size: usize,
+    jr_promote: bool,
+    listing: bool,
 }
 
+// If(false) has not taken a branch yet so ELSEIF and ELSE are evaluated, SkipEnd skips
+// everything up to the matching ENDIF
 #[derive(Debug)]
 pub enum IfBlock {
     None,
@@ -75,11 +104,50 @@ pub struct Assembler {
     collect_macro: bool,
     collect_enum: Option<(String, isize, isize)>,
     collect_struct: Option<(String, isize)>,
-    warnings: Vec<String>,
+    diagnostics: Vec<Error>,
+    messages: Vec<String>,
+    warn_unused: bool,
+    max_errors: usize,
+    max_macro_depth: usize,
     include_dirs: Vec<String>,
     labels_file: String,
+    symbol_format: SymbolFormat,
+    export_all: bool,
     if_level: Vec<IfBlock>,
     defines: Vec<String>,
+    pass_options: PassOptions,
+    jr_promote: bool,
+    // JRs seen this pass at each file & line, a promoted JR is known by its line and which one it is on the line
+    relative_jumps: HashMap<(String, isize), usize>,
+    promoted_jumps: HashSet<(String, isize, usize)>,
+    listing: Listing,
+    nex: Nex,
+    snapshots: Vec<Snapshot>,
+    taps: Vec<Tap>,
+    sld: Sld,
+    macro_source: Option<MacroSource>,
+    files: Box<dyn FileProvider>,
+    source: Option<(String, String)>,
+    testing: bool,
+    tests: Vec<UnitTest>,
+    instructions: Vec<EncodedInstruction>,
+    timings_file: String,
+    timing_regions: Vec<(String, usize)>,
+    phased: bool,
+    sections: Vec<Section>,
+    section: Option<usize>,
+    layout: HashMap<String, Placement>,
+    section_layout: HashMap<String, Placement>,
+    section_options: Vec<String>,
+    memory_block: bool,
+}
+
+// The outcome of a successful assembly, diagnostics holds any warnings and messages the MESSAGE & TIMING output
+pub struct Assembled {
+    pub bytes: Vec<u8>,
+    pub symbols: Vec<Symbol>,
+    pub diagnostics: Vec<Error>,
+    pub messages: Vec<String>,
 }
 
 
@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::assembler::error::Error;
+
+const HEADER_SIZE: usize = 512;
+const PALETTE_SIZE: usize = 512;
+pub const BANK_SIZE: usize = 16384;
+pub const MAX_BANKS: usize = 112;
+
+// Banks 5, 2 and 0 are the ones normally paged in at 0x4000, 0x8000 & 0xC000 so they come first
+const BANK_ORDER: [u8; 3] = [5, 2, 0];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NexScreen {
+    Layer2,
+    Ula,
+    LoRes,
+    HiRes,
+    HiColour,
+    Layer2_320,
+    Layer2_640,
+}
+
+impl NexScreen {
+    fn flags(&self) -> (u8, u8) {
+        match self {
+            NexScreen::Layer2 => (1, 0),
+            NexScreen::Ula => (2, 0),
+            NexScreen::LoRes => (4, 0),
+            NexScreen::HiRes => (8, 0),
+            NexScreen::HiColour => (16, 0),
+            NexScreen::Layer2_320 => (64, 1),
+            NexScreen::Layer2_640 => (64, 2),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            NexScreen::Layer2 => 49152,
+            NexScreen::Ula => 6912,
+            NexScreen::LoRes | NexScreen::HiRes | NexScreen::HiColour => 12288,
+            NexScreen::Layer2_320 | NexScreen::Layer2_640 => 81920,
+        }
+    }
+
+    pub fn has_palette(&self) -> bool {
+        !matches!(self, NexScreen::Ula | NexScreen::HiRes | NexScreen::HiColour)
+    }
+}
+
+impl FromStr for NexScreen {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "l2" => Ok(NexScreen::Layer2),
+            "scr" => Ok(NexScreen::Ula),
+            "lr" => Ok(NexScreen::LoRes),
+            "shr" => Ok(NexScreen::HiRes),
+            "shc" => Ok(NexScreen::HiColour),
+            "l2_320" => Ok(NexScreen::Layer2_320),
+            "l2_640" => Ok(NexScreen::Layer2_640),
+            _ => Err(())
+        }
+    }
+}
+
+pub struct Nex {
+    pub file_name: String,
+    pub open: bool,
+    pub start: Option<isize>,
+    pub stack: isize,
+    pub entry_bank: u8,
+    pub file_handle: isize,
+    pub border: u8,
+    pub preserve_registers: bool,
+    pub ram_2mb: bool,
+    pub core: [u8; 3],
+    pub banks: Vec<u8>,
+    pub screen: Option<(NexScreen, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl Nex {
+    pub fn new() -> Nex {
+        Nex {
+            file_name: String::new(),
+            open: false,
+            start: None,
+            stack: 0xFFFE,
+            entry_bank: 0,
+            file_handle: 0,
+            border: 7,
+            preserve_registers: false,
+            ram_2mb: false,
+            core: [0, 0, 0],
+            banks: vec![],
+            screen: None,
+        }
+    }
+
+    // NEX files always start with 5, 2 & 0 followed by the remaining banks in numerical order
+    pub fn bank_order() -> Vec<u8> {
+        let mut order = BANK_ORDER.to_vec();
+        order.extend((0..MAX_BANKS as u8).filter(|b| !BANK_ORDER.contains(b)));
+        order
+    }
+
+    fn header(&self, start: isize, banks: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut header = vec![0u8; HEADER_SIZE];
+        let (screen_flags, screen_flags2) = match &self.screen {
+            Some((screen, _, palette)) => {
+                let (flags, flags2) = screen.flags();
+                let no_palette = if screen.has_palette() && palette.is_none() { 128 } else { 0 };
+                (flags | no_palette, flags2)
+            }
+            None => (0, 0)
+        };
+        let version = if screen_flags2 > 0 { "V1.3" } else { "V1.2" };
+        header[0..4].copy_from_slice(b"Next");
+        header[4..8].copy_from_slice(version.as_bytes());
+        header[8] = if self.ram_2mb || banks.iter().any(|(b, _)| *b >= 48) { 1 } else { 0 };
+        header[9] = banks.len() as u8;
+        header[10] = screen_flags;
+        header[11] = self.border & 7;
+        header[12] = self.stack as u8;
+        header[13] = (self.stack >> 8) as u8;
+        header[14] = start as u8;
+        header[15] = (start >> 8) as u8;
+        for (bank, _) in banks {
+            header[18 + *bank as usize] = 1;
+        }
+        header[134] = self.preserve_registers as u8;
+        header[135..138].copy_from_slice(&self.core);
+        header[139] = self.entry_bank;
+        header[140] = self.file_handle as u8;
+        header[141] = (self.file_handle >> 8) as u8;
+        header[152] = screen_flags2;
+        header
+    }
+
+    pub fn write(&self, file_name: &str, start: isize, banks: &[(u8, Vec<u8>)]) -> Result<(), Error> {
+        let mut file = File::create(file_name)?;
+        file.write_all(&self.header(start, banks))?;
+        if let Some((screen, data, palette)) = &self.screen {
+            if screen.has_palette() {
+                if let Some(palette) = palette {
+                    let mut palette = palette.clone();
+                    palette.resize(PALETTE_SIZE, 0);
+                    file.write_all(&palette)?;
+                }
+            }
+            let mut data = data.clone();
+            data.resize(screen.size(), 0);
+            file.write_all(&data)?;
+        }
+        for (_, data) in banks {
+            file.write_all(data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::nex::{HEADER_SIZE, Nex, NexScreen};
+
+    #[test]
+    fn bank_order() {
+        let order = Nex::bank_order();
+        assert_eq!(order.len(), 112);
+        assert_eq!(order[..6], [5, 2, 0, 1, 3, 4]);
+        assert_eq!(order[111], 111);
+    }
+
+    #[test]
+    fn header_layout() {
+        let mut nex = Nex::new();
+        nex.border = 9;
+        nex.core = [2, 0, 28];
+        nex.entry_bank = 3;
+        let header = nex.header(0x8123, &[(5, vec![]), (2, vec![]), (0, vec![])]);
+        assert_eq!(header.len(), HEADER_SIZE);
+        assert_eq!(&header[0..8], b"NextV1.2");
+        assert_eq!(header[8..16], [0, 3, 0, 1, 0xFE, 0xFF, 0x23, 0x81]);
+        assert_eq!(header[18..24], [1, 0, 1, 0, 0, 1]);
+        assert_eq!(header[134..142], [0, 2, 0, 28, 0, 3, 0, 0]);
+        assert_eq!(header[152], 0);
+    }
+
+    #[test]
+    fn header_screen_flags() {
+        let mut nex = Nex::new();
+        nex.screen = Some((NexScreen::Layer2_320, vec![], None));
+        nex.file_handle = 0x4000;
+        let header = nex.header(0, &[(50, vec![])]);
+        assert_eq!(&header[4..8], b"V1.3");
+        assert_eq!(header[8], 1);
+        assert_eq!(header[10], 64 | 128);
+        assert_eq!(header[18 + 50], 1);
+        assert_eq!(header[140..142], [0x00, 0x40]);
+        assert_eq!(header[152], 1);
+
+        nex.screen = Some((NexScreen::Ula, vec![], None));
+        assert_eq!(nex.header(0, &[])[10], 2);
+    }
+}
@@ -46,7 +46,7 @@ pub trait HighLow {
 
 impl HighLow for u16 {
     fn lo(&self) -> u8 {
-        return (self & 0xFF) as u8;
+        (self & 0xFF) as u8
     }
     fn hi(&self) -> u8 {
         (self >> 8u16 & 0xff) as u8
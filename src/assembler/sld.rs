@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::assembler::assembler_context::AssemblerContext;
+use crate::assembler::device::Device;
+use crate::assembler::directive::macros::MacroSource;
+use crate::assembler::error::Error;
+
+const SLD_VERSION: usize = 1;
+
+// Source level debug information in the sjasmplus SLD format, each record is
+// file|line|definition file|definition line|page|value|type|data
+// where the definition is the macro body line when the record comes from a macro expansion
+#[derive(Default)]
+pub struct Sld {
+    file_name: String,
+    recording: bool,
+    records: Vec<String>,
+    last_trace: Option<(String, isize, isize)>,
+}
+
+impl Sld {
+    pub fn new() -> Sld {
+        Default::default()
+    }
+
+    pub fn file_name(&mut self, file_name: &str) {
+        self.file_name = file_name.to_string();
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.file_name.is_empty()
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn begin_pass(&mut self, final_pass: bool) {
+        self.records.clear();
+        self.last_trace = None;
+        self.recording = final_pass && self.enabled();
+    }
+
+    fn record(&mut self, context: &AssemblerContext, origin: Option<&MacroSource>, page: Option<usize>, value: isize, kind: char, data: &str) {
+        let (definition_file, definition_line) = match origin {
+            Some(source) => (source.file_name.as_str(), source.line_number),
+            None => ("", 0)
+        };
+        let page = page.map_or(-1, |p| p as isize);
+        self.records.push(format!("{}|{}|{}|{}|{}|{}|{}|{}", context.current_file_name(), context.current_line_number(),
+                                  definition_file, definition_line, page, value, kind, data));
+    }
+
+    // One T record per instruction or data directive, tagged with the current label scope
+    pub fn trace(&mut self, context: &AssemblerContext, origin: Option<&MacroSource>, page: Option<usize>, address: isize) {
+        let key = (context.current_file_name(), context.current_line_number(), address);
+        if self.last_trace.as_ref() == Some(&key) {
+            return;
+        }
+        self.last_trace = Some(key);
+        self.record(context, origin, page, address, 'T', &context.label_context);
+    }
+
+    // Main labels are F records and local labels L records, the data is module,main,local
+    pub fn label(&mut self, context: &AssemblerContext, origin: Option<&MacroSource>, page: Option<usize>, name: &str, address: isize) {
+        let module = context.modules.join(".");
+        let (module, name) = match name.strip_prefix(&format!("{}.", module)) {
+            Some(rest) if !module.is_empty() => (module.as_str(), rest),
+            _ => ("", name)
+        };
+        let (main, local) = match name.find('.') {
+            Some(i) => (&name[..i], &name[i + 1..]),
+            None => (name, "")
+        };
+        let kind = if local.is_empty() { 'F' } else { 'L' };
+        self.record(context, origin, page, address, kind, &format!("{},{},{}", module, main, local));
+    }
+
+    fn model(source: &str, device: &Device) -> String {
+        let (page_size, page_count, slot_count) = device.layout();
+        let slots: Vec<String> = (0..slot_count).map(|s| (s * page_size).to_string()).collect();
+        format!("{}|0||0|-1|-1|Z|pages.size:{},pages.count:{},slots.count:{},slots.adr:{}",
+                source, page_size, page_count, slot_count, slots.join(","))
+    }
+
+    pub fn write(&self, source: &str, device: &Device) -> Result<(), Error> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        let mut file = BufWriter::new(File::create(&self.file_name)?);
+        writeln!(file, "|SLD.data.version|{}", SLD_VERSION)?;
+        if device.is_paged() {
+            writeln!(file, "{}", Sld::model(source, device))?;
+        }
+        for record in &self.records {
+            writeln!(file, "{}", record)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::assembler::assembler_options::AssemblerOptions;
+    use crate::assembler::test_support::{assemble_with, errors, temp_file};
+
+    fn sld(name: &str, source: &str) -> Option<Vec<String>> {
+        let sld = temp_file(name, "sld");
+        let (_, result) = assemble_with(name, source, |a| { a.sld_file(sld.to_str().unwrap()); });
+        let text = fs::read_to_string(&sld).ok();
+        fs::remove_file(&sld).ok();
+        result.ok()?;
+        // The source is a temporary file, name it main.asm
+        let source = temp_file(name, "asm");
+        Some(text?.replace(source.to_str().unwrap(), "main.asm").lines().map(String::from).collect())
+    }
+
+    #[test]
+    fn sld_records() {
+        let source = " device zxspectrum128\n org 0xC000\nmain\n nop\n.loop jr .loop\n";
+        assert_eq!(sld("sld", source).unwrap(), [
+            "|SLD.data.version|1",
+            "main.asm|0||0|-1|-1|Z|pages.size:16384,pages.count:8,slots.count:4,slots.adr:0,16384,32768,49152",
+            "main.asm|3||0|0|49152|F|,main,",
+            "main.asm|4||0|0|49152|T|main",
+            "main.asm|5||0|0|49153|L|,main,loop",
+            "main.asm|5||0|0|49153|T|main",
+        ]);
+    }
+
+    #[test]
+    fn macro_lines_and_no_device() {
+        let source = " org 0x8000\n macro two\n nop\n nop\n endm\nstart two\n";
+        assert_eq!(sld("sld_macro", source).unwrap(), [
+            "|SLD.data.version|1",
+            "main.asm|6||0|-1|32768|F|,start,",
+            "main.asm|6|main.asm|3|-1|32768|T|start",
+            "main.asm|6|main.asm|4|-1|32769|T|start",
+        ]);
+    }
+
+    #[test]
+    fn no_sld_after_errors() {
+        let sld = temp_file("sld_errors", "sld");
+        let (assembler, result) = assemble_with("sld_errors", " nop\n jp\n", |a| { a.sld_file(sld.to_str().unwrap()); });
+        assert!(result.is_err());
+        assert_eq!(errors(&assembler), [(2, "Syntax error".to_string())]);
+        assert!(!sld.exists());
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs::File;
+use std::io::Write;
+
+use crate::assembler::error::Error;
+use crate::assembler::error_type::ErrorType;
+
+pub const RAM_48K: usize = 49152;
+pub const BANK_SIZE: usize = 16384;
+
+const SNA_HEADER_SIZE: usize = 27;
+const Z80_HEADER_SIZE: usize = 30;
+const Z80_EXTRA_HEADER_SIZE: usize = 54;
+
+#[derive(Clone)]
+pub struct Snapshot {
+    pub file_name: String,
+    pub start: Option<isize>,
+    pub stack: isize,
+    pub border: u8,
+    pub bank: u8,
+    pub ram_image: String,
+}
+
+impl Snapshot {
+    pub fn new(file_name: &str) -> Snapshot {
+        Snapshot {
+            file_name: file_name.to_string(),
+            start: None,
+            stack: 0xFFFE,
+            border: 7,
+            bank: 0,
+            ram_image: String::new(),
+        }
+    }
+
+    pub fn is_snapshot(file_name: &str) -> bool {
+        let name = file_name.to_lowercase();
+        name.ends_with(".sna") || name.ends_with(".z80")
+    }
+
+    fn is_z80(&self) -> bool {
+        self.file_name.to_lowercase().ends_with(".z80")
+    }
+
+    // Registers are left as the 48K ROM would have them so code can return to BASIC
+    fn sna_header(&self, stack: isize) -> Vec<u8> {
+        let mut header = vec![0u8; SNA_HEADER_SIZE];
+        header[0] = 0x3F;
+        header[1] = 0x58;
+        header[2] = 0x27;
+        header[15] = 0x3A;
+        header[16] = 0x5C;
+        header[19] = 0x04;
+        header[23] = stack as u8;
+        header[24] = (stack >> 8) as u8;
+        header[25] = 1;
+        header[26] = self.border & 7;
+        header
+    }
+
+    fn z80_header(&self, start: isize, hardware: u8) -> Vec<u8> {
+        let mut header = vec![0u8; Z80_HEADER_SIZE + 2 + Z80_EXTRA_HEADER_SIZE];
+        header[8] = self.stack as u8;
+        header[9] = (self.stack >> 8) as u8;
+        header[10] = 0x3F;
+        header[12] = (self.border & 7) << 1;
+        header[19] = 0x58;
+        header[20] = 0x27;
+        header[23] = 0x3A;
+        header[24] = 0x5C;
+        header[27] = 1;
+        header[28] = 1;
+        header[29] = 1;
+        header[30] = Z80_EXTRA_HEADER_SIZE as u8;
+        header[32] = start as u8;
+        header[33] = (start >> 8) as u8;
+        header[34] = hardware;
+        header[35] = 0x10 | (self.bank & 7);
+        header[61] = 0xFF;
+        header[62] = 0xFF;
+        header
+    }
+
+    // A block length of 0xFFFF marks 16K of uncompressed data
+    fn z80_block(page: u8, data: &[u8]) -> Vec<u8> {
+        let mut block = vec![0xFF, 0xFF, page];
+        block.extend_from_slice(data);
+        block
+    }
+
+    // The 48K .sna format has no PC, it is pushed onto the stack and the snapshot loader
+    // executes a RETN
+    pub fn write_48k(&self, start: isize, ram: &mut [u8]) -> Result<(), Error> {
+        let mut file = File::create(&self.file_name)?;
+        if self.is_z80() {
+            file.write_all(&self.z80_header(start, 0))?;
+            for (page, address) in [(8u8, 0x4000usize), (4, 0x8000), (5, 0xC000)].iter() {
+                let offset = address - 0x4000;
+                file.write_all(&Snapshot::z80_block(*page, &ram[offset..offset + BANK_SIZE]))?;
+            }
+            return Ok(());
+        }
+        let stack = self.stack - 2;
+        if !(0x4000..=0xFFFE).contains(&stack) {
+            return Err(Error::fatal(&ErrorType::SnapshotStack(self.stack).to_string(), -1, &self.file_name));
+        }
+        ram[stack as usize - 0x4000] = start as u8;
+        ram[stack as usize - 0x4000 + 1] = (start >> 8) as u8;
+        file.write_all(&self.sna_header(stack))?;
+        file.write_all(ram)?;
+        Ok(())
+    }
+
+    pub fn write_128k(&self, start: isize, banks: &[Vec<u8>]) -> Result<(), Error> {
+        let mut file = File::create(&self.file_name)?;
+        let paged = (self.bank & 7) as usize;
+        if self.is_z80() {
+            file.write_all(&self.z80_header(start, 4))?;
+            for (bank, data) in banks.iter().enumerate() {
+                file.write_all(&Snapshot::z80_block(bank as u8 + 3, data))?;
+            }
+            return Ok(());
+        }
+        file.write_all(&self.sna_header(self.stack))?;
+        file.write_all(&banks[5])?;
+        file.write_all(&banks[2])?;
+        file.write_all(&banks[paged])?;
+        file.write_all(&[start as u8, (start >> 8) as u8, 0x10 | paged as u8, 0])?;
+        for bank in (0..8).filter(|b| *b != 5 && *b != 2 && *b != paged) {
+            file.write_all(&banks[bank])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::assembler::snapshot::{BANK_SIZE, RAM_48K, SNA_HEADER_SIZE, Snapshot, Z80_HEADER_SIZE};
+
+    fn temp_file(name: &str) -> String {
+        std::env::temp_dir().join(format!("fantasm_{}_{}", std::process::id(), name)).to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn sna_header_layout() {
+        let mut snapshot = Snapshot::new("test.sna");
+        snapshot.border = 2;
+        let header = snapshot.sna_header(0x5FFE);
+        assert_eq!(header.len(), SNA_HEADER_SIZE);
+        assert_eq!(header[0..3], [0x3F, 0x58, 0x27]);
+        assert_eq!(header[15..17], [0x3A, 0x5C]);
+        assert_eq!(header[19], 0x04);
+        assert_eq!(header[23..27], [0xFE, 0x5F, 1, 2]);
+    }
+
+    #[test]
+    fn z80_header_layout() {
+        let mut snapshot = Snapshot::new("test.z80");
+        snapshot.stack = 0x7FF0;
+        snapshot.border = 5;
+        snapshot.bank = 3;
+        let header = snapshot.z80_header(0x8000, 4);
+        assert_eq!(header.len(), Z80_HEADER_SIZE + 56);
+        assert_eq!(header[6..8], [0, 0]);
+        assert_eq!(header[8..13], [0xF0, 0x7F, 0x3F, 0, 5 << 1]);
+        assert_eq!(header[27..36], [1, 1, 1, 54, 0, 0x00, 0x80, 4, 0x13]);
+        assert_eq!(header[61..63], [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn sna_48k_pushes_start() {
+        let file_name = temp_file("48k.sna");
+        let mut snapshot = Snapshot::new(&file_name);
+        snapshot.stack = 0x8000;
+        let mut ram = vec![0u8; RAM_48K];
+        assert!(snapshot.write_48k(0x1234, &mut ram).is_ok());
+        let data = fs::read(&file_name).unwrap();
+        fs::remove_file(&file_name).ok();
+        assert_eq!(data.len(), SNA_HEADER_SIZE + RAM_48K);
+        assert_eq!(data[23..25], [0xFE, 0x7F]);
+        assert_eq!(data[SNA_HEADER_SIZE + 0x3FFE..SNA_HEADER_SIZE + 0x4000], [0x34, 0x12]);
+
+        snapshot.stack = 0x4000;
+        assert!(snapshot.write_48k(0x1234, &mut ram).is_err());
+        fs::remove_file(&file_name).ok();
+    }
+
+    #[test]
+    fn sna_128k_layout() {
+        let file_name = temp_file("128k.sna");
+        let mut snapshot = Snapshot::new(&file_name);
+        snapshot.bank = 3;
+        let banks: Vec<Vec<u8>> = (0..8).map(|b| vec![b as u8; BANK_SIZE]).collect();
+        assert!(snapshot.write_128k(0xC000, &banks).is_ok());
+        let data = fs::read(&file_name).unwrap();
+        fs::remove_file(&file_name).ok();
+        let banks_at = |offset: usize, count: usize| (0..count).map(|n| data[offset + n * BANK_SIZE]).collect::<Vec<u8>>();
+        assert_eq!(data.len(), SNA_HEADER_SIZE + 3 * BANK_SIZE + 4 + 5 * BANK_SIZE);
+        assert_eq!(banks_at(SNA_HEADER_SIZE, 3), [5, 2, 3]);
+        let extra = SNA_HEADER_SIZE + 3 * BANK_SIZE;
+        assert_eq!(data[extra..extra + 4], [0x00, 0xC0, 0x13, 0]);
+        assert_eq!(banks_at(extra + 4, 5), [0, 1, 4, 6, 7]);
+    }
+}
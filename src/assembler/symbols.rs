@@ -0,0 +1,246 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use pad::PadStr;
+
+use crate::assembler::error::Error;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SymbolFormat {
+    Default,
+    CSpect,
+    Zesarux,
+    Mame,
+    NoCash,
+    Json,
+}
+
+impl FromStr for SymbolFormat {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "default" => Ok(SymbolFormat::Default),
+            "cspect" => Ok(SymbolFormat::CSpect),
+            "zesarux" | "fuse" => Ok(SymbolFormat::Zesarux),
+            "mame" => Ok(SymbolFormat::Mame),
+            "nocash" | "sym" => Ok(SymbolFormat::NoCash),
+            "json" => Ok(SymbolFormat::Json),
+            _ => Err(())
+        }
+    }
+}
+
+impl SymbolFormat {
+    pub fn exporter(&self) -> Box<dyn SymbolExporter> {
+        match self {
+            SymbolFormat::Default => Box::new(DefaultExporter),
+            SymbolFormat::CSpect => Box::new(CSpectExporter),
+            SymbolFormat::Zesarux => Box::new(ZesaruxExporter),
+            SymbolFormat::Mame => Box::new(MameExporter),
+            SymbolFormat::NoCash => Box::new(NoCashExporter),
+            SymbolFormat::Json => Box::new(JsonExporter),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SymbolKind {
+    Label,
+    Constant,
+    StructMember,
+    EnumMember,
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SymbolKind::Label => "label",
+            SymbolKind::Constant => "constant",
+            SymbolKind::StructMember => "struct",
+            SymbolKind::EnumMember => "enum",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: isize,
+    pub kind: SymbolKind,
+    pub file: String,
+    pub line: isize,
+    pub global: bool,
+    // A .label scoped under the label before it
+    pub local: bool,
+    // Only labels have their use tracked
+    pub used: Option<bool>,
+    // Where a label is in the device's memory, when a paged device is in use
+    pub physical: Option<usize>,
+}
+
+pub trait SymbolExporter {
+    fn export(&self, symbols: &[Symbol], out: &mut dyn Write) -> Result<(), Error>;
+}
+
+fn labels(symbols: &[Symbol]) -> impl Iterator<Item=&Symbol> {
+    symbols.iter().filter(|s| s.kind == SymbolKind::Label)
+}
+
+// name = 0xaddr, the original FantASM format
+struct DefaultExporter;
+
+impl SymbolExporter for DefaultExporter {
+    fn export(&self, symbols: &[Symbol], out: &mut dyn Write) -> Result<(), Error> {
+        let width = labels(symbols).map(|s| s.name.len() + 1).max().unwrap_or(0);
+        for s in labels(symbols) {
+            writeln!(out, "{} = 0x{:x}", s.name.pad_to_width(width), s.value)?;
+        }
+        Ok(())
+    }
+}
+
+// physical address, logical address, type & name, as produced by SNASM for CSpect
+struct CSpectExporter;
+
+impl SymbolExporter for CSpectExporter {
+    fn export(&self, symbols: &[Symbol], out: &mut dyn Write) -> Result<(), Error> {
+        for s in labels(symbols) {
+            let physical = s.physical.unwrap_or((s.value & 0xFFFF) as usize);
+            writeln!(out, "{:08X} {:04X} 00 {}", physical, s.value & 0xFFFF, s.name)?;
+        }
+        Ok(())
+    }
+}
+
+struct ZesaruxExporter;
+
+impl SymbolExporter for ZesaruxExporter {
+    fn export(&self, symbols: &[Symbol], out: &mut dyn Write) -> Result<(), Error> {
+        for s in labels(symbols) {
+            writeln!(out, "{}: EQU ${:04X}", s.name, s.value & 0xFFFF)?;
+        }
+        Ok(())
+    }
+}
+
+// A debugger script, load with "source file" from the MAME debugger
+struct MameExporter;
+
+impl SymbolExporter for MameExporter {
+    fn export(&self, symbols: &[Symbol], out: &mut dyn Write) -> Result<(), Error> {
+        for s in labels(symbols) {
+            writeln!(out, "comadd {:04X},{}", s.value & 0xFFFF, s.name)?;
+        }
+        Ok(())
+    }
+}
+
+struct NoCashExporter;
+
+impl SymbolExporter for NoCashExporter {
+    fn export(&self, symbols: &[Symbol], out: &mut dyn Write) -> Result<(), Error> {
+        writeln!(out, "; FantASM {}", version!())?;
+        for s in labels(symbols) {
+            writeln!(out, "00:{:04X} {}", s.value & 0xFFFF, s.name)?;
+        }
+        Ok(())
+    }
+}
+
+struct JsonExporter;
+
+impl JsonExporter {
+    fn escape(s: &str) -> String {
+        let mut escaped = String::new();
+        for c in s.chars() {
+            match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                c => escaped.push(c)
+            }
+        }
+        escaped
+    }
+}
+
+impl SymbolExporter for JsonExporter {
+    fn export(&self, symbols: &[Symbol], out: &mut dyn Write) -> Result<(), Error> {
+        writeln!(out, "[")?;
+        for (i, s) in symbols.iter().enumerate() {
+            let separator = if i + 1 < symbols.len() { "," } else { "" };
+            writeln!(out, "  {{\"name\": \"{}\", \"value\": {}, \"type\": \"{}\", \"file\": \"{}\", \"line\": {}, \"global\": {}, \"local\": {}, \"used\": {}}}{}",
+                     JsonExporter::escape(&s.name), s.value, s.kind, JsonExporter::escape(&s.file),
+                     s.line, s.global, s.local, s.used.map_or(String::from("null"), |u| u.to_string()), separator)?;
+        }
+        writeln!(out, "]")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::assembler::label::Label;
+    use crate::assembler::symbols::{Symbol, SymbolFormat, SymbolKind};
+    use crate::assembler::test_support::assemble;
+
+    fn symbol(name: &str, value: isize, kind: SymbolKind) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            value,
+            kind,
+            file: String::from("main.asm"),
+            line: 3,
+            global: false,
+            local: false,
+            used: if kind == SymbolKind::Label { Some(true) } else { None },
+            physical: None,
+        }
+    }
+
+    fn export(format: &str) -> String {
+        let symbols = [
+            symbol("start", 0x8000, SymbolKind::Label),
+            Symbol { local: true, physical: Some(0x14003), ..symbol("start.loop", 0xC003, SymbolKind::Label) },
+            symbol("SIZE", 12, SymbolKind::Constant),
+        ];
+        let mut out = vec![];
+        SymbolFormat::from_str(format).unwrap().exporter().export(&symbols, &mut out).ok().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(export("default"), "start       = 0x8000\nstart.loop  = 0xc003\n");
+        assert_eq!(export("cspect"), "00008000 8000 00 start\n00014003 C003 00 start.loop\n");
+        assert_eq!(export("zesarux"), "start: EQU $8000\nstart.loop: EQU $C003\n");
+        assert_eq!(export("fuse"), export("zesarux"));
+        assert_eq!(export("mame"), "comadd 8000,start\ncomadd C003,start.loop\n");
+        assert!(export("nocash").ends_with("\n00:8000 start\n00:C003 start.loop\n"));
+        assert!(SymbolFormat::from_str("elf").is_err());
+    }
+
+    #[test]
+    fn cspect_paged_addresses() {
+        let source = " device zxspectrum128\n org 0xC000\n page 6\nbank6 nop\n org 0x8000\nbank2 nop\n";
+        let (assembler, result) = assemble("cspect_paged", source);
+        assert!(result.is_ok());
+        let mut out = vec![];
+        SymbolFormat::CSpect.exporter().export(&assembler.context.symbols(true), &mut out).ok().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "00008000 8000 00 bank2\n00018000 C000 00 bank6\n");
+    }
+
+    #[test]
+    fn json() {
+        let json = export("json");
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "[");
+        assert_eq!(lines[2], "  {\"name\": \"start.loop\", \"value\": 49155, \"type\": \"label\", \"file\": \"main.asm\", \"line\": 3, \"global\": false, \"local\": true, \"used\": true},");
+        assert_eq!(lines[3], "  {\"name\": \"SIZE\", \"value\": 12, \"type\": \"constant\", \"file\": \"main.asm\", \"line\": 3, \"global\": false, \"local\": false, \"used\": null}");
+        assert_eq!(lines[4], "]");
+    }
+}
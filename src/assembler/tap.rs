@@ -0,0 +1,152 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::assembler::error::Error;
+
+const HEADER_FLAG: u8 = 0x00;
+const DATA_FLAG: u8 = 0xFF;
+const PROGRAM: u8 = 0;
+const CODE: u8 = 3;
+const LOADER_LINE: u16 = 10;
+
+// BASIC keyword tokens used by the loader
+const CLEAR: u8 = 0xFD;
+const VAL: u8 = 0xB0;
+const LOAD: u8 = 0xEF;
+const CODE_TOKEN: u8 = 0xAF;
+const RANDOMIZE: u8 = 0xF9;
+const USR: u8 = 0xC0;
+
+#[derive(Clone)]
+pub struct Tap {
+    pub file_name: String,
+    pub start: Option<isize>,
+    pub loader: bool,
+}
+
+impl Tap {
+    pub fn new(file_name: &str) -> Tap {
+        Tap {
+            file_name: file_name.to_string(),
+            start: None,
+            loader: false,
+        }
+    }
+
+    pub fn is_tap(file_name: &str) -> bool {
+        file_name.to_lowercase().ends_with(".tap")
+    }
+
+    // Tape names are 10 characters padded with spaces
+    fn name(&self) -> [u8; 10] {
+        let stem = Path::new(&self.file_name).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let mut name = [b' '; 10];
+        for (i, c) in stem.bytes().filter(|&c| c.is_ascii_graphic() || c == b' ').take(10).enumerate() {
+            name[i] = c;
+        }
+        name
+    }
+
+    fn block(flag: u8, data: &[u8]) -> Vec<u8> {
+        let length = data.len() + 2;
+        let mut block = vec![length as u8, (length >> 8) as u8, flag];
+        block.extend_from_slice(data);
+        block.push(data.iter().fold(flag, |checksum, b| checksum ^ b));
+        block
+    }
+
+    fn header(&self, kind: u8, length: usize, param1: u16, param2: u16) -> Vec<u8> {
+        let mut header = vec![kind];
+        header.extend_from_slice(&self.name());
+        header.extend_from_slice(&(length as u16).to_le_bytes());
+        header.extend_from_slice(&param1.to_le_bytes());
+        header.extend_from_slice(&param2.to_le_bytes());
+        Tap::block(HEADER_FLAG, &header)
+    }
+
+    // 10 CLEAR VAL "address-1": LOAD "" CODE : RANDOMIZE USR VAL "start"
+    fn loader_program(address: isize, start: isize) -> Vec<u8> {
+        let mut line = vec![CLEAR, VAL];
+        line.extend(format!("\"{}\":", address - 1).bytes());
+        line.extend(&[LOAD, b'"', b'"', CODE_TOKEN, b':', RANDOMIZE, USR, VAL]);
+        line.extend(format!("\"{}\"", start).bytes());
+        line.push(0x0D);
+        let mut program = LOADER_LINE.to_be_bytes().to_vec();
+        program.extend_from_slice(&(line.len() as u16).to_le_bytes());
+        program.append(&mut line);
+        program
+    }
+
+    pub fn write(&self, address: isize, code: &[u8], start: isize) -> Result<(), Error> {
+        let mut file = File::create(&self.file_name)?;
+        if self.loader {
+            let program = Tap::loader_program(address, start);
+            file.write_all(&self.header(PROGRAM, program.len(), LOADER_LINE, program.len() as u16))?;
+            file.write_all(&Tap::block(DATA_FLAG, &program))?;
+        }
+        file.write_all(&self.header(CODE, code.len(), address as u16, 32768))?;
+        file.write_all(&Tap::block(DATA_FLAG, code))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::assembler::tap::Tap;
+
+    #[test]
+    fn code_header_layout() {
+        let tap = Tap::new("dir/my game loader.tap");
+        let block = tap.header(3, 0x1234, 0x8000, 32768);
+        assert_eq!(block[0..3], [19, 0, 0x00]);
+        assert_eq!(block[3], 3);
+        assert_eq!(&block[4..14], b"my game lo");
+        assert_eq!(block[14..20], [0x34, 0x12, 0x00, 0x80, 0x00, 0x80]);
+        let checksum = block[2..20].iter().fold(0, |c, b| c ^ b);
+        assert_eq!(block[20], checksum);
+        assert_eq!(block.len(), 21);
+    }
+
+    #[test]
+    fn short_names_are_padded() {
+        assert_eq!(&Tap::new("a.tap").name(), b"a         ");
+        assert_eq!(&Tap::new("my\tgame.tap").name(), b"mygame    ");
+        assert_eq!(&Tap::new("my game.tap").name(), b"my game   ");
+    }
+
+    #[test]
+    fn loader_program() {
+        let program = Tap::loader_program(0x8000, 0x8010);
+        assert_eq!(program[0..2], [0, 10]);
+        assert_eq!(program[2..4], [(program.len() - 4) as u8, 0]);
+        assert_eq!(program[4..6], [0xFD, 0xB0]);
+        assert_eq!(&program[6..14], b"\"32767\":");
+        assert_eq!(program[14..22], [0xEF, b'"', b'"', 0xAF, b':', 0xF9, 0xC0, 0xB0]);
+        assert_eq!(&program[22..29], b"\"32784\"");
+        assert_eq!(program[29], 0x0D);
+    }
+
+    #[test]
+    fn blocks() {
+        let file_name = std::env::temp_dir().join(format!("fantasm_{}_blocks.tap", std::process::id()));
+        let mut tap = Tap::new(file_name.to_str().unwrap());
+        tap.loader = true;
+        assert!(tap.write(0x8000, &[0xC9], 0x8000).is_ok());
+        let data = fs::read(&file_name).unwrap();
+        fs::remove_file(&file_name).ok();
+        let program_length = Tap::loader_program(0x8000, 0x8000).len();
+        let mut offset = 0;
+        let mut blocks = vec![];
+        while offset < data.len() {
+            let length = data[offset] as usize + ((data[offset + 1] as usize) << 8);
+            blocks.push((data[offset + 2], length));
+            offset += length + 2;
+        }
+        assert_eq!(offset, data.len());
+        assert_eq!(blocks, [(0x00, 19), (0xFF, program_length + 2), (0x00, 19), (0xFF, 3)]);
+        assert_eq!(data[data.len() - 3..], [0xFF, 0xC9, 0xFF ^ 0xC9]);
+    }
+}
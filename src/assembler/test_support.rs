@@ -0,0 +1,33 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::assembler::Assembler;
+use crate::assembler::error::{Error, ErrorLevel};
+
+// Helpers for the tests that assemble a whole source file
+
+pub fn temp_file(name: &str, extension: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("fantasm_{}_{}.{}", name, std::process::id(), extension))
+}
+
+pub fn assemble_with(name: &str, source: &str, setup: impl FnOnce(&mut Assembler)) -> (Assembler, Result<(), Error>) {
+    let path = temp_file(name, "asm");
+    fs::write(&path, source).unwrap();
+    let mut assembler = Assembler::new();
+    setup(&mut assembler);
+    let result = assembler.assemble(path.to_str().unwrap());
+    fs::remove_file(&path).ok();
+    (assembler, result)
+}
+
+pub fn assemble(name: &str, source: &str) -> (Assembler, Result<(), Error>) {
+    assemble_with(name, source, |_| {})
+}
+
+// The line & message of each error, warnings are left out
+pub fn errors(assembler: &Assembler) -> Vec<(isize, String)> {
+    assembler.diagnostics.iter()
+        .filter(|d| d.level == ErrorLevel::Error)
+        .map(|d| (d.line_no, d.message.clone()))
+        .collect()
+}
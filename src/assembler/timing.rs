@@ -0,0 +1,220 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Add;
+
+use crate::assembler::error::Error;
+use crate::assembler::unit_test::EncodedInstruction;
+use crate::disassembler::Disassembler;
+
+// The T-states an instruction takes, not_taken differs for conditional jumps, calls & returns
+// that fall through and for a repeating block instruction on its last iteration
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct TStates {
+    pub taken: usize,
+    pub not_taken: usize,
+}
+
+impl TStates {
+    fn fixed(t: usize) -> TStates {
+        TStates { taken: t, not_taken: t }
+    }
+
+    fn branch(taken: usize, not_taken: usize) -> TStates {
+        TStates { taken, not_taken }
+    }
+
+    pub fn is_conditional(&self) -> bool {
+        self.taken != self.not_taken
+    }
+
+    // Worked out from the encoded bytes, which are always a whole instruction
+    pub fn of(bytes: &[u8]) -> TStates {
+        let at = |i: usize| bytes.get(i).copied().unwrap_or(0);
+        match at(0) {
+            0xCB => TStates::cb(at(1)),
+            0xED => TStates::ed(at(1)),
+            0xDD | 0xFD => match at(1) {
+                0xCB if at(3) & 0xC0 == 0x40 => TStates::fixed(20),
+                0xCB => TStates::fixed(23),
+                // CSpect EXIT & BREAK
+                0x00 | 0x01 if bytes.len() == 2 => TStates::fixed(8),
+                op => TStates::index(op)
+            },
+            op => TStates::base(op)
+        }
+    }
+
+    fn base(op: u8) -> TStates {
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match (x, z) {
+            (0, 0) => match y {
+                0 | 1 => TStates::fixed(4),
+                2 => TStates::branch(13, 8),
+                3 => TStates::fixed(12),
+                _ => TStates::branch(12, 7)
+            },
+            (0, 1) => TStates::fixed(if q == 0 { 10 } else { 11 }),
+            (0, 2) => TStates::fixed([7, 7, 16, 13][p as usize]),
+            (0, 3) => TStates::fixed(6),
+            (0, 4) | (0, 5) => TStates::fixed(if y == 6 { 11 } else { 4 }),
+            (0, 6) => TStates::fixed(if y == 6 { 10 } else { 7 }),
+            (0, _) => TStates::fixed(4),
+            (1, _) => TStates::fixed(if (y == 6) != (z == 6) { 7 } else { 4 }),
+            (2, _) => TStates::fixed(if z == 6 { 7 } else { 4 }),
+            (_, 0) => TStates::branch(11, 5),
+            (_, 1) => TStates::fixed(match (q, p) {
+                (0, _) | (_, 0) => 10,
+                (_, 1) | (_, 2) => 4,
+                _ => 6
+            }),
+            (_, 2) => TStates::fixed(10),
+            (_, 3) => TStates::fixed([10, 0, 11, 11, 19, 4, 4, 4][y as usize]),
+            (_, 4) => TStates::branch(17, 10),
+            (_, 5) => TStates::fixed(if q == 0 { 11 } else { 17 }),
+            (_, 6) => TStates::fixed(7),
+            _ => TStates::fixed(11)
+        }
+    }
+
+    // IX & IY take 4 more for the prefix, (ix+d) replaces (hl) and also works out the address
+    fn index(op: u8) -> TStates {
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        match (x, z) {
+            (0, 4) | (0, 5) if y == 6 => TStates::fixed(23),
+            (0, 6) if y == 6 => TStates::fixed(19),
+            (1, _) if (y == 6) != (z == 6) => TStates::fixed(19),
+            (2, 6) => TStates::fixed(19),
+            _ => {
+                let t = TStates::base(op);
+                TStates::branch(t.taken + 4, t.not_taken + 4)
+            }
+        }
+    }
+
+    fn cb(op: u8) -> TStates {
+        match (op >> 6, op & 7) {
+            (1, 6) => TStates::fixed(12),
+            (_, 6) => TStates::fixed(15),
+            _ => TStates::fixed(8)
+        }
+    }
+
+    fn ed(op: u8) -> TStates {
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        match op {
+            0x23 | 0x24 | 0x28..=0x2C | 0x30..=0x33 | 0x93..=0x95 => TStates::fixed(8),
+            0x27 => TStates::fixed(11),
+            0x34..=0x36 => TStates::fixed(16),
+            0x8A => TStates::fixed(23),
+            0x90 => TStates::fixed(16),
+            0x91 => TStates::fixed(20),
+            0x92 => TStates::fixed(17),
+            0x98 => TStates::fixed(13),
+            0xA4 | 0xAC => TStates::fixed(16),
+            0xA5 => TStates::fixed(14),
+            0xB4 | 0xB7 | 0xBC => TStates::branch(21, 16),
+            _ => match (x, z) {
+                (1, 0) | (1, 1) => TStates::fixed(12),
+                (1, 2) => TStates::fixed(15),
+                (1, 3) => TStates::fixed(20),
+                (1, 5) => TStates::fixed(14),
+                (1, 7) if y < 4 => TStates::fixed(9),
+                (1, 7) if y < 6 => TStates::fixed(18),
+                (2, _) if z <= 3 && y >= 6 => TStates::branch(21, 16),
+                (2, _) if z <= 3 && y >= 4 => TStates::fixed(16),
+                _ => TStates::fixed(8)
+            }
+        }
+    }
+}
+
+impl Add for TStates {
+    type Output = TStates;
+
+    fn add(self, other: TStates) -> TStates {
+        TStates::branch(self.taken + other.taken, self.not_taken + other.not_taken)
+    }
+}
+
+impl fmt::Display for TStates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_conditional() {
+            write!(f, "{}/{}", self.taken, self.not_taken)
+        } else {
+            write!(f, "{}", self.taken)
+        }
+    }
+}
+
+// Every instruction assembled in the final pass with its T-states, conditional instructions
+// show the taken and not taken times
+pub fn write_report(file_name: &str, instructions: &[EncodedInstruction], disassembler: &mut Disassembler) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(file_name)?);
+    writeln!(file, "{:<24}{:>6}  {:<4}  {:>8}  Instruction", "File", "Line", "Addr", "T-states")?;
+    for instruction in instructions {
+        let decoded = disassembler.origin(instruction.address as u16).decode(&instruction.bytes, 0);
+        writeln!(file, "{:<24}{:>6}  {:04X}  {:>8}  {}", instruction.file_name, instruction.line_no,
+                 instruction.address, instruction.t_states.to_string(), decoded)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::timing::TStates;
+
+    fn t(bytes: &[u8]) -> (usize, usize) {
+        let t = TStates::of(bytes);
+        (t.taken, t.not_taken)
+    }
+
+    #[test]
+    fn base_instructions() {
+        assert_eq!(t(&[0x00]), (4, 4));
+        assert_eq!(t(&[0x3E, 0x01]), (7, 7));
+        assert_eq!(t(&[0x7E]), (7, 7));
+        assert_eq!(t(&[0x76]), (4, 4));
+        assert_eq!(t(&[0x36, 0x01]), (10, 10));
+        assert_eq!(t(&[0x34]), (11, 11));
+        assert_eq!(t(&[0x2A, 0x00, 0x80]), (16, 16));
+        assert_eq!(t(&[0x3A, 0x00, 0x80]), (13, 13));
+        assert_eq!(t(&[0xE3]), (19, 19));
+        assert_eq!(t(&[0xF9]), (6, 6));
+        assert_eq!(t(&[0xC5]), (11, 11));
+        assert_eq!(t(&[0xC1]), (10, 10));
+    }
+
+    #[test]
+    fn conditional_instructions() {
+        assert_eq!(t(&[0x20, 0x00]), (12, 7));
+        assert_eq!(t(&[0x10, 0x00]), (13, 8));
+        assert_eq!(t(&[0xC4, 0x00, 0x80]), (17, 10));
+        assert_eq!(t(&[0xC0]), (11, 5));
+        assert_eq!(t(&[0xED, 0xB0]), (21, 16));
+        assert_eq!(t(&[0xED, 0xB4]), (21, 16));
+        assert!(TStates::of(&[0x20, 0x00]).is_conditional());
+        assert_eq!(TStates::of(&[0x20, 0x00]).to_string(), "12/7");
+    }
+
+    #[test]
+    fn prefixed_instructions() {
+        assert_eq!(t(&[0xCB, 0x46]), (12, 12));
+        assert_eq!(t(&[0xCB, 0xC6]), (15, 15));
+        assert_eq!(t(&[0xDD, 0x36, 0x05, 0x01]), (19, 19));
+        assert_eq!(t(&[0xDD, 0x34, 0x05]), (23, 23));
+        assert_eq!(t(&[0xDD, 0x21, 0x00, 0x80]), (14, 14));
+        assert_eq!(t(&[0xDD, 0xCB, 0x01, 0x46]), (20, 20));
+        assert_eq!(t(&[0xDD, 0xCB, 0x01, 0xC6]), (23, 23));
+        assert_eq!(t(&[0xED, 0x5B, 0x00, 0x80]), (20, 20));
+        assert_eq!(t(&[0xED, 0x91, 0x07, 0x03]), (20, 20));
+        assert_eq!(t(&[0xED, 0x98]), (13, 13));
+    }
+
+    #[test]
+    fn sum() {
+        let total = TStates::of(&[0x00]) + TStates::of(&[0x10, 0x00]);
+        assert_eq!((total.taken, total.not_taken), (17, 12));
+    }
+}
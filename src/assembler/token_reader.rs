@@ -5,10 +5,13 @@ use crate::assembler::error_type::ErrorType;
 use crate::assembler::token_traits::Tokens;
 use crate::assembler::TokenReader;
 use crate::assembler::tokens::{Cnd, Op, Reg, RegPair, RegPairInd, Token};
-use crate::assembler::tokens::Functions::SizeOf;
+use crate::assembler::tokens::Functions::{Defined, SizeOf};
 use crate::assembler::tokens::Op::{LParens, RParens};
 use crate::assembler::tokens::Token::{Condition, ConstLabel, IndexIndirect, IndirectExpression, Number, Operator, Register, RegisterIndirect, RegisterPair};
 
+pub const OPERATORS: &str = "()*/+-<>=^&|%~!";
+pub const DELIMITERS: &str = ",";
+
 impl<R> TokenReader<R> where R: BufRead {
     pub fn new(reader: R) -> TokenReader<R> {
         TokenReader {
@@ -22,6 +25,7 @@ impl<R> TokenReader<R> where R: BufRead {
             preceding_token: Token::EndOfFile,
             file_name: String::default(),
             whitespace_at_start: false,
+            source_line: String::new(),
         }
     }
 
@@ -40,25 +44,57 @@ impl<R> TokenReader<R> where R: BufRead {
         self
     }
 
+    pub fn source_line(&self) -> &str {
+        &self.source_line
+    }
+
     fn store_token_string(&mut self) {
         if !self.token_string.is_empty() {
-            self.words.push(self.token_string.replace("\\", ""));
+            let word = self.token_string.replace("\\", "");
+            // !opt and !message start with the logical not operator
+            if self.words.len() == 1 && self.words[0] == "!" {
+                self.words[0].push_str(&word);
+            } else {
+                self.words.push(word);
+            }
             self.token_string.clear();
         }
     }
 
-    fn split_line(&mut self, line: &String) {
+    fn double_operator(&self, previous: char, c: char) -> Option<String> {
+        let op = match (previous, c) {
+            ('<', '<') | ('>', '>') | ('<', '=') | ('>', '=') |
+            ('=', '=') | ('!', '=') | ('&', '&') | ('|', '|') => format!("{}{}", previous, c),
+            _ => return None
+        };
+        if self.token_string.is_empty() && self.words.last() == Some(&previous.to_string()) {
+            Some(op)
+        } else {
+            None
+        }
+    }
+
+    fn follows_operand(&self) -> bool {
+        match self.words.last() {
+            Some(w) if w == ")" => true,
+            Some(w) => matches!(Token::from_string(w.clone()), Number(_) | ConstLabel(_) | Operator(Op::AsmPc)),
+            None => false
+        }
+    }
+
+    fn split_line(&mut self, line: &str) {
         let mut in_quotes = false;
         self.words = vec![];
 
-        let mut pos = 0;
         self.whitespace_at_start = false;
 
-        for c in line.chars() {
+        let mut previous = '\0';
+        for (pos, c) in line.chars().enumerate() {
             if pos == 0 && c.is_whitespace() {
                 self.whitespace_at_start = true;
             }
-            pos += 1;
+            let preceding = previous;
+            previous = c;
             if in_quotes {
                 self.token_string.push(c);
                 if (c == '\"' || c == '\'') && !self.token_string.ends_with("\\\"") {
@@ -81,15 +117,19 @@ impl<R> TokenReader<R> where R: BufRead {
                     continue;
                 }
                 // check for operators that are double characters
-                s @ '<' | s @ '>' => if self.words.last() == Some(&s.to_string()) {
+                '<' | '>' | '=' | '&' | '|' => if let Some(op) = self.double_operator(preceding, c) {
                     self.words.pop();
-                    self.words.push(format!("{}{}", s, s));
-                    self.token_string.clear();
+                    self.words.push(op);
+                    continue;
+                }
+                // % is a binary number prefix unless it follows an operand, then it is modulo
+                '%' if self.token_string.is_empty() && !self.follows_operand() => {
+                    self.token_string.push(c);
                     continue;
                 }
                 _ => {}
             }
-            if c == ':' && self.words.len() > 0 {
+            if c == ':' && !self.words.is_empty() {
                 self.store_token_string();
                 continue;
             }
@@ -111,27 +151,36 @@ impl<R> TokenReader<R> where R: BufRead {
     }
 
     fn parse_function(&mut self, keyword: &str) -> Option<Token> {
-        let x = match keyword {
+        match keyword {
             "sizeof" => {
-                if &self.words.pop().unwrap_or(String::new()) != "(" {
+                if &self.words.pop().unwrap_or_default() != "(" {
                     return None;
                 }
-                let label = self.words.pop().unwrap_or(String::new());
-                if &self.words.pop().unwrap_or(String::new()) != ")" {
+                let label = self.words.pop().unwrap_or_default();
+                if &self.words.pop().unwrap_or_default() != ")" {
                     return None;
                 }
                 Some(Token::Function(SizeOf(label)))
             }
+            "defined" => {
+                if &self.words.pop().unwrap_or_default() != "(" {
+                    return None;
+                }
+                let label = self.words.pop().unwrap_or_default();
+                if &self.words.pop().unwrap_or_default() != ")" {
+                    return None;
+                }
+                Some(Token::Function(Defined(label)))
+            }
             _ => None
-        };
-        x
+        }
     }
 
     fn next_token(&mut self) -> Option<Token> {
         if self.words.is_empty() {
             return None;
         }
-        let w = self.words.pop().unwrap_or(String::new());
+        let w = self.words.pop().unwrap_or_default();
         let mut tok = self.parse_function(&w.to_lowercase()).unwrap_or(Token::from_string(w));
         if self.preceding_token.can_be_conditional() && tok == Register(Reg::C) {
             tok = Condition(Cnd::C)
@@ -141,15 +190,8 @@ impl<R> TokenReader<R> where R: BufRead {
     }
 
     fn handle_index_indirect(&mut self, tokens: &mut Vec<Token>, rp: RegPair) -> Result<Option<Token>, Error> {
-        let valid = match tokens.last() {
-            Some(Operator(Op::Sub)) |
-            Some(Operator(Op::Add)) => true,
-            None => true,
-            _ => false
-        };
-
-        if !valid {
-            return Err(Error::fatal("Invalid operand, Expected +/-", self.line_number, &self.file_name));
+        if !matches!(tokens.last(), Some(Operator(Op::Sub)) | Some(Operator(Op::Add)) | None) {
+            return Err(Error::error("Invalid operand, Expected +/-", self.line_number, &self.file_name));
         }
 
         let mut expr = tokens.clone();
@@ -157,7 +199,7 @@ impl<R> TokenReader<R> where R: BufRead {
         expr.reverse();
         let ret = Ok(Some(IndexIndirect(rp, expr)));
         tokens.clear();
-        return ret;
+        ret
     }
 
     fn handle_parentheses(&mut self, s: usize, e: usize) -> Result<(), Error> {
@@ -194,9 +236,10 @@ impl<R> TokenReader<R> where R: BufRead {
         let mut parens: Vec<usize> = vec![];
         self.line_number += 1;
         let count = self.reader.read_line(&mut line)?;
-        if count <= 0 {
+        if count == 0 {
             return Ok(vec![Token::EndOfFile]);
         }
+        self.source_line = line.trim_end_matches(['\r', '\n']).to_string();
         self.split_line(&line);
         self.tokens.clear();
         let mut pos = 0;
@@ -211,7 +254,7 @@ impl<R> TokenReader<R> where R: BufRead {
                         self.handle_parentheses(s, pos + 1)?;
                         continue;
                     } else {
-                        return Err(Error::fatal(&ErrorType::UnexpectedClose.to_string(), self.line_number, &self.file_name));
+                        return Err(Error::error(&ErrorType::UnexpectedClose.to_string(), self.line_number, &self.file_name));
                     }
                 }
                 _ => {}
@@ -220,7 +263,7 @@ impl<R> TokenReader<R> where R: BufRead {
             pos += 1;
         }
         if !parens.is_empty() {
-            return Err(Error::fatal(&ErrorType::UnclosedParentheses.to_string(), self.line_number, &self.file_name));
+            return Err(Error::error(&ErrorType::UnclosedParentheses.to_string(), self.line_number, &self.file_name));
         }
 
         Ok(self.tokens.to_owned())
@@ -1,8 +1,10 @@
+use std::fmt;
+
 use crate::assembler::tokens::{Cnd, Del, Directive, Ir, IxU, IyU, Op, OpCode, OptionType, Reg, RegPair, RegPairInd, Token};
 
-impl ToString for OpCode {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             OpCode::Nop => "nop",
             OpCode::Adc => "adc",
             OpCode::Add => "add",
@@ -93,13 +95,13 @@ impl ToString for OpCode {
             OpCode::Bsrl => "bsrl",
             OpCode::Bsrf => "bsrf",
             OpCode::Brlc => "brlc"
-        }.to_string()
+        })
     }
 }
 
-impl ToString for Directive {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             Directive::Org => "ORG",
             Directive::Include => "INCLUDE",
             Directive::Binary => "INCBIN",
@@ -115,6 +117,7 @@ impl ToString for Directive {
             Directive::If => "IF",
             Directive::IfDef => "IFDEF",
             Directive::Else => "ELSE",
+            Directive::ElseIf => "ELSEIF",
             Directive::EndIf => "ENDIF",
             Directive::Global => "GLOBAL",
             Directive::Define => "DEFINE",
@@ -122,14 +125,40 @@ impl ToString for Directive {
             Directive::Enum => "ENUM",
             Directive::EndEnum => "ENDE",
             Directive::Struct => "STRUCT",
-            Directive::EndStruct => "ENDS"
-        }.to_string()
+            Directive::EndStruct => "ENDS",
+            Directive::SaveNex => "SAVENEX",
+            Directive::Device => "DEVICE",
+            Directive::Slot => "SLOT",
+            Directive::Page => "PAGE",
+            Directive::Mmu => "MMU",
+            Directive::SaveSna => "SAVESNA",
+            Directive::SaveTap => "SAVETAP",
+            Directive::UnitTest => "UNITTEST",
+            Directive::AssertReg => "ASSERT_REG",
+            Directive::AssertMem => "ASSERT_MEM",
+            Directive::Timing => "TIMING",
+            Directive::Rept => "REPT",
+            Directive::EndRept => "ENDR",
+            Directive::Assert => "ASSERT",
+            Directive::UserError => "ERROR",
+            Directive::UserWarning => "WARNING",
+            Directive::Align => "ALIGN",
+            Directive::Phase => "PHASE",
+            Directive::Dephase => "DEPHASE",
+            Directive::Section => "SECTION",
+            Directive::Memory => "MEMORY",
+            Directive::EndMemory => "ENDMEMORY",
+            Directive::Place => "PLACE",
+            Directive::Module => "MODULE",
+            Directive::EndModule => "ENDMODULE",
+            Directive::Local => "LOCAL"
+        })
     }
 }
 
-impl ToString for RegPair {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for RegPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             RegPair::Bc => "bc",
             RegPair::De => "de",
             RegPair::Hl => "hl",
@@ -138,22 +167,22 @@ impl ToString for RegPair {
             RegPair::Iy => "iy",
             RegPair::Af => "af",
             RegPair::_Af => "af'",
-        }.to_string()
+        })
     }
 }
 
-impl ToString for Ir {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Ir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             Ir::I => "I",
             Ir::R => "R",
-        }.to_string()
+        })
     }
 }
 
-impl ToString for Reg {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             Reg::B => "b",
             Reg::C => "c",
             Reg::D => "d",
@@ -162,39 +191,39 @@ impl ToString for Reg {
             Reg::L => "l",
             Reg::_HL_ => "(hl)",
             Reg::A => "a"
-        }.to_string()
+        })
     }
 }
 
-impl ToString for IxU {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for IxU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             IxU::Ixh => "ixh",
             IxU::Ixl => "ixl",
-        }.to_string()
+        })
     }
 }
 
-impl ToString for IyU {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for IyU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             IyU::Iyh => "iyh",
             IyU::Iyl => "iyl",
-        }.to_string()
+        })
     }
 }
 
-impl ToString for Del {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Del {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             Del::Comma => ",",
-        }.to_string()
+        })
     }
 }
 
-impl ToString for Op {
-    fn to_string(&self) -> String {
-        let x = match self {
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             Op::Add => "+",
             Op::Sub => "-",
             Op::Mul => "*",
@@ -209,25 +238,34 @@ impl ToString for Op {
             Op::Ampersand => "&",
             Op::Pipe => "|",
             Op::AsmPc => "$",
-        }.to_string();
-        x
+            Op::Mod => "%",
+            Op::Caret => "^",
+            Op::Tilde => "~",
+            Op::Not => "!",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Le => "<=",
+            Op::Ge => ">=",
+            Op::LogicalAnd => "&&",
+            Op::LogicalOr => "||",
+        })
     }
 }
 
-impl ToString for RegPairInd {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for RegPairInd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             RegPairInd::Bc => "(bc)",
             RegPairInd::De => "(de)",
             RegPairInd::Sp => "(sp)",
             RegPairInd::C => "(c)",
-        }.to_string()
+        })
     }
 }
 
-impl ToString for Cnd {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Cnd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             Cnd::Nz => "nz",
             Cnd::Z => "z",
             Cnd::NC => "nc",
@@ -236,25 +274,27 @@ impl ToString for Cnd {
             Cnd::PE => "pe",
             Cnd::P => "p",
             Cnd::M => "m",
-        }.to_string()
+        })
     }
 }
 
-impl ToString for OptionType {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for OptionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             OptionType::Verbose => "verbose",
             OptionType::CSpect => "cspect",
             OptionType::Z80n => "z80n",
-            OptionType::MaxCodeSize => "maxcodesize"
-        }.to_string()
+            OptionType::MaxCodeSize => "maxcodesize",
+            OptionType::JrPromote => "jrpromote",
+            OptionType::Listing => "listing",
+        })
     }
 }
 
 
-impl ToString for Token {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
             Token::None => "None".to_string(),
             Token::Invalid => "Invalid".to_string(),
             Token::EndOfFile => "EOF".to_string(),
@@ -270,17 +310,18 @@ impl ToString for Token {
             Token::Delimiter(del) => del.to_string(),
             Token::Operator(op) => op.to_string(),
             Token::RegisterIndirect(ri) => ri.to_string(),
-            Token::IndexIndirect(r, i) => format!("({}+{:?})", r.to_string(), i),
+            Token::IndexIndirect(r, i) => format!("({}+{:?})", r, i),
             Token::Condition(c) => c.to_string(),
             Token::StringLiteral(l) => l.to_string(),
             Token::Opt(o) => o.to_string(),
             Token::Boolean(b) => format!("{}", b),
             Token::IndirectExpression(i) => {
-                let t: Vec<String> = i.into_iter().map(|e| e.to_string()).collect();
+                let t: Vec<String> = i.iter().map(|e| e.to_string()).collect();
                 t.join(" ")
             }
             Token::MacroParam(mp) => mp.to_string(),
             Token::Function(_) => "".to_string()
-        }
+        };
+        f.write_str(&text)
     }
 }
\ No newline at end of file
@@ -10,19 +10,17 @@ use crate::assembler::tokens::Token::{ConstLabel, Function, IndexIndirect, Indir
 use crate::assembler::zx_ascii::ZXAscii;
 
 lazy_static! {
-    static ref LABEL: Regex = Regex::new(r"^\.?[\w.]*:?$").unwrap();
+    static ref LABEL: Regex = Regex::new(r"^(@@|[.@]?[\w.]*):?$").unwrap();
 }
 
 pub trait Tokens {
     fn from_string(word: String) -> Token;
-    fn is_index_prefix(&self) -> Option<u8>;
     fn is_indirect(&self) -> bool;
     fn is_reg(&self) -> bool;
     fn is_reg_pair(&self) -> bool;
     fn is_special_reg(&self) -> bool;
     fn is_expression(&self) -> bool;
     fn can_be_conditional(&self) -> bool;
-    fn number_to_u8(&self) -> Option<u8>;
     fn reg_value(&self) -> Option<u8>;
 }
 
@@ -45,6 +43,8 @@ impl FromStr for OptionType {
             "cspect" => Ok(OptionType::CSpect),
             "z80n" => Ok(OptionType::Z80n),
             "maxcodesize" => Ok(OptionType::MaxCodeSize),
+            "jrpromote" => Ok(OptionType::JrPromote),
+            "listing" => Ok(OptionType::Listing),
             _ => Err(())
         }
     }
@@ -181,7 +181,16 @@ impl FromStr for Directive {
             "ds" | "block" => Ok(Directive::Block),
             "dh" | "hex" => Ok(Directive::Hex),
             "!opt" | "#pragma" => Ok(Directive::Opt),
-            //"align" => Ok(Directive::Align),
+            "align" => Ok(Directive::Align),
+            "phase" | "disp" => Ok(Directive::Phase),
+            "dephase" | "ent" => Ok(Directive::Dephase),
+            "section" => Ok(Directive::Section),
+            "memory" => Ok(Directive::Memory),
+            "endmemory" => Ok(Directive::EndMemory),
+            "place" => Ok(Directive::Place),
+            "module" => Ok(Directive::Module),
+            "endmodule" => Ok(Directive::EndModule),
+            "local" => Ok(Directive::Local),
             "macro" => Ok(Directive::Macro),
             "end" | "endm" => Ok(Directive::End),
             "dz" => Ok(Directive::StringZero),
@@ -189,10 +198,13 @@ impl FromStr for Directive {
             "ifdef" => Ok(Directive::IfDef),
             "ifndef" => Ok(Directive::IfNotDef),
             "else" => Ok(Directive::Else),
+            "elseif" | "elif" => Ok(Directive::ElseIf),
             "endif" => Ok(Directive::EndIf),
+            "#if" => Ok(Directive::If),
             "#ifdef" => Ok(Directive::IfDef),
             "#ifndef" => Ok(Directive::IfNotDef),
             "#else" => Ok(Directive::Else),
+            "#elif" => Ok(Directive::ElseIf),
             "#endif" => Ok(Directive::EndIf),
             "global" => Ok(Directive::Global),
             "#define" => Ok(Directive::Define),
@@ -200,6 +212,22 @@ impl FromStr for Directive {
             "ende" => Ok(Directive::EndEnum),
             "struct" => Ok(Directive::Struct),
             "ends" => Ok(Directive::EndStruct),
+            "savenex" => Ok(Directive::SaveNex),
+            "device" => Ok(Directive::Device),
+            "slot" => Ok(Directive::Slot),
+            "page" => Ok(Directive::Page),
+            "mmu" => Ok(Directive::Mmu),
+            "savesna" => Ok(Directive::SaveSna),
+            "savetap" => Ok(Directive::SaveTap),
+            "unittest" => Ok(Directive::UnitTest),
+            "assert_reg" => Ok(Directive::AssertReg),
+            "assert_mem" => Ok(Directive::AssertMem),
+            "timing" => Ok(Directive::Timing),
+            "rept" | "dup" => Ok(Directive::Rept),
+            "endr" | "edup" => Ok(Directive::EndRept),
+            "assert" => Ok(Directive::Assert),
+            "error" | "!error" => Ok(Directive::UserError),
+            "warning" | "!warning" => Ok(Directive::UserWarning),
             _ => Err(())
         }
     }
@@ -233,6 +261,16 @@ impl FromStr for Op {
             "&" => Ok(Op::Ampersand),
             "|" => Ok(Op::Pipe),
             "$" | "asmpc" => Ok(Op::AsmPc),
+            "%" => Ok(Op::Mod),
+            "^" => Ok(Op::Caret),
+            "~" => Ok(Op::Tilde),
+            "!" => Ok(Op::Not),
+            "==" => Ok(Op::Eq),
+            "!=" => Ok(Op::Ne),
+            "<=" => Ok(Op::Le),
+            ">=" => Ok(Op::Ge),
+            "&&" => Ok(Op::LogicalAnd),
+            "||" => Ok(Op::LogicalOr),
             _ => Err(())
         }
     }
@@ -243,8 +281,8 @@ impl Tokens for Token {
         let w = word.to_lowercase();
 
         // string literals
-        let is_double_quoted = word.starts_with("\"") & &word.ends_with("\"");
-        let is_single_quoted = word.starts_with("\'") & &word.ends_with("\'");
+        let is_double_quoted = word.starts_with("\"") & word.ends_with("\"");
+        let is_single_quoted = word.starts_with("\'") & word.ends_with("\'");
 
         if is_single_quoted && word.chars().count() == 3 {
             let zx_string = ZXAscii::zx_safe(&word);
@@ -327,14 +365,6 @@ impl Tokens for Token {
         Token::Invalid
     }
 
-    fn is_index_prefix(&self) -> Option<u8> {
-        match self {
-            RegisterPair(RegPair::Ix) | RegisterIX(_) => Some(0xDD),
-            RegisterPair(RegPair::Iy) | RegisterIY(_) => Some(0xFD),
-            _ => None
-        }
-    }
-
     fn is_indirect(&self) -> bool {
         match self {
             RegisterIndirect(_) => true,
@@ -346,57 +376,23 @@ impl Tokens for Token {
     }
 
     fn is_reg(&self) -> bool {
-        match self {
-            Register(_) | RegisterIX(_) | RegisterIY(_) => true,
-            _ => false
-        }
+        matches!(self, Register(_) | RegisterIX(_) | RegisterIY(_))
     }
 
     fn is_reg_pair(&self) -> bool {
-        match self {
-            RegisterPair(_) => true,
-            _ => false
-        }
+        matches!(self, RegisterPair(_))
     }
 
     fn is_special_reg(&self) -> bool {
-        match self {
-            RegisterPair(RegPair::Sp) => true,
-            RegisterIR(_) => true,
-            _ => false
-        }
+        matches!(self, RegisterPair(RegPair::Sp) | RegisterIR(_))
     }
 
     fn is_expression(&self) -> bool {
-        match self.clone() {
-            Number(_) => true,
-            Operator(_) => true,
-            ConstLabel(_) => true,
-            Function(_) => true,
-            _ => false
-        }
+        matches!(self, Number(_) | Operator(_) | ConstLabel(_) | Function(_))
     }
 
     fn can_be_conditional(&self) -> bool {
-        match self {
-            Token::OpCode(OpCode::Jr) => true,
-            Token::OpCode(OpCode::Ret) => true,
-            Token::OpCode(OpCode::Call) => true,
-            Token::OpCode(OpCode::Jp) => true,
-            _ => false
-        }
-    }
-
-    fn number_to_u8(&self) -> Option<u8> {
-        match self {
-            Number(n) => if (0..256).contains(n) {
-                Some(n.clone() as u8)
-            } else { None }
-//            AddressIndirect(n) => if (0..256).contains(n) {
-//                Some(n.clone() as u8)
-//            } else { None }
-            _ => None
-        }
+        matches!(self, Token::OpCode(OpCode::Jr) | Token::OpCode(OpCode::Ret) | Token::OpCode(OpCode::Call) | Token::OpCode(OpCode::Jp))
     }
 
     fn reg_value(&self) -> Option<u8> {
@@ -404,7 +400,7 @@ impl Tokens for Token {
             Register(r) => Some(r.clone() as u8),
             RegisterIX(r) => Some(r.clone() as u8),
             RegisterIY(r) => Some(r.clone() as u8),
-            RegisterPair(r) => Some(r.clone() as u8),
+            RegisterPair(r) => Some(*r as u8),
             _ => None
         }
     }
@@ -113,12 +113,39 @@ pub enum Directive {
     IfDef,
     IfNotDef,
     Else,
+    ElseIf,
     EndIf,
     Global,
     Enum,
     EndEnum,
     Struct,
     EndStruct,
+    SaveNex,
+    Device,
+    Slot,
+    Page,
+    Mmu,
+    SaveSna,
+    SaveTap,
+    UnitTest,
+    AssertReg,
+    AssertMem,
+    Timing,
+    Rept,
+    EndRept,
+    Assert,
+    UserError,
+    UserWarning,
+    Align,
+    Phase,
+    Dephase,
+    Section,
+    Memory,
+    EndMemory,
+    Place,
+    Module,
+    EndModule,
+    Local,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -142,11 +169,22 @@ pub enum Op {
     Ampersand,
     Pipe,
     AsmPc,
+    Mod,
+    Caret,
+    Tilde,
+    Not,
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Functions {
-    SizeOf(String)
+    SizeOf(String),
+    Defined(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -253,6 +291,8 @@ pub enum OptionType {
     CSpect,
     Z80n,
     MaxCodeSize,
+    JrPromote,
+    Listing,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -0,0 +1,49 @@
+use crate::assembler::timing::TStates;
+
+// Entry points declared with UNITTEST and the expectations checked once each has run
+#[derive(Debug, Clone)]
+pub enum Expectation {
+    Register(String, isize),
+    Memory(isize, Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    pub expect: Expectation,
+    pub file_name: String,
+    pub line_no: isize,
+}
+
+#[derive(Debug, Clone)]
+pub struct UnitTest {
+    pub name: String,
+    pub entry: isize,
+    pub file_name: String,
+    pub line_no: isize,
+    pub assertions: Vec<Assertion>,
+}
+
+// An instruction as assembled, used to check the emulator decodes the same instruction
+// and for the T-state timings
+#[derive(Debug, Clone)]
+pub struct EncodedInstruction {
+    pub address: isize,
+    pub bytes: Vec<u8>,
+    pub t_states: TStates,
+    pub file_name: String,
+    pub line_no: isize,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub file_name: String,
+    pub line_no: isize,
+    pub failures: Vec<String>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
@@ -0,0 +1,337 @@
+use crate::disassembler::{Instruction, Operand};
+
+const R: [&str; 8] = ["b", "c", "d", "e", "h", "l", "(hl)", "a"];
+const RP: [&str; 4] = ["bc", "de", "hl", "sp"];
+const RP2: [&str; 4] = ["bc", "de", "hl", "af"];
+const CC: [&str; 8] = ["nz", "z", "nc", "c", "po", "pe", "p", "m"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ROT: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "sll", "srl"];
+const BLOCK: [[&str; 4]; 4] = [
+    ["ldi", "cpi", "ini", "outi"],
+    ["ldd", "cpd", "ind", "outd"],
+    ["ldir", "cpir", "inir", "otir"],
+    ["lddr", "cpdr", "indr", "otdr"]
+];
+
+type Decoded = (&'static str, Vec<Operand>);
+
+// Which register pair stands in for HL, set by the DD & FD prefixes
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Index {
+    Hl,
+    Ix,
+    Iy,
+}
+
+fn t(s: &str) -> Operand {
+    Operand::Text(s.to_string())
+}
+
+fn alu(y: u8, operand: Operand) -> Decoded {
+    match y {
+        0 | 1 | 3 => (ALU[y as usize], vec![t("a"), operand]),
+        _ => (ALU[y as usize], vec![operand])
+    }
+}
+
+// Decodes one instruction, anything FantASM would not assemble back to the same bytes becomes a DB
+pub(super) struct Decoder<'a> {
+    code: &'a [u8],
+    first: usize,
+    pos: usize,
+    address: u16,
+    index: Index,
+    indexed: bool,
+    z80n: bool,
+    cspect: bool,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(code: &'a [u8], offset: usize, address: u16, z80n: bool, cspect: bool) -> Decoder<'a> {
+        Decoder { code, first: offset, pos: offset, address, index: Index::Hl, indexed: false, z80n, cspect }
+    }
+
+    pub fn decode(mut self) -> Instruction {
+        match self.instruction() {
+            Some((mnemonic, operands)) => self.result(mnemonic, operands),
+            None => self.result("db", vec![])
+        }
+    }
+
+    fn result(&self, mnemonic: &str, operands: Vec<Operand>) -> Instruction {
+        let bytes = self.code[self.first..self.pos].to_vec();
+        let operands = if mnemonic == "db" {
+            bytes.iter().map(|b| Operand::Text(format!("0x{:02x}", b))).collect()
+        } else {
+            operands
+        };
+        Instruction { address: self.address, bytes, mnemonic: mnemonic.to_string(), operands }
+    }
+
+    fn instruction(&mut self) -> Option<Decoded> {
+        let mut opcode = self.byte()?;
+        if opcode == 0xDD || opcode == 0xFD {
+            self.index = if opcode == 0xDD { Index::Ix } else { Index::Iy };
+            opcode = self.byte()?;
+            if self.cspect && self.index == Index::Ix && opcode <= 1 {
+                return Some((if opcode == 0 { "exit" } else { "break" }, vec![]));
+            }
+        }
+        let decoded = match opcode {
+            0xDD | 0xED | 0xFD if self.index != Index::Hl => None,
+            0xCB if self.index == Index::Hl => self.cb(),
+            0xCB => self.index_cb(),
+            0xED => self.ed(),
+            _ => self.base(opcode)
+        };
+        // A prefix that changes nothing is kept as a byte on its own
+        if self.index != Index::Hl && opcode != 0xCB && (decoded.is_none() || !self.indexed) {
+            self.pos = self.first + 1;
+            return None;
+        }
+        decoded
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let b = *self.code.get(self.pos)?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn word(&mut self) -> Option<u16> {
+        let lo = self.byte()? as u16;
+        Some(lo | (self.byte()? as u16) << 8)
+    }
+
+    fn n(&mut self) -> Option<Operand> {
+        Some(Operand::Text(format!("0x{:02x}", self.byte()?)))
+    }
+
+    fn nn(&mut self) -> Option<Operand> {
+        Some(Operand::Word(self.word()?))
+    }
+
+    fn relative(&mut self) -> Option<Operand> {
+        let d = self.byte()? as i8;
+        let next = self.address.wrapping_add((self.pos - self.first) as u16);
+        Some(Operand::Address(next.wrapping_add(d as u16)))
+    }
+
+    fn displacement(&mut self) -> Option<Operand> {
+        let d = self.byte()? as i8;
+        let name = self.hl();
+        Some(Operand::Text(if d < 0 {
+            format!("({}-{})", name, -(d as i16))
+        } else {
+            format!("({}+{})", name, d)
+        }))
+    }
+
+    fn hl(&mut self) -> &'static str {
+        self.indexed = true;
+        match self.index {
+            Index::Hl => "hl",
+            Index::Ix => "ix",
+            Index::Iy => "iy"
+        }
+    }
+
+    fn r(&mut self, i: u8) -> Option<Operand> {
+        if self.index == Index::Hl || !(4..=6).contains(&i) {
+            return Some(t(R[i as usize]));
+        }
+        if i == 6 {
+            return self.displacement();
+        }
+        Some(Operand::Text(format!("{}{}", self.hl(), R[i as usize])))
+    }
+
+    fn rp(&mut self, p: u8) -> Operand {
+        if p == 2 { t(self.hl()) } else { t(RP[p as usize]) }
+    }
+
+    fn rp2(&mut self, p: u8) -> Operand {
+        if p == 2 { t(self.hl()) } else { t(RP2[p as usize]) }
+    }
+
+    fn base(&mut self, op: u8) -> Option<Decoded> {
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+        Some(match (x, z) {
+            (0, 0) => match y {
+                0 => ("nop", vec![]),
+                1 => ("ex", vec![t("af"), t("af'")]),
+                2 => ("djnz", vec![self.relative()?]),
+                3 => ("jr", vec![self.relative()?]),
+                _ => ("jr", vec![t(CC[y as usize - 4]), self.relative()?])
+            },
+            (0, 1) if q == 0 => ("ld", vec![self.rp(p), self.nn()?]),
+            (0, 1) => ("add", vec![t(self.hl()), self.rp(p)]),
+            (0, 2) => match (p, q) {
+                (0, 0) => ("ld", vec![t("(bc)"), t("a")]),
+                (1, 0) => ("ld", vec![t("(de)"), t("a")]),
+                (2, 0) => ("ld", vec![Operand::Memory(self.word()?), t(self.hl())]),
+                (3, 0) => ("ld", vec![Operand::Memory(self.word()?), t("a")]),
+                (0, _) => ("ld", vec![t("a"), t("(bc)")]),
+                (1, _) => ("ld", vec![t("a"), t("(de)")]),
+                (2, _) => ("ld", vec![t(self.hl()), Operand::Memory(self.word()?)]),
+                _ => ("ld", vec![t("a"), Operand::Memory(self.word()?)])
+            },
+            (0, 3) => (if q == 0 { "inc" } else { "dec" }, vec![self.rp(p)]),
+            (0, 4) => ("inc", vec![self.r(y)?]),
+            (0, 5) => ("dec", vec![self.r(y)?]),
+            (0, 6) => ("ld", vec![self.r(y)?, self.n()?]),
+            (0, _) => (["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"][y as usize], vec![]),
+            (1, _) if y == 6 && z == 6 => ("halt", vec![]),
+            // With an index prefix the other side of (ix+d) is still H or L
+            (1, _) if y == 6 => ("ld", vec![self.r(6)?, t(R[z as usize])]),
+            (1, _) if z == 6 => ("ld", vec![t(R[y as usize]), self.r(6)?]),
+            (1, _) => ("ld", vec![self.r(y)?, self.r(z)?]),
+            (2, _) => {
+                let operand = self.r(z)?;
+                alu(y, operand)
+            }
+            (_, 0) => ("ret", vec![t(CC[y as usize])]),
+            (_, 1) => match (q, p) {
+                (0, _) => ("pop", vec![self.rp2(p)]),
+                (_, 0) => ("ret", vec![]),
+                (_, 1) => ("exx", vec![]),
+                (_, 2) => ("jp", vec![Operand::Text(format!("({})", self.hl()))]),
+                _ => ("ld", vec![t("sp"), t(self.hl())])
+            },
+            (_, 2) => ("jp", vec![t(CC[y as usize]), Operand::Address(self.word()?)]),
+            (_, 3) => match y {
+                0 => ("jp", vec![Operand::Address(self.word()?)]),
+                2 => ("out", vec![Operand::Text(format!("(0x{:02x})", self.byte()?)), t("a")]),
+                3 => ("in", vec![t("a"), Operand::Text(format!("(0x{:02x})", self.byte()?))]),
+                4 => ("ex", vec![t("(sp)"), t(self.hl())]),
+                5 => ("ex", vec![t("de"), t("hl")]),
+                6 => ("di", vec![]),
+                _ => ("ei", vec![])
+            },
+            (_, 4) => ("call", vec![t(CC[y as usize]), Operand::Address(self.word()?)]),
+            (_, 5) if q == 0 => ("push", vec![self.rp2(p)]),
+            (_, 5) => ("call", vec![Operand::Address(self.word()?)]),
+            (_, 6) => {
+                let operand = self.n()?;
+                alu(y, operand)
+            }
+            _ => ("rst", vec![Operand::Text(format!("0x{:02x}", y * 8))])
+        })
+    }
+
+    fn cb(&mut self) -> Option<Decoded> {
+        let op = self.byte()?;
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let r = t(R[z as usize]);
+        let bit = Operand::Text(y.to_string());
+        Some(match x {
+            0 => (ROT[y as usize], vec![r]),
+            1 => ("bit", vec![bit, r]),
+            2 => ("res", vec![bit, r]),
+            _ => ("set", vec![bit, r])
+        })
+    }
+
+    // DDCB & FDCB, the undocumented forms copy the result into a register as well
+    fn index_cb(&mut self) -> Option<Decoded> {
+        let address = self.displacement()?;
+        let op = self.byte()?;
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let mut operands = match x {
+            0 => vec![address],
+            1 if z == 6 => vec![Operand::Text(y.to_string()), address],
+            1 => return None,
+            _ => vec![Operand::Text(y.to_string()), address]
+        };
+        if z != 6 {
+            operands.push(t(R[z as usize]));
+        }
+        Some(match x {
+            0 => (ROT[y as usize], operands),
+            1 => ("bit", operands),
+            2 => ("res", operands),
+            _ => ("set", operands)
+        })
+    }
+
+    fn ed(&mut self) -> Option<Decoded> {
+        let op = self.byte()?;
+        if self.z80n {
+            if let Some(decoded) = self.z80n(op) {
+                return Some(decoded);
+            }
+        }
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match (x, z) {
+            (1, 0) if y == 6 => Some(("in", vec![t("(c)")])),
+            (1, 0) => Some(("in", vec![t(R[y as usize]), t("(c)")])),
+            (1, 1) if y == 6 => Some(("out", vec![t("(c)"), t("0")])),
+            (1, 1) => Some(("out", vec![t("(c)"), t(R[y as usize])])),
+            (1, 2) => Some((if q == 0 { "sbc" } else { "adc" }, vec![t("hl"), t(RP[p as usize])])),
+            // ED 63 & ED 6B duplicate LD (nn),HL & LD HL,(nn)
+            (1, 3) if p == 2 => None,
+            (1, 3) if q == 0 => Some(("ld", vec![Operand::Memory(self.word()?), t(RP[p as usize])])),
+            (1, 3) => Some(("ld", vec![t(RP[p as usize]), Operand::Memory(self.word()?)])),
+            (1, 4) if y == 0 => Some(("neg", vec![])),
+            (1, 5) if y == 0 => Some(("retn", vec![])),
+            (1, 5) if y == 1 => Some(("reti", vec![])),
+            (1, 6) => match y {
+                0 => Some(("im", vec![t("0")])),
+                2 => Some(("im", vec![t("1")])),
+                3 => Some(("im", vec![t("2")])),
+                _ => None
+            },
+            (1, 7) if y < 6 => Some(match y {
+                0 => ("ld", vec![t("i"), t("a")]),
+                1 => ("ld", vec![t("r"), t("a")]),
+                2 => ("ld", vec![t("a"), t("i")]),
+                3 => ("ld", vec![t("a"), t("r")]),
+                4 => ("rrd", vec![]),
+                _ => ("rld", vec![])
+            }),
+            (2, _) if z <= 3 && y >= 4 => Some((BLOCK[y as usize - 4][z as usize], vec![])),
+            _ => None
+        }
+    }
+
+    fn z80n(&mut self, op: u8) -> Option<Decoded> {
+        Some(match op {
+            0x23 => ("swapnib", vec![]),
+            0x24 => ("mirror", vec![]),
+            0x27 => ("test", vec![self.n()?]),
+            0x28 => ("bsla", vec![]),
+            0x29 => ("bsra", vec![]),
+            0x2A => ("bsrl", vec![]),
+            0x2B => ("bsrf", vec![]),
+            0x2C => ("brlc", vec![]),
+            0x30 => ("mul", vec![t("d"), t("e")]),
+            0x31 => ("add", vec![t("hl"), t("a")]),
+            0x32 => ("add", vec![t("de"), t("a")]),
+            0x33 => ("add", vec![t("bc"), t("a")]),
+            0x34 => ("add", vec![t("hl"), self.nn()?]),
+            0x35 => ("add", vec![t("de"), self.nn()?]),
+            0x36 => ("add", vec![t("bc"), self.nn()?]),
+            // PUSH nn is the only big endian operand
+            0x8A => {
+                let hi = self.byte()? as u16;
+                ("push", vec![Operand::Word(hi << 8 | self.byte()? as u16)])
+            }
+            0x90 => ("outinb", vec![]),
+            0x91 => ("nextreg", vec![self.n()?, self.n()?]),
+            0x92 => ("nextreg", vec![self.n()?, t("a")]),
+            0x93 => ("pixeldn", vec![]),
+            0x94 => ("pixelad", vec![]),
+            0x95 => ("setae", vec![]),
+            0x98 => ("jp", vec![t("(c)")]),
+            0xA4 => ("ldix", vec![]),
+            0xA5 => ("ldws", vec![]),
+            0xAC => ("lddx", vec![]),
+            0xB4 => ("ldirx", vec![]),
+            0xB7 => ("ldpirx", vec![]),
+            0xBC => ("lddrx", vec![]),
+            _ => return None
+        })
+    }
+}
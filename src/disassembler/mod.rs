@@ -0,0 +1,211 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Write;
+
+use crate::disassembler::decoder::Decoder;
+
+mod decoder;
+mod symbol_file;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Text(String),
+    // A 16 bit immediate, named when a symbol has the same value
+    Word(u16),
+    // Jump & call targets, named by a symbol or a generated label
+    Address(u16),
+    // (nn)
+    Memory(u16),
+}
+
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub operands: Vec<Operand>,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Text(s) => write!(f, "{}", s),
+            Operand::Word(v) | Operand::Address(v) => write!(f, "0x{:04x}", v),
+            Operand::Memory(v) => write!(f, "(0x{:04x})", v)
+        }
+    }
+}
+
+impl Instruction {
+    fn text(&self, operands: &[String]) -> String {
+        if operands.is_empty() {
+            self.mnemonic.clone()
+        } else {
+            format!("{} {}", self.mnemonic, operands.join(","))
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operands: Vec<String> = self.operands.iter().map(|o| o.to_string()).collect();
+        write!(f, "{}", self.text(&operands))
+    }
+}
+
+// Turns a binary back into source that FantASM assembles to the same bytes, instructions it
+// cannot write (undocumented duplicates, ignored prefixes) are written as DB
+#[derive(Default)]
+pub struct Disassembler {
+    origin: u16,
+    z80n: bool,
+    cspect: bool,
+    symbols: Vec<(String, u16)>,
+}
+
+impl Disassembler {
+    pub fn new() -> Disassembler {
+        Default::default()
+    }
+
+    pub fn origin(&mut self, origin: u16) -> &mut Disassembler {
+        self.origin = origin;
+        self
+    }
+
+    pub fn enable_z80n(&mut self, enabled: bool) -> &mut Disassembler {
+        self.z80n = enabled;
+        self
+    }
+
+    pub fn enable_cspect(&mut self, enabled: bool) -> &mut Disassembler {
+        self.cspect = enabled;
+        self
+    }
+
+    // Names addresses using the labels from an exported symbol file
+    pub fn add_symbols(&mut self, text: &str) -> &mut Disassembler {
+        for (name, value) in symbol_file::parse(text) {
+            if !self.symbols.iter().any(|(n, _)| *n == name) {
+                self.symbols.push((name, value));
+            }
+        }
+        self
+    }
+
+    // Decodes the instruction at an offset into the code
+    pub fn decode(&self, code: &[u8], offset: usize) -> Instruction {
+        let address = self.origin.wrapping_add(offset as u16);
+        Decoder::new(code, offset, address, self.z80n, self.cspect).decode()
+    }
+
+    pub fn instructions(&self, code: &[u8]) -> Vec<Instruction> {
+        let mut instructions = vec![];
+        let mut offset = 0;
+        while offset < code.len() {
+            let instruction = self.decode(code, offset);
+            offset += instruction.bytes.len();
+            instructions.push(instruction);
+        }
+        instructions
+    }
+
+    pub fn disassemble(&self, code: &[u8]) -> String {
+        let instructions = self.instructions(code);
+        let boundaries: HashSet<u16> = instructions.iter().map(|i| i.address).collect();
+
+        let mut names = HashMap::new();
+        for (name, value) in &self.symbols {
+            names.entry(*value).or_insert_with(|| name.clone());
+        }
+        let mut labels = HashMap::new();
+        for target in instructions.iter().flat_map(|i| &i.operands) {
+            if let Operand::Address(a) = target {
+                if boundaries.contains(a) && !names.contains_key(a) {
+                    labels.insert(*a, format!("L{:04X}", a));
+                }
+            }
+        }
+
+        let mut source = String::new();
+        writeln!(source, "; Disassembled by FantASM {}", version!()).unwrap();
+        let mut equates: Vec<&(String, u16)> = self.symbols.iter().filter(|(_, v)| !boundaries.contains(v)).collect();
+        equates.sort_by_key(|(_, v)| *v);
+        if !equates.is_empty() {
+            writeln!(source).unwrap();
+        }
+        for (name, value) in equates {
+            writeln!(source, "{} = 0x{:04x}", name, value).unwrap();
+        }
+        writeln!(source, "\n\torg 0x{:04x}\n", self.origin).unwrap();
+
+        for instruction in &instructions {
+            for (name, _) in self.symbols.iter().filter(|(_, v)| *v == instruction.address) {
+                writeln!(source, "{}:", name).unwrap();
+            }
+            if let Some(label) = labels.get(&instruction.address) {
+                writeln!(source, "{}:", label).unwrap();
+            }
+            let operands: Vec<String> = instruction.operands.iter().map(|o| match o {
+                Operand::Text(s) => s.clone(),
+                Operand::Word(v) => names.get(v).cloned().unwrap_or_else(|| format!("0x{:04x}", v)),
+                Operand::Address(v) => names.get(v).or_else(|| labels.get(v)).cloned().unwrap_or_else(|| format!("0x{:04x}", v)),
+                Operand::Memory(v) => format!("({})", names.get(v).or_else(|| labels.get(v)).cloned().unwrap_or_else(|| format!("0x{:04x}", v)))
+            }).collect();
+            writeln!(source, "\t{}", instruction.text(&operands)).unwrap();
+        }
+        source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+    use crate::assembler::assembler_options::AssemblerOptions;
+    use crate::disassembler::Disassembler;
+
+    fn assemble(source: &str) -> Vec<u8> {
+        let mut assembler = Assembler::new();
+        assembler.enable_z80n(true).enable_cspect(true);
+        assembler.assemble_source("test.asm", source).ok().unwrap().bytes
+    }
+
+    fn disassembler() -> Disassembler {
+        let mut disassembler = Disassembler::new();
+        disassembler.origin(0x8000).enable_z80n(true).enable_cspect(true);
+        disassembler
+    }
+
+    #[test]
+    fn decode() {
+        let disassembler = disassembler();
+        let text = |code: &[u8]| {
+            let i = disassembler.decode(code, 0);
+            (i.mnemonic, i.bytes.len())
+        };
+        assert_eq!(text(&[0xDD, 0x36, 0x05, 0x12]), ("ld".to_string(), 4));
+        assert_eq!(text(&[0xED, 0x8A, 0x12, 0x34]), ("push".to_string(), 4));
+        assert_eq!(text(&[0xED, 0x98]), ("jp".to_string(), 2));
+        // ED 63 is a duplicate of LD (nn),HL so it can't be written as an instruction
+        assert_eq!(text(&[0xED, 0x63, 0x00, 0x80]), ("db".to_string(), 2));
+    }
+
+    #[test]
+    fn labels_and_symbols() {
+        let code = assemble(" org 0x8000\nstart\n ld hl,0x4000\n jr start\n");
+        let mut disassembler = disassembler();
+        disassembler.add_symbols("screen = 0x4000\n");
+        let source = disassembler.disassemble(&code);
+        let lines: Vec<&str> = source.lines().skip(2).collect();
+        assert_eq!(lines, ["screen = 0x4000", "", "\torg 0x8000", "", "L8000:", "\tld hl,screen", "\tjr L8000"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        for source in [include_str!("../../tests/test_all_opcodes.asm"), include_str!("../../tests/test_z80n.asm"),
+            include_str!("../../tests/test_cspect.asm")] {
+            let code = assemble(source);
+            assert_eq!(assemble(&disassembler().disassemble(&code)), code);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use regex::Regex;
+
+lazy_static! {
+    // One pattern per --sym-format, all of them are tried on every line
+    static ref FORMATS: Vec<Regex> = vec![
+        Regex::new(r"^\s*(?P<name>[\w.]+)\s*=\s*(?:0x|\$)(?P<hex>[0-9a-fA-F]+)\s*$").unwrap(),
+        Regex::new(r"^[0-9a-fA-F]{8} (?P<hex>[0-9a-fA-F]{4}) [0-9a-fA-F]{2} (?P<name>[\w.]+)").unwrap(),
+        Regex::new(r"(?i)^(?P<name>[\w.]+): EQU \$(?P<hex>[0-9a-f]+)").unwrap(),
+        Regex::new(r"(?i)^comadd (?P<hex>[0-9a-f]+),(?P<name>[\w.]+)").unwrap(),
+        Regex::new(r"^[0-9a-fA-F]{2}:(?P<hex>[0-9a-fA-F]{4}) (?P<name>[\w.]+)").unwrap(),
+        Regex::new(r#""name": "(?P<name>[\w.]+)", "value": (?P<dec>\d+), "type": "label""#).unwrap(),
+    ];
+}
+
+// Reads the labels from a file written by --export-labels in any of the symbol formats
+pub fn parse(text: &str) -> Vec<(String, u16)> {
+    let mut symbols = vec![];
+    for line in text.lines() {
+        for format in FORMATS.iter() {
+            if let Some(c) = format.captures(line.trim_end()) {
+                let value = match (c.name("hex"), c.name("dec")) {
+                    (Some(h), _) => u32::from_str_radix(h.as_str(), 16).ok(),
+                    (_, Some(d)) => d.as_str().parse::<u32>().ok(),
+                    _ => None
+                };
+                if let Some(v) = value {
+                    symbols.push((c["name"].to_string(), v as u16));
+                }
+                break;
+            }
+        }
+    }
+    symbols
+}
@@ -0,0 +1,933 @@
+use crate::emulator::Emulator;
+use crate::emulator::registers::{FLAG_C, FLAG_H, FLAG_N, FLAG_PV, FLAG_S, FLAG_X, FLAG_Y, FLAG_Z};
+
+// Which register pair stands in for HL, set by the DD & FD prefixes
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Index {
+    Hl,
+    Ix,
+    Iy,
+}
+
+const IM_MODES: [u8; 8] = [0, 0, 1, 2, 0, 0, 1, 2];
+
+fn szxy(v: u8) -> u8 {
+    (v & (FLAG_S | FLAG_Y | FLAG_X)) | if v == 0 { FLAG_Z } else { 0 }
+}
+
+fn parity(v: u8) -> u8 {
+    if v.count_ones().is_multiple_of(2) { FLAG_PV } else { 0 }
+}
+
+impl Emulator {
+    fn refresh(&mut self) {
+        self.regs.r = (self.regs.r & 0x80) | (self.regs.r.wrapping_add(1) & 0x7F);
+    }
+
+    pub(super) fn execute(&mut self) {
+        let mut index = Index::Hl;
+        let mut opcode = self.fetch();
+        self.refresh();
+        while opcode == 0xDD || opcode == 0xFD {
+            index = if opcode == 0xDD { Index::Ix } else { Index::Iy };
+            opcode = self.fetch();
+            self.refresh();
+            if self.cspect && index == Index::Ix && (opcode == 0x00 || opcode == 0x01) {
+                self.exit = opcode == 0x00;
+                return;
+            }
+        }
+        match opcode {
+            0xCB if index == Index::Hl => {
+                let op = self.fetch();
+                self.refresh();
+                self.cb(op);
+            }
+            0xCB => {
+                let address = self.index_address(index);
+                let op = self.fetch();
+                self.index_cb(op, address);
+            }
+            0xED => {
+                let op = self.fetch();
+                self.refresh();
+                self.ed(op);
+            }
+            _ => self.base(opcode, index)
+        }
+    }
+
+    fn hl(&self, index: Index) -> u16 {
+        match index {
+            Index::Hl => self.regs.hl(),
+            Index::Ix => self.regs.ix,
+            Index::Iy => self.regs.iy,
+        }
+    }
+
+    fn set_hl(&mut self, index: Index, v: u16) {
+        match index {
+            Index::Hl => self.regs.set_hl(v),
+            Index::Ix => self.regs.ix = v,
+            Index::Iy => self.regs.iy = v,
+        }
+    }
+
+    // (HL) or (IX+d) fetching the displacement
+    fn index_address(&mut self, index: Index) -> u16 {
+        match index {
+            Index::Hl => self.regs.hl(),
+            _ => {
+                let d = self.fetch() as i8;
+                self.hl(index).wrapping_add(d as u16)
+            }
+        }
+    }
+
+    fn reg(&self, r: u8, index: Index) -> u8 {
+        match (r, index) {
+            (0, _) => self.regs.b,
+            (1, _) => self.regs.c,
+            (2, _) => self.regs.d,
+            (3, _) => self.regs.e,
+            (4, Index::Hl) => self.regs.h,
+            (5, Index::Hl) => self.regs.l,
+            (4, _) => (self.hl(index) >> 8) as u8,
+            (5, _) => self.hl(index) as u8,
+            _ => self.regs.a
+        }
+    }
+
+    fn set_reg(&mut self, r: u8, index: Index, v: u8) {
+        match (r, index) {
+            (0, _) => self.regs.b = v,
+            (1, _) => self.regs.c = v,
+            (2, _) => self.regs.d = v,
+            (3, _) => self.regs.e = v,
+            (4, Index::Hl) => self.regs.h = v,
+            (5, Index::Hl) => self.regs.l = v,
+            (4, _) => self.set_hl(index, (self.hl(index) & 0x00FF) | (v as u16) << 8),
+            (5, _) => self.set_hl(index, (self.hl(index) & 0xFF00) | v as u16),
+            _ => self.regs.a = v
+        }
+    }
+
+    fn rp(&self, p: u8, index: Index) -> u16 {
+        match p {
+            0 => self.regs.bc(),
+            1 => self.regs.de(),
+            2 => self.hl(index),
+            _ => self.regs.sp
+        }
+    }
+
+    fn set_rp(&mut self, p: u8, index: Index, v: u16) {
+        match p {
+            0 => self.regs.set_bc(v),
+            1 => self.regs.set_de(v),
+            2 => self.set_hl(index, v),
+            _ => self.regs.sp = v
+        }
+    }
+
+    fn condition(&self, cc: u8) -> bool {
+        let flag = [FLAG_Z, FLAG_C, FLAG_PV, FLAG_S][(cc >> 1) as usize];
+        self.regs.flag(flag) == (cc & 1 == 1)
+    }
+
+    fn ret(&mut self) {
+        self.regs.pc = self.pop();
+        self.returned = true;
+    }
+
+    fn jr(&mut self, taken: bool) {
+        let d = self.fetch() as i8;
+        if taken {
+            self.regs.pc = self.regs.pc.wrapping_add(d as u16);
+        }
+    }
+
+    fn add(&mut self, v: u8, carry: bool) {
+        let a = self.regs.a;
+        let c = if carry && self.regs.flag(FLAG_C) { 1 } else { 0 };
+        let r = a as u16 + v as u16 + c;
+        let res = r as u8;
+        let overflow = if (a ^ res) & (v ^ res) & 0x80 != 0 { FLAG_PV } else { 0 };
+        self.regs.f = szxy(res) | ((a ^ v ^ res) & FLAG_H) | overflow | if r > 0xFF { FLAG_C } else { 0 };
+        self.regs.a = res;
+    }
+
+    fn sub(&mut self, v: u8, carry: bool) -> u8 {
+        let a = self.regs.a;
+        let c = if carry && self.regs.flag(FLAG_C) { 1 } else { 0 };
+        let res = a.wrapping_sub(v).wrapping_sub(c);
+        let overflow = if (a ^ v) & (a ^ res) & 0x80 != 0 { FLAG_PV } else { 0 };
+        let borrow = if (a as u16) < v as u16 + c as u16 { FLAG_C } else { 0 };
+        self.regs.f = szxy(res) | ((a ^ v ^ res) & FLAG_H) | overflow | FLAG_N | borrow;
+        res
+    }
+
+    fn alu(&mut self, op: u8, v: u8) {
+        match op {
+            0 => self.add(v, false),
+            1 => self.add(v, true),
+            2 => self.regs.a = self.sub(v, false),
+            3 => self.regs.a = self.sub(v, true),
+            4 => {
+                self.regs.a &= v;
+                self.regs.f = szxy(self.regs.a) | parity(self.regs.a) | FLAG_H;
+            }
+            5 => {
+                self.regs.a ^= v;
+                self.regs.f = szxy(self.regs.a) | parity(self.regs.a);
+            }
+            6 => {
+                self.regs.a |= v;
+                self.regs.f = szxy(self.regs.a) | parity(self.regs.a);
+            }
+            _ => {
+                self.sub(v, false);
+                self.regs.f = (self.regs.f & !(FLAG_Y | FLAG_X)) | (v & (FLAG_Y | FLAG_X));
+            }
+        }
+    }
+
+    fn inc(&mut self, v: u8) -> u8 {
+        let res = v.wrapping_add(1);
+        let half = if v & 0x0F == 0x0F { FLAG_H } else { 0 };
+        let overflow = if v == 0x7F { FLAG_PV } else { 0 };
+        self.regs.f = (self.regs.f & FLAG_C) | szxy(res) | half | overflow;
+        res
+    }
+
+    fn dec(&mut self, v: u8) -> u8 {
+        let res = v.wrapping_sub(1);
+        let half = if v & 0x0F == 0 { FLAG_H } else { 0 };
+        let overflow = if v == 0x80 { FLAG_PV } else { 0 };
+        self.regs.f = (self.regs.f & FLAG_C) | szxy(res) | half | overflow | FLAG_N;
+        res
+    }
+
+    fn add16(&mut self, a: u16, v: u16) -> u16 {
+        let r = a as u32 + v as u32;
+        let res = r as u16;
+        let half = (((a ^ v ^ res) >> 8) as u8) & FLAG_H;
+        self.regs.f = (self.regs.f & (FLAG_S | FLAG_Z | FLAG_PV)) | ((res >> 8) as u8 & (FLAG_Y | FLAG_X))
+            | half | if r > 0xFFFF { FLAG_C } else { 0 };
+        res
+    }
+
+    fn adc16(&mut self, v: u16) {
+        let a = self.regs.hl();
+        let c = if self.regs.flag(FLAG_C) { 1 } else { 0 };
+        let r = a as u32 + v as u32 + c;
+        let res = r as u16;
+        let overflow = if (a ^ res) & (v ^ res) & 0x8000 != 0 { FLAG_PV } else { 0 };
+        self.regs.f = szxy((res >> 8) as u8) & !FLAG_Z | if res == 0 { FLAG_Z } else { 0 }
+            | (((a ^ v ^ res) >> 8) as u8 & FLAG_H) | overflow | if r > 0xFFFF { FLAG_C } else { 0 };
+        self.regs.set_hl(res);
+    }
+
+    fn sbc16(&mut self, v: u16) {
+        let a = self.regs.hl();
+        let c = if self.regs.flag(FLAG_C) { 1 } else { 0 };
+        let res = a.wrapping_sub(v).wrapping_sub(c);
+        let overflow = if (a ^ v) & (a ^ res) & 0x8000 != 0 { FLAG_PV } else { 0 };
+        let borrow = if (a as u32) < v as u32 + c as u32 { FLAG_C } else { 0 };
+        self.regs.f = szxy((res >> 8) as u8) & !FLAG_Z | if res == 0 { FLAG_Z } else { 0 }
+            | (((a ^ v ^ res) >> 8) as u8 & FLAG_H) | overflow | FLAG_N | borrow;
+        self.regs.set_hl(res);
+    }
+
+    fn daa(&mut self) {
+        let a = self.regs.a;
+        let low = a & 0x0F;
+        let mut diff = 0;
+        let mut carry = self.regs.flag(FLAG_C);
+        if self.regs.flag(FLAG_H) || low > 9 {
+            diff |= 0x06;
+        }
+        if carry || a > 0x99 {
+            diff |= 0x60;
+            carry = true;
+        }
+        let (res, half) = if self.regs.flag(FLAG_N) {
+            (a.wrapping_sub(diff), self.regs.flag(FLAG_H) && low < 6)
+        } else {
+            (a.wrapping_add(diff), low > 9)
+        };
+        self.regs.f = szxy(res) | parity(res) | (self.regs.f & FLAG_N)
+            | if half { FLAG_H } else { 0 } | if carry { FLAG_C } else { 0 };
+        self.regs.a = res;
+    }
+
+    fn accumulator_rotate(&mut self, y: u8) {
+        let a = self.regs.a;
+        let carry = self.regs.flag(FLAG_C) as u8;
+        let (res, c) = match y {
+            0 => (a.rotate_left(1), a >> 7),
+            1 => (a.rotate_right(1), a & 1),
+            2 => (a << 1 | carry, a >> 7),
+            _ => (a >> 1 | carry << 7, a & 1)
+        };
+        self.regs.a = res;
+        self.regs.f = (self.regs.f & (FLAG_S | FLAG_Z | FLAG_PV)) | (res & (FLAG_Y | FLAG_X)) | c;
+    }
+
+    fn rotate(&mut self, y: u8, v: u8) -> u8 {
+        let carry = self.regs.flag(FLAG_C) as u8;
+        let (res, c) = match y {
+            0 => (v.rotate_left(1), v >> 7),
+            1 => (v.rotate_right(1), v & 1),
+            2 => (v << 1 | carry, v >> 7),
+            3 => (v >> 1 | carry << 7, v & 1),
+            4 => (v << 1, v >> 7),
+            5 => (v >> 1 | (v & 0x80), v & 1),
+            6 => (v << 1 | 1, v >> 7),
+            _ => (v >> 1, v & 1)
+        };
+        self.regs.f = szxy(res) | parity(res) | c;
+        res
+    }
+
+    fn bit(&mut self, y: u8, v: u8, xy: u8) {
+        let set = v & (1 << y) != 0;
+        let zero = if set { 0 } else { FLAG_Z | FLAG_PV };
+        let sign = if y == 7 && set { FLAG_S } else { 0 };
+        self.regs.f = (self.regs.f & FLAG_C) | FLAG_H | zero | sign | (xy & (FLAG_Y | FLAG_X));
+    }
+
+    fn base(&mut self, opcode: u8, index: Index) {
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 7;
+        let z = opcode & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        match (x, z) {
+            (0, 0) => match y {
+                0 => {}
+                1 => {
+                    let af = self.regs.af();
+                    self.regs.set_af(self.regs.alt_af);
+                    self.regs.alt_af = af;
+                }
+                2 => {
+                    self.regs.b = self.regs.b.wrapping_sub(1);
+                    let taken = self.regs.b != 0;
+                    self.jr(taken);
+                }
+                3 => self.jr(true),
+                _ => {
+                    let taken = self.condition(y - 4);
+                    self.jr(taken);
+                }
+            }
+            (0, 1) => if q == 0 {
+                let nn = self.fetch_word();
+                self.set_rp(p, index, nn);
+            } else {
+                let v = self.add16(self.hl(index), self.rp(p, index));
+                self.set_hl(index, v);
+            }
+            (0, 2) => match (q, p) {
+                (0, 0) => self.write(self.regs.bc(), self.regs.a),
+                (0, 1) => self.write(self.regs.de(), self.regs.a),
+                (0, 2) => {
+                    let nn = self.fetch_word();
+                    self.write_word(nn, self.hl(index));
+                }
+                (0, _) => {
+                    let nn = self.fetch_word();
+                    self.write(nn, self.regs.a);
+                }
+                (_, 0) => self.regs.a = self.read(self.regs.bc()),
+                (_, 1) => self.regs.a = self.read(self.regs.de()),
+                (_, 2) => {
+                    let nn = self.fetch_word();
+                    let v = self.read_word(nn);
+                    self.set_hl(index, v);
+                }
+                _ => {
+                    let nn = self.fetch_word();
+                    self.regs.a = self.read(nn);
+                }
+            }
+            (0, 3) => {
+                let v = self.rp(p, index);
+                let v = if q == 0 { v.wrapping_add(1) } else { v.wrapping_sub(1) };
+                self.set_rp(p, index, v);
+            }
+            (0, 4) | (0, 5) => if y == 6 {
+                let address = self.index_address(index);
+                let v = self.read(address);
+                let v = if z == 4 { self.inc(v) } else { self.dec(v) };
+                self.write(address, v);
+            } else {
+                let v = self.reg(y, index);
+                let v = if z == 4 { self.inc(v) } else { self.dec(v) };
+                self.set_reg(y, index, v);
+            }
+            (0, 6) => if y == 6 {
+                let address = self.index_address(index);
+                let n = self.fetch();
+                self.write(address, n);
+            } else {
+                let n = self.fetch();
+                self.set_reg(y, index, n);
+            }
+            (0, _) => match y {
+                0..=3 => self.accumulator_rotate(y),
+                4 => self.daa(),
+                5 => {
+                    self.regs.a = !self.regs.a;
+                    self.regs.f = (self.regs.f & (FLAG_S | FLAG_Z | FLAG_PV | FLAG_C)) | FLAG_H | FLAG_N
+                        | (self.regs.a & (FLAG_Y | FLAG_X));
+                }
+                6 => self.regs.f = (self.regs.f & (FLAG_S | FLAG_Z | FLAG_PV)) | FLAG_C | (self.regs.a & (FLAG_Y | FLAG_X)),
+                _ => {
+                    let carry = self.regs.flag(FLAG_C);
+                    self.regs.f = (self.regs.f & (FLAG_S | FLAG_Z | FLAG_PV)) | (self.regs.a & (FLAG_Y | FLAG_X))
+                        | if carry { FLAG_H } else { FLAG_C };
+                }
+            }
+            (1, _) => if y == 6 && z == 6 {
+                self.halted = true;
+            } else if y == 6 {
+                let address = self.index_address(index);
+                self.write(address, self.reg(z, Index::Hl));
+            } else if z == 6 {
+                let address = self.index_address(index);
+                let v = self.read(address);
+                self.set_reg(y, Index::Hl, v);
+            } else {
+                let v = self.reg(z, index);
+                self.set_reg(y, index, v);
+            }
+            (2, _) => {
+                let v = if z == 6 {
+                    let address = self.index_address(index);
+                    self.read(address)
+                } else {
+                    self.reg(z, index)
+                };
+                self.alu(y, v);
+            }
+            (_, 0) => if self.condition(y) {
+                self.ret();
+            }
+            (_, 1) => match (q, p) {
+                (0, 3) => {
+                    let v = self.pop();
+                    self.regs.set_af(v);
+                }
+                (0, _) => {
+                    let v = self.pop();
+                    self.set_rp(p, index, v);
+                }
+                (_, 0) => self.ret(),
+                (_, 1) => {
+                    let (bc, de, hl) = (self.regs.bc(), self.regs.de(), self.regs.hl());
+                    self.regs.set_bc(self.regs.alt_bc);
+                    self.regs.set_de(self.regs.alt_de);
+                    self.regs.set_hl(self.regs.alt_hl);
+                    self.regs.alt_bc = bc;
+                    self.regs.alt_de = de;
+                    self.regs.alt_hl = hl;
+                }
+                (_, 2) => self.regs.pc = self.hl(index),
+                _ => self.regs.sp = self.hl(index)
+            }
+            (_, 2) => {
+                let nn = self.fetch_word();
+                if self.condition(y) {
+                    self.regs.pc = nn;
+                }
+            }
+            (_, 3) => match y {
+                0 => self.regs.pc = self.fetch_word(),
+                2 => {
+                    let n = self.fetch();
+                    self.port_out((self.regs.a as u16) << 8 | n as u16, self.regs.a);
+                }
+                3 => {
+                    let n = self.fetch();
+                    self.regs.a = self.port_in((self.regs.a as u16) << 8 | n as u16);
+                }
+                4 => {
+                    let v = self.read_word(self.regs.sp);
+                    self.write_word(self.regs.sp, self.hl(index));
+                    self.set_hl(index, v);
+                }
+                5 => {
+                    let de = self.regs.de();
+                    self.regs.set_de(self.regs.hl());
+                    self.regs.set_hl(de);
+                }
+                6 => {
+                    self.regs.iff1 = false;
+                    self.regs.iff2 = false;
+                }
+                _ => {
+                    self.regs.iff1 = true;
+                    self.regs.iff2 = true;
+                }
+            }
+            (_, 4) => {
+                let nn = self.fetch_word();
+                if self.condition(y) {
+                    self.push(self.regs.pc);
+                    self.regs.pc = nn;
+                }
+            }
+            (_, 5) => if q == 0 {
+                let v = if p == 3 { self.regs.af() } else { self.rp(p, index) };
+                self.push(v);
+            } else {
+                let nn = self.fetch_word();
+                self.push(self.regs.pc);
+                self.regs.pc = nn;
+            }
+            (_, 6) => {
+                let n = self.fetch();
+                self.alu(y, n);
+            }
+            _ => {
+                self.push(self.regs.pc);
+                self.regs.pc = y as u16 * 8;
+            }
+        }
+    }
+
+    fn cb(&mut self, op: u8) {
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let address = self.regs.hl();
+        let v = if z == 6 { self.read(address) } else { self.reg(z, Index::Hl) };
+        let res = match x {
+            0 => self.rotate(y, v),
+            1 => {
+                self.bit(y, v, v);
+                return;
+            }
+            2 => v & !(1 << y),
+            _ => v | (1 << y)
+        };
+        if z == 6 {
+            self.write(address, res);
+        } else {
+            self.set_reg(z, Index::Hl, res);
+        }
+    }
+
+    // DDCB & FDCB, results are also copied into a register when one is given
+    fn index_cb(&mut self, op: u8, address: u16) {
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let v = self.read(address);
+        let res = match x {
+            0 => self.rotate(y, v),
+            1 => {
+                self.bit(y, v, (address >> 8) as u8);
+                return;
+            }
+            2 => v & !(1 << y),
+            _ => v | (1 << y)
+        };
+        self.write(address, res);
+        if z != 6 {
+            self.set_reg(z, Index::Hl, res);
+        }
+    }
+
+    fn ed(&mut self, op: u8) {
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        match (x, z) {
+            (1, 0) => {
+                let v = self.port_in(self.regs.bc());
+                self.regs.f = (self.regs.f & FLAG_C) | szxy(v) | parity(v);
+                if y != 6 {
+                    self.set_reg(y, Index::Hl, v);
+                }
+            }
+            (1, 1) => {
+                let v = if y == 6 { 0 } else { self.reg(y, Index::Hl) };
+                self.port_out(self.regs.bc(), v);
+            }
+            (1, 2) => if q == 0 {
+                self.sbc16(self.rp(p, Index::Hl));
+            } else {
+                self.adc16(self.rp(p, Index::Hl));
+            }
+            (1, 3) => {
+                let nn = self.fetch_word();
+                if q == 0 {
+                    self.write_word(nn, self.rp(p, Index::Hl));
+                } else {
+                    let v = self.read_word(nn);
+                    self.set_rp(p, Index::Hl, v);
+                }
+            }
+            (1, 4) => {
+                let a = self.regs.a;
+                self.regs.a = 0;
+                self.regs.a = self.sub(a, false);
+            }
+            (1, 5) => {
+                self.regs.iff1 = self.regs.iff2;
+                self.ret();
+            }
+            (1, 6) => self.regs.im = IM_MODES[y as usize],
+            (1, 7) => match y {
+                0 => self.regs.i = self.regs.a,
+                1 => self.regs.r = self.regs.a,
+                2 | 3 => {
+                    self.regs.a = if y == 2 { self.regs.i } else { self.regs.r };
+                    self.regs.f = (self.regs.f & FLAG_C) | szxy(self.regs.a) | if self.regs.iff2 { FLAG_PV } else { 0 };
+                }
+                4 | 5 => {
+                    let address = self.regs.hl();
+                    let m = self.read(address);
+                    let a = self.regs.a;
+                    let (m, a) = if y == 4 {
+                        (a << 4 | m >> 4, (a & 0xF0) | (m & 0x0F))
+                    } else {
+                        (m << 4 | (a & 0x0F), (a & 0xF0) | m >> 4)
+                    };
+                    self.write(address, m);
+                    self.regs.a = a;
+                    self.regs.f = (self.regs.f & FLAG_C) | szxy(a) | parity(a);
+                }
+                _ => self.invalid = true
+            }
+            (2, 0..=3) if y >= 4 => self.block(y, z),
+            _ => self.z80n(op)
+        }
+    }
+
+    // LDI, CPI, INI, OUTI and the decrementing and repeating versions
+    fn block(&mut self, y: u8, z: u8) {
+        let step: u16 = if y & 1 == 0 { 1 } else { 0xFFFF };
+        let repeat = y >= 6;
+        let hl = self.regs.hl();
+        match z {
+            0 => {
+                let v = self.read(hl);
+                self.write(self.regs.de(), v);
+                self.regs.set_hl(hl.wrapping_add(step));
+                self.regs.set_de(self.regs.de().wrapping_add(step));
+                self.regs.set_bc(self.regs.bc().wrapping_sub(1));
+                let n = v.wrapping_add(self.regs.a);
+                let more = self.regs.bc() != 0;
+                self.regs.f = (self.regs.f & (FLAG_S | FLAG_Z | FLAG_C)) | (n & FLAG_X) | ((n << 4) & FLAG_Y)
+                    | if more { FLAG_PV } else { 0 };
+                if repeat && more {
+                    self.regs.pc = self.regs.pc.wrapping_sub(2);
+                }
+            }
+            1 => {
+                let v = self.read(hl);
+                let res = self.regs.a.wrapping_sub(v);
+                let half = (self.regs.a ^ v ^ res) & FLAG_H;
+                self.regs.set_hl(hl.wrapping_add(step));
+                self.regs.set_bc(self.regs.bc().wrapping_sub(1));
+                let n = res.wrapping_sub(if half != 0 { 1 } else { 0 });
+                let more = self.regs.bc() != 0;
+                self.regs.f = (self.regs.f & FLAG_C) | FLAG_N | half | (res & FLAG_S) | if res == 0 { FLAG_Z } else { 0 }
+                    | (n & FLAG_X) | ((n << 4) & FLAG_Y) | if more { FLAG_PV } else { 0 };
+                if repeat && more && res != 0 {
+                    self.regs.pc = self.regs.pc.wrapping_sub(2);
+                }
+            }
+            _ => {
+                if z == 2 {
+                    let v = self.port_in(self.regs.bc());
+                    self.write(hl, v);
+                    self.regs.b = self.regs.b.wrapping_sub(1);
+                } else {
+                    self.regs.b = self.regs.b.wrapping_sub(1);
+                    let v = self.read(hl);
+                    self.port_out(self.regs.bc(), v);
+                }
+                self.regs.set_hl(hl.wrapping_add(step));
+                self.regs.f = (self.regs.f & FLAG_C) | szxy(self.regs.b) | FLAG_N;
+                if repeat && self.regs.b != 0 {
+                    self.regs.pc = self.regs.pc.wrapping_sub(2);
+                }
+            }
+        }
+    }
+
+    // Z80n extended instructions, anything else is an ED NOP flagged as invalid
+    fn z80n(&mut self, op: u8) {
+        if !self.z80n {
+            self.invalid = true;
+            return;
+        }
+        let de = self.regs.de();
+        let shift = (self.regs.b & 0x1F) as u32;
+        match op {
+            0x23 => self.regs.a = self.regs.a.rotate_left(4),
+            0x24 => self.regs.a = self.regs.a.reverse_bits(),
+            0x27 => {
+                let n = self.fetch();
+                let v = self.regs.a & n;
+                self.regs.f = szxy(v) | parity(v) | FLAG_H;
+            }
+            0x28 => self.regs.set_de(de.checked_shl(shift).unwrap_or(0)),
+            0x29 => self.regs.set_de(((de as i16) >> shift.min(15)) as u16),
+            0x2A => self.regs.set_de(de.checked_shr(shift).unwrap_or(0)),
+            0x2B => self.regs.set_de(!((!de).checked_shr(shift).unwrap_or(0))),
+            0x2C => self.regs.set_de(de.rotate_left(shift & 0x0F)),
+            0x30 => self.regs.set_de(self.regs.d as u16 * self.regs.e as u16),
+            0x31 => self.regs.set_hl(self.regs.hl().wrapping_add(self.regs.a as u16)),
+            0x32 => self.regs.set_de(de.wrapping_add(self.regs.a as u16)),
+            0x33 => self.regs.set_bc(self.regs.bc().wrapping_add(self.regs.a as u16)),
+            0x34..=0x36 => {
+                let nn = self.fetch_word();
+                match op {
+                    0x34 => self.regs.set_hl(self.regs.hl().wrapping_add(nn)),
+                    0x35 => self.regs.set_de(de.wrapping_add(nn)),
+                    _ => self.regs.set_bc(self.regs.bc().wrapping_add(nn))
+                }
+            }
+            0x8A => {
+                let hi = self.fetch() as u16;
+                let lo = self.fetch() as u16;
+                self.push(hi << 8 | lo);
+            }
+            0x90 => {
+                let hl = self.regs.hl();
+                let v = self.read(hl);
+                self.port_out(self.regs.bc(), v);
+                self.regs.set_hl(hl.wrapping_add(1));
+            }
+            0x91 => {
+                let register = self.fetch();
+                let value = self.fetch();
+                self.next_regs[register as usize] = value;
+            }
+            0x92 => {
+                let register = self.fetch();
+                self.next_regs[register as usize] = self.regs.a;
+            }
+            0x93 => {
+                let hl = self.regs.hl();
+                let hl = if hl & 0x0700 != 0x0700 {
+                    hl.wrapping_add(0x100)
+                } else if hl & 0xE0 != 0xE0 {
+                    (hl & 0xF8FF).wrapping_add(0x20)
+                } else {
+                    (hl & 0xF81F).wrapping_add(0x800)
+                };
+                self.regs.set_hl(hl);
+            }
+            0x94 => {
+                let (d, e) = (self.regs.d as u16, self.regs.e as u16);
+                self.regs.set_hl(0x4000 + ((d & 0xC0) << 5) + ((d & 0x07) << 8) + ((d & 0x38) << 2) + (e >> 3));
+            }
+            0x95 => self.regs.a = 0x80 >> (self.regs.e & 7),
+            0x98 => {
+                let v = self.port_in(self.regs.bc()) as u16;
+                self.regs.pc = (self.regs.pc & 0xC000) | v << 6;
+            }
+            0xA4 | 0xAC | 0xB4 | 0xBC => {
+                let hl = self.regs.hl();
+                let v = self.read(hl);
+                if v != self.regs.a {
+                    self.write(de, v);
+                }
+                let step: u16 = if op & 0x08 == 0 { 1 } else { 0xFFFF };
+                self.regs.set_hl(hl.wrapping_add(step));
+                self.regs.set_de(de.wrapping_add(1));
+                self.regs.set_bc(self.regs.bc().wrapping_sub(1));
+                if op >= 0xB4 && self.regs.bc() != 0 {
+                    self.regs.pc = self.regs.pc.wrapping_sub(2);
+                }
+            }
+            0xA5 => {
+                let hl = self.regs.hl();
+                let v = self.read(hl);
+                self.write(de, v);
+                self.regs.l = self.regs.l.wrapping_add(1);
+                self.regs.d = self.inc(self.regs.d);
+            }
+            0xB7 => {
+                let v = self.read((self.regs.hl() & 0xFFF8) | (self.regs.e & 7) as u16);
+                if v != self.regs.a {
+                    self.write(de, v);
+                }
+                self.regs.set_de(de.wrapping_add(1));
+                self.regs.set_bc(self.regs.bc().wrapping_sub(1));
+                if self.regs.bc() != 0 {
+                    self.regs.pc = self.regs.pc.wrapping_sub(2);
+                }
+            }
+            _ => self.invalid = true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::{Emulator, Stop};
+
+    // Runs code loaded at 0x0000 until the PC moves past it
+    fn run(code: &[u8]) -> Emulator {
+        let mut emulator = Emulator::new(code);
+        emulator.regs.sp = 0xFF00;
+        while (emulator.regs.pc as usize) < code.len() {
+            emulator.step();
+        }
+        emulator
+    }
+
+    #[test]
+    fn add_and_sub_flags() {
+        let e = run(&[0x3E, 0x7F, 0xC6, 0x01]);
+        assert_eq!((e.regs.a, e.regs.f), (0x80, 0x94));
+        let e = run(&[0x3E, 0xFF, 0xC6, 0x01]);
+        assert_eq!((e.regs.a, e.regs.f), (0x00, 0x51));
+        let e = run(&[0x3E, 0x80, 0xD6, 0x01]);
+        assert_eq!((e.regs.a, e.regs.f), (0x7F, 0x3E));
+        let e = run(&[0x37, 0x3E, 0x10, 0xCE, 0x0F]);
+        assert_eq!((e.regs.a, e.regs.f), (0x20, 0x30));
+        let e = run(&[0x37, 0x3E, 0x00, 0xDE, 0x00]);
+        assert_eq!((e.regs.a, e.regs.f), (0xFF, 0xBB));
+    }
+
+    #[test]
+    fn compare_takes_xy_from_the_operand() {
+        let e = run(&[0x3E, 0x10, 0xFE, 0x20]);
+        assert_eq!((e.regs.a, e.regs.f), (0x10, 0xA3));
+        let e = run(&[0x3E, 0x42, 0xFE, 0x42]);
+        assert_eq!(e.regs.f, 0x42);
+    }
+
+    #[test]
+    fn logical_flags() {
+        let e = run(&[0xAF]);
+        assert_eq!((e.regs.a, e.regs.f), (0x00, 0x44));
+        let e = run(&[0x3E, 0x0F, 0xE6, 0xF3]);
+        assert_eq!((e.regs.a, e.regs.f), (0x03, 0x14));
+        let e = run(&[0x3E, 0x03, 0xF6, 0x80]);
+        assert_eq!((e.regs.a, e.regs.f), (0x83, 0x80));
+    }
+
+    #[test]
+    fn inc_dec_keep_carry() {
+        let e = run(&[0x37, 0x3E, 0x7F, 0x3C]);
+        assert_eq!((e.regs.a, e.regs.f), (0x80, 0x95));
+        let e = run(&[0x06, 0x01, 0x05]);
+        assert_eq!((e.regs.b, e.regs.f), (0x00, 0x42));
+        let e = run(&[0x06, 0x80, 0x05]);
+        assert_eq!((e.regs.b, e.regs.f), (0x7F, 0x3E));
+    }
+
+    #[test]
+    fn sixteen_bit_arithmetic() {
+        let e = run(&[0x21, 0xFF, 0x7F, 0x11, 0x01, 0x00, 0xED, 0x5A]);
+        assert_eq!((e.regs.hl(), e.regs.f), (0x8000, 0x94));
+        let e = run(&[0x21, 0x00, 0x00, 0x11, 0x01, 0x00, 0xED, 0x52]);
+        assert_eq!((e.regs.hl(), e.regs.f), (0xFFFF, 0xBB));
+        let e = run(&[0x21, 0x00, 0xF0, 0x01, 0x00, 0x10, 0x09]);
+        assert_eq!((e.regs.hl(), e.regs.f & 0x11), (0x0000, 0x01));
+    }
+
+    #[test]
+    fn daa_neg_and_rotates() {
+        let e = run(&[0x3E, 0x15, 0xC6, 0x27, 0x27]);
+        assert_eq!((e.regs.a, e.regs.f), (0x42, 0x14));
+        let e = run(&[0x3E, 0x01, 0xED, 0x44]);
+        assert_eq!((e.regs.a, e.regs.f), (0xFF, 0xBB));
+        let e = run(&[0x3E, 0x81, 0x07]);
+        assert_eq!((e.regs.a, e.regs.f), (0x03, 0x01));
+        let e = run(&[0x06, 0x80, 0xCB, 0x38]);
+        assert_eq!((e.regs.b, e.regs.f), (0x40, 0x00));
+        let e = run(&[0x3E, 0x80, 0xCB, 0x7F]);
+        assert_eq!(e.regs.f, 0x90);
+    }
+
+    #[test]
+    fn z80n_extensions() {
+        let e = run(&[0x16, 0x12, 0x1E, 0x34, 0xED, 0x30]);
+        assert_eq!(e.regs.de(), 0x03A8);
+        let e = run(&[0x3E, 0x12, 0xED, 0x23]);
+        assert_eq!(e.regs.a, 0x21);
+        let e = run(&[0x3E, 0x01, 0xED, 0x24]);
+        assert_eq!(e.regs.a, 0x80);
+        let e = run(&[0x3E, 0x0F, 0xED, 0x27, 0xF0]);
+        assert_eq!((e.regs.a, e.regs.f), (0x0F, 0x54));
+        let e = run(&[0x21, 0x00, 0x40, 0x3E, 0x10, 0xED, 0x31, 0x11, 0x00, 0x01, 0xED, 0x35, 0x34, 0x12]);
+        assert_eq!((e.regs.hl(), e.regs.de()), (0x4010, 0x1334));
+        let e = run(&[0xED, 0x8A, 0x12, 0x34]);
+        assert_eq!((e.regs.sp, e.read_word(e.regs.sp)), (0xFEFE, 0x1234));
+        let e = run(&[0x3E, 0x05, 0xED, 0x91, 0x07, 0x03, 0xED, 0x92, 0x08]);
+        assert_eq!(e.next_regs[7..9], [0x03, 0x05]);
+        let e = run(&[0x1E, 0x07, 0xED, 0x95]);
+        assert_eq!(e.regs.a, 0x01);
+    }
+
+    #[test]
+    fn z80n_barrel_shifts() {
+        let shift = |op: u8, de: u16, b: u8| run(&[0x11, de as u8, (de >> 8) as u8, 0x06, b, 0xED, op]).regs.de();
+        assert_eq!(shift(0x28, 0x0001, 3), 0x0008);
+        assert_eq!(shift(0x29, 0x8000, 1), 0xC000);
+        assert_eq!(shift(0x2A, 0x8000, 1), 0x4000);
+        assert_eq!(shift(0x2B, 0x0001, 4), 0xF000);
+        assert_eq!(shift(0x2C, 0x8001, 1), 0x0003);
+    }
+
+    #[test]
+    fn z80n_screen_addresses() {
+        let pixel_down = |hl: u16| run(&[0x21, hl as u8, (hl >> 8) as u8, 0xED, 0x93]).regs.hl();
+        assert_eq!(pixel_down(0x4000), 0x4100);
+        assert_eq!(pixel_down(0x4700), 0x4020);
+        assert_eq!(pixel_down(0x47E0), 0x4800);
+        let e = run(&[0x16, 191, 0x1E, 255, 0xED, 0x94]);
+        assert_eq!(e.regs.hl(), 0x57FF);
+    }
+
+    #[test]
+    fn z80n_copies() {
+        let mut code = vec![0x21, 0x10, 0x00, 0x11, 0x20, 0x00, 0x01, 0x03, 0x00, 0x3E, 0xAA, 0xED, 0xB4];
+        code.resize(0x10, 0);
+        code.extend_from_slice(&[1, 0xAA, 3]);
+        let mut e = Emulator::new(&code);
+        while e.regs.pc < 0x0D {
+            e.step();
+        }
+        assert_eq!(e.memory[0x20..0x23], [1, 0, 3]);
+        assert_eq!(e.regs.bc(), 0);
+    }
+
+    #[test]
+    fn cspect_exit() {
+        let mut e = Emulator::new(&[0xDD, 0x00, 0xC9]);
+        assert_eq!(e.call(0, 10), Stop::Returned);
+        e.cspect = true;
+        assert_eq!(e.call(0, 10), Stop::Exit);
+    }
+
+    #[test]
+    fn invalid_opcodes() {
+        let mut e = Emulator::new(&[0xED, 0x00, 0xED, 0x23, 0xED, 0x77, 0x00]);
+        assert_eq!(e.step(), 2);
+        assert!(e.invalid);
+        assert_eq!(e.step(), 2);
+        assert!(!e.invalid);
+        e.z80n = false;
+        e.regs.pc = 2;
+        e.step();
+        assert!(e.invalid);
+        e.step();
+        assert!(e.invalid);
+        e.step();
+        assert!(!e.invalid);
+    }
+}
@@ -0,0 +1,130 @@
+pub use crate::emulator::registers::Registers;
+
+mod registers;
+mod execute;
+
+// Why execution stopped
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stop {
+    Returned,
+    Halted,
+    Exit,
+    InstructionLimit,
+}
+
+// A Z80 with the Z80n extensions and, when cspect is set, the CSpect EXIT & BREAK opcodes, 64K of
+// RAM and ports that read 0xFF. Timing and interrupts are not emulated.
+pub struct Emulator {
+    pub regs: Registers,
+    pub memory: Vec<u8>,
+    pub next_regs: Vec<u8>,
+    pub ports: Vec<(u16, u8)>,
+    pub cspect: bool,
+    pub z80n: bool,
+    // Set by step when the opcode was not a Z80 (or enabled Z80n) instruction
+    pub invalid: bool,
+    fetched: usize,
+    returned: bool,
+    halted: bool,
+    exit: bool,
+}
+
+impl Emulator {
+    pub fn new(memory: &[u8]) -> Emulator {
+        let mut ram = memory.to_vec();
+        ram.resize(65536, 0);
+        Emulator {
+            regs: Registers::default(),
+            memory: ram,
+            next_regs: vec![0; 256],
+            ports: vec![],
+            cspect: false,
+            z80n: true,
+            invalid: false,
+            fetched: 0,
+            returned: false,
+            halted: false,
+            exit: false,
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    pub fn read_word(&self, address: u16) -> u16 {
+        self.read(address) as u16 | (self.read(address.wrapping_add(1)) as u16) << 8
+    }
+
+    pub fn write_word(&mut self, address: u16, value: u16) {
+        self.write(address, value as u8);
+        self.write(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    fn port_in(&mut self, _port: u16) -> u8 {
+        0xFF
+    }
+
+    fn port_out(&mut self, port: u16, value: u8) {
+        self.ports.push((port, value));
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let b = self.read(self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        self.fetched += 1;
+        b
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let lo = self.fetch() as u16;
+        lo | (self.fetch() as u16) << 8
+    }
+
+    fn push(&mut self, value: u16) {
+        self.regs.sp = self.regs.sp.wrapping_sub(2);
+        self.write_word(self.regs.sp, value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let value = self.read_word(self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(2);
+        value
+    }
+
+    // Executes one instruction returning the number of opcode and operand bytes it was made of
+    pub fn step(&mut self) -> usize {
+        self.fetched = 0;
+        self.returned = false;
+        self.invalid = false;
+        self.execute();
+        self.fetched
+    }
+
+    // Calls a routine with the stack at 0x0000, it finishes when the routine returns to the
+    // caller, executes HALT or the CSpect EXIT opcode
+    pub fn call(&mut self, address: u16, limit: usize) -> Stop {
+        let stack = self.regs.sp;
+        self.push(0);
+        self.regs.pc = address;
+        self.halted = false;
+        self.exit = false;
+        for _ in 0..limit {
+            self.step();
+            if self.exit {
+                return Stop::Exit;
+            }
+            if self.halted {
+                return Stop::Halted;
+            }
+            if self.returned && self.regs.sp == stack {
+                return Stop::Returned;
+            }
+        }
+        Stop::InstructionLimit
+    }
+}
@@ -0,0 +1,114 @@
+pub const FLAG_C: u8 = 0x01;
+pub const FLAG_N: u8 = 0x02;
+pub const FLAG_PV: u8 = 0x04;
+pub const FLAG_X: u8 = 0x08;
+pub const FLAG_H: u8 = 0x10;
+pub const FLAG_Y: u8 = 0x20;
+pub const FLAG_Z: u8 = 0x40;
+pub const FLAG_S: u8 = 0x80;
+
+#[derive(Debug, Default, Clone)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub alt_af: u16,
+    pub alt_bc: u16,
+    pub alt_de: u16,
+    pub alt_hl: u16,
+    pub ix: u16,
+    pub iy: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub i: u8,
+    pub r: u8,
+    pub iff1: bool,
+    pub iff2: bool,
+    pub im: u8,
+}
+
+impl Registers {
+    pub fn af(&self) -> u16 {
+        (self.a as u16) << 8 | self.f as u16
+    }
+
+    pub fn bc(&self) -> u16 {
+        (self.b as u16) << 8 | self.c as u16
+    }
+
+    pub fn de(&self) -> u16 {
+        (self.d as u16) << 8 | self.e as u16
+    }
+
+    pub fn hl(&self) -> u16 {
+        (self.h as u16) << 8 | self.l as u16
+    }
+
+    pub fn set_af(&mut self, v: u16) {
+        self.a = (v >> 8) as u8;
+        self.f = v as u8;
+    }
+
+    pub fn set_bc(&mut self, v: u16) {
+        self.b = (v >> 8) as u8;
+        self.c = v as u8;
+    }
+
+    pub fn set_de(&mut self, v: u16) {
+        self.d = (v >> 8) as u8;
+        self.e = v as u8;
+    }
+
+    pub fn set_hl(&mut self, v: u16) {
+        self.h = (v >> 8) as u8;
+        self.l = v as u8;
+    }
+
+    pub fn flag(&self, flag: u8) -> bool {
+        self.f & flag != 0
+    }
+
+    pub fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.f |= flag;
+        } else {
+            self.f &= !flag;
+        }
+    }
+
+    // Registers by the name used in source, F' and friends are not included
+    pub fn get(&self, name: &str) -> Option<u16> {
+        let value = match name.to_lowercase().as_str() {
+            "a" => self.a as u16,
+            "f" => self.f as u16,
+            "b" => self.b as u16,
+            "c" => self.c as u16,
+            "d" => self.d as u16,
+            "e" => self.e as u16,
+            "h" => self.h as u16,
+            "l" => self.l as u16,
+            "i" => self.i as u16,
+            "r" => self.r as u16,
+            "ixh" => self.ix >> 8,
+            "ixl" => self.ix & 0xFF,
+            "iyh" => self.iy >> 8,
+            "iyl" => self.iy & 0xFF,
+            "af" => self.af(),
+            "bc" => self.bc(),
+            "de" => self.de(),
+            "hl" => self.hl(),
+            "ix" => self.ix,
+            "iy" => self.iy,
+            "sp" => self.sp,
+            "pc" => self.pc,
+            "af'" => self.alt_af,
+            _ => return None
+        };
+        Some(value)
+    }
+}
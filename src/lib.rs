@@ -0,0 +1,19 @@
+#[macro_use]
+extern crate colour;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate version;
+
+pub use crate::assembler::{Assembled, Assembler};
+pub use crate::assembler::assembler_options::AssemblerOptions;
+pub use crate::assembler::error::{Error, ErrorLevel};
+pub use crate::assembler::file_provider::{DiskFiles, FileProvider, MemoryFiles};
+pub use crate::assembler::symbols::{Symbol, SymbolFormat, SymbolKind};
+pub use crate::assembler::timing::TStates;
+pub use crate::assembler::unit_test::TestResult;
+pub use crate::disassembler::Disassembler;
+
+pub mod assembler;
+pub mod disassembler;
+pub mod emulator;
@@ -1,20 +1,21 @@
 #[macro_use]
 extern crate colour;
 #[macro_use]
-extern crate lazy_static;
-#[macro_use]
 extern crate version;
 
+use std::fs::File;
+use std::io::Write;
 use std::process::exit;
+use std::str::FromStr;
 use std::time::Instant;
 
-use crate::assembler::Assembler;
-use crate::assembler::assembler_options::AssemblerOptions;
-use crate::assembler::error::Error;
+use fantasm::{Assembler, AssemblerOptions, DiskFiles, Disassembler, Error, ErrorLevel, FileProvider, SymbolFormat};
+use fantasm::assembler::snapshot::Snapshot;
+use fantasm::assembler::tap::Tap;
+
 use crate::options::Options;
 
 mod options;
-mod assembler;
 
 fn main() {
     match _main() {
@@ -26,8 +27,63 @@ fn main() {
     }
 }
 
+fn snapshot(options: &Options) -> Snapshot {
+    let mut snapshot = Snapshot::new(&options.output);
+    snapshot.start = options.start.map(|a| a as isize);
+    if let Some(stack) = options.stack {
+        snapshot.stack = stack as isize;
+    }
+    snapshot.border = options.border.unwrap_or(snapshot.border);
+    snapshot.bank = options.bank.unwrap_or(snapshot.bank);
+    snapshot.ram_image = options.ram_image.clone();
+    snapshot
+}
+
+// Prints each result, the first is the check of every assembled instruction
+fn run_tests(assembler: &mut Assembler) -> bool {
+    let results = assembler.run_tests();
+    for result in &results {
+        if result.passed() {
+            green_ln!("PASS {}", result.name);
+        } else {
+            if result.line_no > -1 {
+                red_ln!("FAIL {} [{} : {}]", result.name, result.file_name, result.line_no);
+            } else {
+                red_ln!("FAIL {}", result.name);
+            }
+            for failure in &result.failures {
+                red_ln!("    {}", failure);
+            }
+        }
+    }
+    let failed = results.iter().filter(|r| !r.passed()).count();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    failed == 0
+}
+
+fn disassemble(options: &Options) -> Result<(), Error> {
+    let mut disassembler = Disassembler::new();
+    disassembler
+        .origin(options.origin)
+        .enable_z80n(options.z80n)
+        .enable_cspect(options.c_spect);
+    if !options.symbols.is_empty() {
+        let symbols = DiskFiles.read(&options.symbols)?;
+        disassembler.add_symbols(&String::from_utf8_lossy(&symbols));
+    }
+    let code = DiskFiles.read(&options.source)?;
+    File::create(&options.output)?.write_all(disassembler.disassemble(&code).as_bytes())?;
+    Ok(())
+}
+
 fn _main() -> Result<(), Error> {
     let options = Options::parse()?;
+    if options.disassemble {
+        return disassemble(&options);
+    }
+    let snapshot = snapshot(&options);
+    let sym_format = SymbolFormat::from_str(&options.sym_format)
+        .map_err(|_| Error::fatal(&format!("Unknown symbol format: {}", options.sym_format), -1, ""))?;
 
     let mut assembler = Assembler::new();
 
@@ -38,9 +94,19 @@ fn _main() -> Result<(), Error> {
         .enable_debug(options.debug)
         .add_include_dirs(options.include_dirs)
         .add_defines(options.defines)
+        .add_sections(options.sections)
         .export_labels(&options.export_labels)
+        .symbol_format(sym_format)
+        .export_all(options.export_all)
+        .listing_file(&options.listing)
+        .sld_file(&options.sld)
+        .timings_file(&options.timings)
         .origin(options.origin)
         .max_code_size(options.max_code_size as usize)
+        .max_errors(options.max_errors)
+        .max_macro_depth(options.max_macro_depth)
+        .enable_tests(options.test)
+        .warn_unused(options.warnings)
         .case_insensitive(options.case_insensitive_labels);
 
     let now = Instant::now();
@@ -49,16 +115,47 @@ fn _main() -> Result<(), Error> {
     }
 
 
-    match assembler.assemble(options.source.as_str()) {
-        Ok(_) => assembler.save_raw(&options.output)?,
+    let result = assembler.assemble(options.source.as_str());
+    for message in assembler.messages() {
+        dark_yellow_ln!("{}", message);
+    }
+    match result {
+        Ok(_) => if options.test {
+            if !run_tests(&mut assembler) {
+                exit(1);
+            }
+        } else if options.output.to_lowercase().ends_with(".nex") {
+            assembler.save_nex(&options.output)?
+        } else if options.tap || Tap::is_tap(&options.output) {
+            let mut tap = Tap::new(&options.output);
+            tap.start = snapshot.start;
+            tap.loader = options.loader;
+            assembler.save_tap(&tap)?
+        } else if Snapshot::is_snapshot(&options.output) {
+            assembler.save_snapshot(&snapshot)?
+        } else {
+            assembler.save_raw(&options.output)?
+        },
         Err(e) => {
-            red_ln!("[{} : {}] {}",e.file_name,e.line_no,e.message);
+            assembler.display_errors();
+            if e.line_no > -1 {
+                red_ln!("[{} : {}] {}",e.file_name,e.line_no,e.message);
+            } else {
+                red_ln!("[{}] {}",e.file_name,e.message);
+            }
             exit(1);
         }
     }
 
-    if options.warnings {
-        assembler.display_unused();
+    // Verbose output has already listed the warnings
+    if options.warnings && !options.verbose {
+        for warning in assembler.diagnostics().iter().filter(|d| d.level == ErrorLevel::Warning) {
+            if warning.line_no > -1 {
+                cyan_ln!("[{} : {}] Warning: {}", warning.file_name, warning.line_no, warning.message);
+            } else {
+                cyan_ln!("Warning: {}", warning.message);
+            }
+        }
     }
 
     if options.verbose {
@@ -4,7 +4,7 @@ extern crate envmnt;
 use std::path::Path;
 use std::process::exit;
 
-use argparse::{ArgumentParser, Store, StoreTrue};
+use argparse::{ArgumentParser, Store, StoreOption, StoreTrue};
 
 use self::argparse::List;
 
@@ -20,11 +20,29 @@ pub struct Options {
     pub version: bool,
     pub include_dirs: Vec<String>,
     pub export_labels: String,
+    pub sym_format: String,
+    pub export_all: bool,
     pub origin: u16,
     pub max_code_size: isize,
     pub defines: Vec<String>,
+    pub sections: Vec<String>,
     pub case_insensitive_labels: bool,
     pub warnings: bool,
+    pub listing: String,
+    pub sld: String,
+    pub timings: String,
+    pub test: bool,
+    pub disassemble: bool,
+    pub symbols: String,
+    pub max_errors: usize,
+    pub max_macro_depth: usize,
+    pub start: Option<u16>,
+    pub stack: Option<u16>,
+    pub border: Option<u8>,
+    pub bank: Option<u8>,
+    pub ram_image: String,
+    pub tap: bool,
+    pub loader: bool,
 }
 
 impl Options {
@@ -67,10 +85,43 @@ impl Options {
                 .metavar("constant")
                 .add_option(&["-D", "--define"], List, "Defines a constant");
 
+            parser.refer(&mut options.sections)
+                .metavar("name=address[,page]")
+                .add_option(&["--section"], List, "Places a section at an address and optional page");
+
             parser.refer(&mut options.export_labels)
                 .metavar("file")
                 .add_option(&["-e", "--export-labels"], Store, "Export labels to a file");
 
+            parser.refer(&mut options.sym_format)
+                .metavar("format")
+                .add_option(&["--sym-format"], Store, "Exported label format: default, cspect, zesarux, mame, nocash or json");
+
+            parser.refer(&mut options.export_all)
+                .add_option(&["--export-all"], StoreTrue, "Export all labels, not only globals");
+
+            parser.refer(&mut options.listing)
+                .metavar("file")
+                .add_option(&["-l", "--listing"], Store, "Write an assembly listing to a file");
+
+            parser.refer(&mut options.timings)
+                .metavar("file")
+                .add_option(&["--timings"], Store, "Write the T-states of every instruction to a file");
+
+            parser.refer(&mut options.test)
+                .add_option(&["--test"], StoreTrue, "Run the UNITTEST routines in the emulator instead of writing output");
+
+            parser.refer(&mut options.disassemble)
+                .add_option(&["--disassemble"], StoreTrue, "Disassemble a binary <source> into <file>, the binary is loaded at --origin");
+
+            parser.refer(&mut options.symbols)
+                .metavar("file")
+                .add_option(&["--symbols"], Store, "Exported label file used to name addresses when disassembling");
+
+            parser.refer(&mut options.sld)
+                .metavar("file")
+                .add_option(&["--sld"], Store, "Write source level debug information (SLD) for DeZog");
+
             parser.refer(&mut options.warnings)
                 .metavar("warnings")
                 .add_option(&["-W", "--enable-warnings"], StoreTrue, "Enable warnings (unused labels)");
@@ -84,6 +135,40 @@ impl Options {
                 .metavar("size")
                 .add_option(&["-M", "--max-code-size"], Store, "Limit the size of assembled code");
 
+            parser.refer(&mut options.max_errors)
+                .metavar("count")
+                .add_option(&["--max-errors"], Store, "Stop after this many errors (0 = no limit)");
+
+            parser.refer(&mut options.max_macro_depth)
+                .metavar("depth")
+                .add_option(&["--max-macro-depth"], Store, "Limit how deep macros can call macros (0 = 64)");
+
+            parser.refer(&mut options.tap)
+                .add_option(&["-t", "--tap"], StoreTrue, "Write the output as a .tap file");
+
+            parser.refer(&mut options.loader)
+                .add_option(&["--loader"], StoreTrue, "Add a BASIC loader to .tap output");
+
+            parser.refer(&mut options.start)
+                .metavar("address")
+                .add_option(&["--start"], StoreOption, "Start address for snapshots and the .tap loader");
+
+            parser.refer(&mut options.stack)
+                .metavar("address")
+                .add_option(&["--stack"], StoreOption, "Snapshot stack pointer");
+
+            parser.refer(&mut options.border)
+                .metavar("colour")
+                .add_option(&["--border"], StoreOption, "Snapshot border colour");
+
+            parser.refer(&mut options.bank)
+                .metavar("bank")
+                .add_option(&["--bank"], StoreOption, "128K snapshot bank paged in at 0xC000");
+
+            parser.refer(&mut options.ram_image)
+                .metavar("file")
+                .add_option(&["--ram-image"], Store, "RAM image used for memory not assembled into by a snapshot");
+
             parser.parse_args_or_exit();
         }
 
@@ -100,7 +185,7 @@ impl Options {
             return Err(String::from("<source> is required"));
         }
 
-        if options.output.is_empty() {
+        if options.output.is_empty() && !options.test {
             return Err(String::from("<output> is required"));
         }
 
@@ -112,9 +197,8 @@ impl Options {
         if envmnt::exists("Z80_INCLUDE") {
             let mut opt = envmnt::ListOptions::new();
             opt.separator = Some(":".to_string());
-            match envmnt::get_list_with_options("Z80_INCLUDE", &opt) {
-                Some(mut v) => options.include_dirs.append(v.as_mut()),
-                None => {}
+            if let Some(mut v) = envmnt::get_list_with_options("Z80_INCLUDE", &opt) {
+                options.include_dirs.append(v.as_mut())
             }
         }
 
@@ -0,0 +1,44 @@
+use fantasm::{Assembler, AssemblerOptions, ErrorLevel, MemoryFiles, SymbolKind};
+
+#[test]
+fn assemble_source() {
+    let mut assembler = Assembler::new();
+    let result = assembler.assemble_source("main.asm", " org 0x8000\nstart\n ld a,SIZE\n jr start\nSIZE equ 4\n").ok().unwrap();
+    assert_eq!(result.bytes, [0x3E, 0x04, 0x18, 0xFC]);
+    let start = result.symbols.iter().find(|s| s.name == "start").unwrap();
+    assert_eq!((start.value, start.kind, start.line), (0x8000, SymbolKind::Label, 2));
+    let size = result.symbols.iter().find(|s| s.name == "SIZE").unwrap();
+    assert_eq!((size.value, size.kind), (4, SymbolKind::Constant));
+    assert!(result.diagnostics.is_empty());
+}
+
+#[test]
+fn memory_files() {
+    let mut files = MemoryFiles::new();
+    files.add("lib/util.asm", b" ld b,2\n").add("./data.bin", &[1, 2, 3]);
+    let mut assembler = Assembler::new();
+    assembler.file_provider(Box::new(files)).enable_z80n(true);
+    let result = assembler.assemble_source("main.asm", " include \"lib/util.asm\"\n incbin \"data.bin\"\n nextreg 7,3\n").ok().unwrap();
+    assert_eq!(result.bytes, [0x06, 0x02, 1, 2, 3, 0xED, 0x91, 0x07, 0x03]);
+}
+
+#[test]
+fn diagnostics() {
+    let mut assembler = Assembler::new();
+    assert!(assembler.assemble_source("main.asm", " nop\n ld q,1\n include \"missing.asm\"\n").is_err());
+    let errors: Vec<(isize, ErrorLevel)> = assembler.diagnostics().iter().map(|d| (d.line_no, d.level)).collect();
+    assert_eq!(errors, [(2, ErrorLevel::Error), (3, ErrorLevel::Error)]);
+}
+
+#[test]
+fn messages_and_warnings() {
+    let mut assembler = Assembler::new();
+    assembler.add_defines(vec![String::from("DEBUG")]).warn_unused(true);
+    let result = assembler.assemble_source("main.asm", " !message \"hello\"\nstart\n jr start\nspare\n nop\n").ok().unwrap();
+    assert_eq!(result.messages, ["hello"]);
+    let warnings: Vec<(isize, &str)> = result.diagnostics.iter().map(|d| (d.line_no, d.message.as_str())).collect();
+    assert_eq!(warnings, [
+        (-1, "Define 'DEBUG' has no value and is ignored, use name=value"),
+        (4, "Unused label spare"),
+    ]);
+}
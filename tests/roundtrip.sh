@@ -0,0 +1,10 @@
+cargo build --release
+rm -f fantasm.bin roundtrip.asm roundtrip.bin
+for i in test_*.asm ; do
+  printf "%-22s ... " "$i"
+  cargo run --quiet --release -- "$i" --z80n --nologo fantasm.bin --cspect &&
+  cargo run --quiet --release -- fantasm.bin roundtrip.asm --disassemble --origin 32768 --z80n --nologo --cspect &&
+  cargo run --quiet --release -- roundtrip.asm --z80n --nologo roundtrip.bin --cspect &&
+  cmp fantasm.bin roundtrip.bin && printf "Passed!\n"
+done
+rm -f fantasm.bin roundtrip.asm roundtrip.bin
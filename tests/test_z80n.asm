@@ -39,6 +39,7 @@ start
 	PIXELDN
 	PIXELAD
 	SETAE
+	JP	(C)
 
 	test	register
 	test	finish